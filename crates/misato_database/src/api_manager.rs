@@ -1,14 +1,17 @@
+use async_trait::async_trait;
 use mongodb::{
     bson::{doc, Document},
-    error::Error,
     options::ReplaceOptions,
-    results::{DeleteResult, UpdateResult},
     Collection,
 };
 
+use crate::database::{lookup_index, unique_index};
+use crate::error::Error;
+
 use misato_utils::get_current_timestamp;
 
 use crate::models::apiuser_model::*;
+use crate::repository::{ApiUserRepository, DeleteResult, UpdateResult};
 
 pub struct ApiUserManager {
     pub apiusers: Collection<ApiUser>,
@@ -19,7 +22,23 @@ impl ApiUserManager {
         Self { apiusers }
     }
 
-    pub async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        self.apiusers
+            .create_indexes(
+                [
+                    unique_index(doc! {"uuid": 1}),
+                    lookup_index(doc! {"token.token": 1}),
+                ],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ApiUserRepository for ApiUserManager {
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
         Ok(self
             .apiusers
             .count_documents(doc! { "uuid": uuid }, None)
@@ -27,7 +46,7 @@ impl ApiUserManager {
             != 0)
     }
 
-    pub async fn create_apiuser(&self, apiuser: &ApiUser) -> Result<UpdateResult, Error> {
+    async fn create_apiuser(&self, apiuser: &ApiUser) -> Result<UpdateResult, Error> {
         let target = self
             .apiusers
             .replace_one(
@@ -36,10 +55,10 @@ impl ApiUserManager {
                 ReplaceOptions::builder().upsert(true).build(),
             )
             .await?;
-        Ok(target)
+        Ok(target.into())
     }
 
-    pub async fn get_apiuser(
+    async fn get_apiuser(
         &self,
         username: Option<&str>,
         uuid: Option<&str>,
@@ -60,7 +79,7 @@ impl ApiUserManager {
         }
     }
 
-    pub async fn delete_apiuser(
+    async fn delete_apiuser(
         &self,
         username: Option<&str>,
         uuid: Option<&str>,
@@ -75,46 +94,47 @@ impl ApiUserManager {
         if doc.is_empty() {
             return Ok(None);
         }
-        Ok(Some(self.apiusers.delete_one(doc, None).await?))
+        Ok(Some(self.apiusers.delete_one(doc, None).await?.into()))
     }
 
-    pub async fn delete_apiuser_from_token(
-        &self,
-        token: &str,
-    ) -> Result<Option<DeleteResult>, Error> {
+    async fn delete_apiuser_from_token(&self, token: &str) -> Result<Option<DeleteResult>, Error> {
         Ok(Some(
             self.apiusers
                 .delete_one(doc! {"token.token": token, "token.expiration_timestamp": { "$gte": get_current_timestamp() as i64 } }, None)
-                .await?,
+                .await?
+                .into(),
         ))
     }
 
-    pub async fn set_token(&self, uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error> {
+    async fn set_token(&self, uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error> {
         let doc = mongodb::bson::to_document(token).unwrap();
         let update = doc! {"$set": {"token": doc} };
         Ok(self
             .apiusers
             .update_one(doc! {"uuid": uuid}, update, None)
-            .await?)
+            .await?
+            .into())
     }
 
-    pub async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
         let update = doc! {"$unset": {"token": ""} };
         Ok(self
             .apiusers
             .update_one(doc! {"uuid": uuid}, update, None)
-            .await?)
+            .await?
+            .into())
     }
 
-    pub async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
         let update = doc! {"$unset": {"token": ""} };
         Ok(self
             .apiusers
             .update_one(doc! {"token.token": token, "token.expiration_timestamp": { "$gte": get_current_timestamp() as i64 } }, update, None)
-            .await?)
+            .await?
+            .into())
     }
 
-    pub async fn get_apiuser_from_token(&self, token: &str) -> Result<Option<ApiUser>, Error> {
+    async fn get_apiuser_from_token(&self, token: &str) -> Result<Option<ApiUser>, Error> {
         match self
             .apiusers
             .find_one(
@@ -1,20 +1,109 @@
-use mongodb::{error::Error, *};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    *,
+};
 
 use crate::api_manager::*;
-use crate::models::data_model::Data;
+use crate::deferred::Deferred;
+use crate::error::Error;
+use crate::export_manager::*;
+use crate::invite_manager::*;
+use crate::login_manager::*;
+use crate::measured::{Measured, OperationObserver};
+use crate::memory::{
+    api_manager::MemoryApiUserManager, export_manager::MemoryExportManager,
+    invite_manager::MemoryInviteManager, login_manager::MemoryLoginAttemptManager,
+    oauth_manager::MemoryOAuthManager, user_manager::MemoryUserManager,
+};
+use crate::migration;
+use crate::oauth_manager::*;
+use crate::query_log::mongodb_client;
+pub use crate::repository::*;
+use crate::sql::{
+    self, api_manager::SqlApiUserManager, export_manager::SqlExportManager,
+    invite_manager::SqlInviteManager, login_manager::SqlLoginAttemptManager,
+    oauth_manager::SqlOAuthManager, user_manager::SqlUserManager,
+};
+use crate::transaction::{CompensatingUnitOfWork, MongoUnitOfWork, SqlUnitOfWork, UnitOfWork};
 use crate::user_manager::*;
 use misato_utils::settings::Settings;
 
+fn slow_query_threshold(settings: &Settings) -> Duration {
+    Duration::from_millis(settings.slow_query_threshold)
+}
+
 pub struct Database {
-    pub data: Collection<Data>,
-    pub usermanager: UserManager,
-    pub apiusermanager: ApiUserManager,
+    pub usermanager: Arc<dyn UserRepository>,
+    pub apiusermanager: Arc<dyn ApiUserRepository>,
+    pub loginattemptmanager: Arc<dyn LoginAttemptRepository>,
+    pub invitemanager: Arc<dyn InviteRepository>,
+    pub exportmanager: Arc<dyn ExportRepository>,
+    pub oauthmanager: Arc<dyn OAuthRepository>,
+    /// Set once the stores are connected.
+    backend: Arc<OnceLock<Backend>>,
+}
+
+#[derive(Clone)]
+enum Backend {
+    /// `transactions` when the server supports them, units of work then run in one.
+    MongoDb {
+        client: Client,
+        db: mongodb::Database,
+        transactions: bool,
+    },
+    Sql(sqlx::AnyPool),
+    Memory,
+}
+
+/// Receives the stores of a `Database::deferred` once they could be opened.
+pub struct DatabaseSlot {
+    usermanager: Arc<OnceLock<Arc<dyn UserRepository>>>,
+    apiusermanager: Arc<OnceLock<Arc<dyn ApiUserRepository>>>,
+    loginattemptmanager: Arc<OnceLock<Arc<dyn LoginAttemptRepository>>>,
+    invitemanager: Arc<OnceLock<Arc<dyn InviteRepository>>>,
+    exportmanager: Arc<OnceLock<Arc<dyn ExportRepository>>>,
+    oauthmanager: Arc<OnceLock<Arc<dyn OAuthRepository>>>,
+    backend: Arc<OnceLock<Backend>>,
+}
+
+impl DatabaseSlot {
+    /// Points the deferred stores to those of `database`, only the first call has an effect.
+    pub fn fill(self, database: Database) {
+        let _ = self.usermanager.set(database.usermanager);
+        let _ = self.apiusermanager.set(database.apiusermanager);
+        let _ = self.loginattemptmanager.set(database.loginattemptmanager);
+        let _ = self.invitemanager.set(database.invitemanager);
+        let _ = self.exportmanager.set(database.exportmanager);
+        let _ = self.oauthmanager.set(database.oauthmanager);
+        if let Some(backend) = database.backend.get() {
+            let _ = self.backend.set(backend.clone());
+        }
+    }
 }
 
 impl Database {
+    /// Opens the backend chosen by `MISATO_DATABASE_BACKEND`.
     pub async fn init(settings: &Settings) -> Result<Self, Error> {
-        let uri = &settings.mongodb_uri;
-        let client = Client::with_uri_str(uri).await?;
+        match settings.database_backend.as_str() {
+            "memory" => Ok(Self::memory(settings)),
+            "sql" => Self::sql(settings).await,
+            _ => Self::mongodb(settings).await,
+        }
+    }
+
+    pub async fn open_mongodb(settings: &Settings) -> Result<mongodb::Database, Error> {
+        let client = mongodb_client(&settings.mongodb_uri, slow_query_threshold(settings)).await?;
+        Ok(client.database(&settings.mongodb_name))
+    }
+
+    /// Brings the documents up to date first, unless `MISATO_MIGRATE_ON_STARTUP` is off,
+    /// then ensures the indexes of every manager. Creating an existing index is a no-op.
+    pub async fn mongodb(settings: &Settings) -> Result<Self, Error> {
+        let client = mongodb_client(&settings.mongodb_uri, slow_query_threshold(settings)).await?;
         let db = client.database(&settings.mongodb_name);
         let names = db.list_collection_names(None).await?;
         if !names.contains(&"data".to_string()) {
@@ -26,10 +115,221 @@ impl Database {
         if !names.contains(&"users".to_string()) {
             db.create_collection("users", None).await?;
         }
+        if !names.contains(&"loginattempts".to_string()) {
+            db.create_collection("loginattempts", None).await?;
+        }
+        if !names.contains(&"invites".to_string()) {
+            db.create_collection("invites", None).await?;
+        }
+        if !names.contains(&"exports".to_string()) {
+            db.create_collection("exports", None).await?;
+        }
+        for name in [
+            "oauthclients",
+            "oauthconsents",
+            "oauthcodes",
+            "oauthtokens",
+            "sessions",
+            "migrations",
+        ] {
+            if !names.contains(&name.to_string()) {
+                db.create_collection(name, None).await?;
+            }
+        }
+        migration::check(&db).await?;
+        if settings.migrate_on_startup {
+            migration::apply(&db).await?;
+        }
+        let usermanager =
+            UserManager::init(db.collection("users"), db.collection("sessions"), settings);
+        let apiusermanager = ApiUserManager::init(db.collection("apiusers"));
+        let loginattemptmanager =
+            LoginAttemptManager::init(db.collection("loginattempts"), settings);
+        let invitemanager = InviteManager::init(db.collection("invites"));
+        let exportmanager = ExportManager::init(db.collection("exports"));
+        let oauthmanager = OAuthManager::init(
+            db.collection("oauthclients"),
+            db.collection("oauthconsents"),
+            db.collection("oauthcodes"),
+            db.collection("oauthtokens"),
+        );
+        usermanager.create_indexes().await?;
+        usermanager.move_sessions().await?;
+        apiusermanager.create_indexes().await?;
+        loginattemptmanager.create_indexes().await?;
+        invitemanager.create_indexes().await?;
+        exportmanager.create_indexes().await?;
+        oauthmanager.create_indexes().await?;
+        // Transactions need a replica set or mongos, a standalone server has neither.
+        let transactions = match db.run_command(doc! {"hello": 1}, None).await {
+            Ok(hello) => hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid"),
+            Err(_) => false,
+        };
+        Ok(Database {
+            usermanager: Arc::new(usermanager),
+            apiusermanager: Arc::new(apiusermanager),
+            loginattemptmanager: Arc::new(loginattemptmanager),
+            invitemanager: Arc::new(invitemanager),
+            exportmanager: Arc::new(exportmanager),
+            oauthmanager: Arc::new(oauthmanager),
+            backend: Arc::new(OnceLock::from(Backend::MongoDb {
+                client,
+                db,
+                transactions,
+            })),
+        })
+    }
+
+    /// SQLite or PostgreSQL at `MISATO_SQL_URL`, migrated to the latest schema.
+    pub async fn sql(settings: &Settings) -> Result<Self, Error> {
+        let pool = sql::connect(&settings.sql_url, slow_query_threshold(settings)).await?;
         Ok(Database {
-            data: db.collection("data"),
-            usermanager: UserManager::init(db.collection("users")),
-            apiusermanager: ApiUserManager::init(db.collection("apiusers")),
+            usermanager: Arc::new(SqlUserManager::init(&pool, settings)),
+            apiusermanager: Arc::new(SqlApiUserManager::init(&pool)),
+            loginattemptmanager: Arc::new(SqlLoginAttemptManager::init(&pool, settings)),
+            invitemanager: Arc::new(SqlInviteManager::init(&pool)),
+            exportmanager: Arc::new(SqlExportManager::init(&pool)),
+            oauthmanager: Arc::new(SqlOAuthManager::init(&pool)),
+            backend: Arc::new(OnceLock::from(Backend::Sql(pool))),
         })
     }
+
+    /// Empty stores living as long as the process.
+    pub fn memory(settings: &Settings) -> Self {
+        Database {
+            usermanager: Arc::new(MemoryUserManager::init(settings)),
+            apiusermanager: Arc::new(MemoryApiUserManager::init()),
+            loginattemptmanager: Arc::new(MemoryLoginAttemptManager::init(settings)),
+            invitemanager: Arc::new(MemoryInviteManager::init()),
+            exportmanager: Arc::new(MemoryExportManager::init()),
+            oauthmanager: Arc::new(MemoryOAuthManager::init()),
+            backend: Arc::new(OnceLock::from(Backend::Memory)),
+        }
+    }
+
+    /// Stores failing as unavailable until the slot is filled, for a server started
+    /// while its database is unreachable.
+    pub fn deferred() -> (Self, DatabaseSlot) {
+        let (usermanager, users) = Deferred::<dyn UserRepository>::new();
+        let (apiusermanager, apiusers) = Deferred::<dyn ApiUserRepository>::new();
+        let (loginattemptmanager, loginattempts) = Deferred::<dyn LoginAttemptRepository>::new();
+        let (invitemanager, invites) = Deferred::<dyn InviteRepository>::new();
+        let (exportmanager, exports) = Deferred::<dyn ExportRepository>::new();
+        let (oauthmanager, oauth) = Deferred::<dyn OAuthRepository>::new();
+        let backend = Arc::new(OnceLock::new());
+        let database = Database {
+            usermanager: Arc::new(usermanager),
+            apiusermanager: Arc::new(apiusermanager),
+            loginattemptmanager: Arc::new(loginattemptmanager),
+            invitemanager: Arc::new(invitemanager),
+            exportmanager: Arc::new(exportmanager),
+            oauthmanager: Arc::new(oauthmanager),
+            backend: backend.clone(),
+        };
+        let slot = DatabaseSlot {
+            usermanager: users,
+            apiusermanager: apiusers,
+            loginattemptmanager: loginattempts,
+            invitemanager: invites,
+            exportmanager: exports,
+            oauthmanager: oauth,
+            backend,
+        };
+        (database, slot)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.backend.get().is_some()
+    }
+
+    /// One round trip to the backend.
+    pub async fn ping(&self) -> Result<(), Error> {
+        match self.backend.get() {
+            Some(Backend::MongoDb { db, .. }) => {
+                db.run_command(doc! {"ping": 1}, None).await?;
+            }
+            Some(Backend::Sql(pool)) => {
+                sqlx::query("SELECT 1").execute(pool).await?;
+            }
+            Some(Backend::Memory) => {}
+            None => return Err(not_connected()),
+        }
+        Ok(())
+    }
+
+    /// Migrations of this build the database is still missing, as `<collection>:<version>`.
+    pub async fn pending_migrations(&self) -> Result<Vec<String>, Error> {
+        match self.backend.get() {
+            Some(Backend::MongoDb { db, .. }) => Ok(migration::status(db)
+                .await?
+                .into_iter()
+                .filter(|status| status.applied.is_none() || status.pending > 0)
+                .map(|status| format!("{}:{}", status.collection, status.version))
+                .collect()),
+            Some(Backend::Sql(pool)) => sql::pending_migrations(pool).await,
+            Some(Backend::Memory) => Ok(Vec::new()),
+            None => Err(not_connected()),
+        }
+    }
+
+    /// Reports every call to the stores to `observer`. Units of work running in a MongoDB or
+    /// SQL transaction write past the stores and are not reported.
+    pub fn observed(self, observer: Arc<dyn OperationObserver>) -> Self {
+        Database {
+            usermanager: Arc::new(Measured::new(self.usermanager, observer.clone())),
+            apiusermanager: Arc::new(Measured::new(self.apiusermanager, observer.clone())),
+            loginattemptmanager: Arc::new(Measured::new(
+                self.loginattemptmanager,
+                observer.clone(),
+            )),
+            invitemanager: Arc::new(Measured::new(self.invitemanager, observer.clone())),
+            exportmanager: Arc::new(Measured::new(self.exportmanager, observer.clone())),
+            oauthmanager: Arc::new(Measured::new(self.oauthmanager, observer)),
+            backend: self.backend,
+        }
+    }
+
+    /// Starts a unit of work, see `transaction` for how each backend keeps it atomic.
+    pub async fn begin(&self) -> Result<Box<dyn UnitOfWork>, Error> {
+        match self.backend.get() {
+            Some(Backend::MongoDb {
+                client,
+                db,
+                transactions: true,
+            }) => Ok(Box::new(MongoUnitOfWork::start(client, db).await?)),
+            Some(Backend::Sql(pool)) => Ok(Box::new(SqlUnitOfWork::start(pool).await?)),
+            _ => Ok(Box::new(CompensatingUnitOfWork::new(
+                self.usermanager.clone(),
+                self.apiusermanager.clone(),
+                self.invitemanager.clone(),
+            ))),
+        }
+    }
+}
+
+pub(crate) fn not_connected() -> Error {
+    Error::Unavailable("the database is not connected yet".to_string())
+}
+
+pub(crate) fn unique_index(keys: Document) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+}
+
+pub(crate) fn lookup_index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+/// Removes a document once its `expires_at` date is past.
+pub(crate) fn expiry_index() -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! {"expires_at": 1})
+        .options(
+            IndexOptions::builder()
+                .expire_after(Duration::from_secs(0))
+                .build(),
+        )
+        .build()
 }
@@ -0,0 +1,56 @@
+//! Repositories that exist before their backend does, so the server can start while the
+//! database is unreachable. Every call fails as unavailable until the backend is set.
+
+use std::sync::{Arc, OnceLock};
+
+use async_trait::async_trait;
+
+use misato_security::password::Password;
+
+use crate::database::not_connected;
+use crate::error::Error;
+use crate::models::{
+    apiuser_model::*, export_model::*, invite_model::*, loginattempt_model::*, oauth_model::*,
+    user_model::*,
+};
+use crate::repository::{for_each_repository, *};
+use crate::user_manager::{UserSearch, UserSort};
+
+pub struct Deferred<T: ?Sized> {
+    inner: Arc<OnceLock<Arc<T>>>,
+}
+
+impl<T: ?Sized> Deferred<T> {
+    /// The repository and the cell that later receives its backend.
+    pub fn new() -> (Self, Arc<OnceLock<Arc<T>>>) {
+        let inner = Arc::new(OnceLock::new());
+        (
+            Self {
+                inner: inner.clone(),
+            },
+            inner,
+        )
+    }
+
+    fn get(&self) -> Result<&Arc<T>, Error> {
+        self.inner.get().ok_or_else(not_connected)
+    }
+}
+
+/// Forwards every method of the trait to the backend once it is set.
+macro_rules! deferred {
+    ($trait:ident as $manager:literal {
+        $(fn $method:ident($($arg:ident: $type:ty),* $(,)?) -> $result:ty;)*
+    }) => {
+        #[async_trait]
+        impl $trait for Deferred<dyn $trait> {
+            $(
+                async fn $method(&self, $($arg: $type),*) -> $result {
+                    self.get()?.$method($($arg),*).await
+                }
+            )*
+        }
+    };
+}
+
+for_each_repository!(deferred);
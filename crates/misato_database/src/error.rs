@@ -0,0 +1,129 @@
+//! Failures of the stores, whatever the backend, sorted by what the caller can do about them.
+
+use std::fmt;
+
+use mongodb::error::{ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR};
+
+/// Duplicate key code of MongoDB, for inserts, updates and `findAndModify`.
+const MONGODB_DUPLICATE_KEY: i32 = 11000;
+
+/// Unique violations of SQLite (unique and primary key) and PostgreSQL.
+const SQL_UNIQUE_VIOLATIONS: &[&str] = &["2067", "1555", "23505"];
+
+/// Busy or locked SQLite database, PostgreSQL serialization failure, deadlock and shutdown.
+const SQL_TRANSIENT: &[&str] = &["5", "6", "40001", "40P01", "57P01"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The document a write expected is not there.
+    NotFound(String),
+    /// A write broke a unique index, such as a username taken concurrently.
+    DuplicateKey(String),
+    /// The store cannot be reached or gave up, retrying later may succeed.
+    Unavailable(String),
+    /// A document could not be encoded or decoded.
+    Serialization(String),
+    /// The store refused the request or failed in a way retrying will not fix.
+    Invalid(String),
+}
+
+impl Error {
+    /// Short name for logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::DuplicateKey(_) => "duplicate_key",
+            Error::Unavailable(_) => "unavailable",
+            Error::Serialization(_) => "serialization",
+            Error::Invalid(_) => "invalid",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::NotFound(message)
+            | Error::DuplicateKey(message)
+            | Error::Unavailable(message)
+            | Error::Serialization(message)
+            | Error::Invalid(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<mongodb::error::Error> for Error {
+    fn from(error: mongodb::error::Error) -> Self {
+        let message = error.to_string();
+        match &*error.kind {
+            ErrorKind::Write(WriteFailure::WriteError(write))
+                if write.code == MONGODB_DUPLICATE_KEY =>
+            {
+                Error::DuplicateKey(message)
+            }
+            ErrorKind::Command(command) if command.code == MONGODB_DUPLICATE_KEY => {
+                Error::DuplicateKey(message)
+            }
+            ErrorKind::BsonDeserialization(_) | ErrorKind::BsonSerialization(_) => {
+                Error::Serialization(message)
+            }
+            ErrorKind::Io(_)
+            | ErrorKind::ConnectionPoolCleared { .. }
+            | ErrorKind::DnsResolve { .. }
+            | ErrorKind::ServerSelection { .. } => Error::Unavailable(message),
+            _ if error.contains_label(TRANSIENT_TRANSACTION_ERROR) => Error::Unavailable(message),
+            _ => Error::Invalid(message),
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        let message = error.to_string();
+        match &error {
+            sqlx::Error::Database(database) => match database.code() {
+                Some(code) if SQL_UNIQUE_VIOLATIONS.contains(&code.as_ref()) => {
+                    Error::DuplicateKey(message)
+                }
+                Some(code) if SQL_TRANSIENT.contains(&code.as_ref()) => Error::Unavailable(message),
+                _ => Error::Invalid(message),
+            },
+            sqlx::Error::RowNotFound => Error::NotFound(message),
+            sqlx::Error::ColumnDecode { .. }
+            | sqlx::Error::Decode(_)
+            | sqlx::Error::ColumnNotFound(_)
+            | sqlx::Error::ColumnIndexOutOfBounds { .. }
+            | sqlx::Error::TypeNotFound { .. } => Error::Serialization(message),
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => Error::Unavailable(message),
+            _ => Error::Invalid(message),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Serialization(error.to_string())
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for Error {
+    fn from(error: mongodb::bson::ser::Error) -> Self {
+        Error::Serialization(error.to_string())
+    }
+}
+
+impl From<mongodb::bson::de::Error> for Error {
+    fn from(error: mongodb::bson::de::Error) -> Self {
+        Error::Serialization(error.to_string())
+    }
+}
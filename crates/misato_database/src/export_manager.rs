@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use mongodb::{
+    bson::doc,
+    options::{FindOneOptions, IndexOptions},
+    Collection, IndexModel,
+};
+
+use misato_utils::get_current_timestamp;
+
+use crate::database::{lookup_index, unique_index};
+use crate::error::Error;
+use crate::models::export_model::*;
+use crate::repository::{ExportRepository, UpdateResult};
+
+#[derive(Clone)]
+pub struct ExportManager {
+    pub exports: Collection<Export>,
+}
+
+impl ExportManager {
+    pub fn init(exports: Collection<Export>) -> Self {
+        Self { exports }
+    }
+
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        self.exports
+            .create_indexes(
+                [
+                    unique_index(doc! {"id": 1}),
+                    lookup_index(doc! {"uuid": 1}),
+                    // A single export per user is prepared at a time.
+                    IndexModel::builder()
+                        .keys(doc! {"uuid": 1, "status": 1})
+                        .options(
+                            IndexOptions::builder()
+                                .unique(true)
+                                .partial_filter_expression(doc! {"status": "pending"})
+                                .build(),
+                        )
+                        .build(),
+                ],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExportRepository for ExportManager {
+    async fn create_export(&self, export: &Export) -> Result<(), Error> {
+        self.exports.insert_one(export, None).await?;
+        Ok(())
+    }
+
+    async fn get_latest_export(&self, uuid: &str) -> Result<Option<Export>, Error> {
+        self.exports
+            .find_one(
+                doc! {"uuid": uuid, "expiration_timestamp": {"$gt": get_current_timestamp() as i64}},
+                FindOneOptions::builder()
+                    .sort(doc! {"timestamp": -1})
+                    .build(),
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn set_status(
+        &self,
+        id: &str,
+        status: &ExportStatus,
+        size: Option<u64>,
+    ) -> Result<UpdateResult, Error> {
+        let mut update = doc! {"status": mongodb::bson::to_bson(status).unwrap()};
+        if let Some(size) = size {
+            update.insert("size", size as i64);
+        }
+        self.exports
+            .update_one(doc! {"id": id}, doc! {"$set": update}, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn fail_stale_export(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        self.exports
+            .update_one(
+                doc! {"uuid": uuid, "status": "pending", "timestamp": {"$lt": timestamp as i64}},
+                doc! {"$set": {"status": "failed"}},
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn consume_export(&self, id: &str) -> Result<Option<Export>, Error> {
+        self.exports
+            .find_one_and_update(
+                doc! {"id": id, "status": "ready", "expiration_timestamp": {"$gt": get_current_timestamp() as i64}},
+                doc! {"$set": {"status": "downloaded"}},
+                None,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn remove_expired(&self) -> Result<Vec<Export>, Error> {
+        let filter = doc! {"expiration_timestamp": {"$lte": get_current_timestamp() as i64}};
+        let mut cursor = self.exports.find(filter.clone(), None).await?;
+        let mut exports = Vec::new();
+        while cursor.advance().await? {
+            exports.push(cursor.deserialize_current()?);
+        }
+        self.exports.delete_many(filter, None).await?;
+        Ok(exports)
+    }
+}
@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, Document},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use misato_utils::get_current_timestamp;
+
+use crate::database::unique_index;
+use crate::error::Error;
+use crate::models::invite_model::*;
+use crate::repository::{DeleteResult, InviteRepository, UpdateResult};
+
+pub struct InviteManager {
+    pub invites: Collection<Invite>,
+}
+
+impl InviteManager {
+    pub fn init(invites: Collection<Invite>) -> Self {
+        Self { invites }
+    }
+
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        self.invites
+            .create_indexes(
+                [unique_index(doc! {"id": 1}), unique_index(doc! {"hash": 1})],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Filter and update redeeming the invite matching `hash` for `uuid`, if it has uses left.
+pub(crate) fn redemption(hash: &str, uuid: &str) -> (Document, Document) {
+    let timestamp = get_current_timestamp();
+    let redemption = doc! {"uuid": uuid, "timestamp": timestamp as i64};
+    (
+        doc! {
+            "hash": hash,
+            "$expr": {"$lt": ["$uses", "$max_uses"]},
+            "$or": [
+                {"expiration_timestamp": 0_i64},
+                {"expiration_timestamp": {"$gt": timestamp as i64}},
+            ],
+        },
+        doc! {"$inc": {"uses": 1}, "$push": {"redemptions": redemption}},
+    )
+}
+
+#[async_trait]
+impl InviteRepository for InviteManager {
+    async fn create_invite(&self, invite: &Invite) -> Result<(), Error> {
+        self.invites.insert_one(invite, None).await?;
+        Ok(())
+    }
+
+    async fn get_invites(&self) -> Result<Vec<Invite>, Error> {
+        let mut cursor = self.invites.find(None, None).await?;
+        let mut invites = Vec::new();
+        while cursor.advance().await? {
+            invites.push(cursor.deserialize_current()?);
+        }
+        Ok(invites)
+    }
+
+    async fn delete_invite(&self, id: &str) -> Result<DeleteResult, Error> {
+        self.invites
+            .delete_one(doc! {"id": id}, None)
+            .await
+            .map(DeleteResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn redeem(&self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let (filter, update) = redemption(hash, uuid);
+        self.invites
+            .find_one_and_update(
+                filter,
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn release(&self, id: &str, uuid: &str) -> Result<UpdateResult, Error> {
+        self.invites
+            .update_one(
+                doc! {"id": id, "redemptions.uuid": uuid},
+                doc! {"$inc": {"uses": -1}, "$pull": {"redemptions": {"uuid": uuid}}},
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+}
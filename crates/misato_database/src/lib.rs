@@ -1,4 +1,19 @@
 pub mod api_manager;
 pub mod database;
+pub mod deferred;
+pub mod error;
+pub mod export_manager;
+pub mod invite_manager;
+pub mod login_manager;
+pub mod measured;
+pub mod memory;
+pub mod migration;
 pub mod models;
+pub mod oauth_manager;
+pub mod query_log;
+pub mod repository;
+pub mod sql;
+pub mod transaction;
 pub mod user_manager;
+
+pub use error::Error;
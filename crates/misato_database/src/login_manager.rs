@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use mongodb::{
+    bson::doc,
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::database::unique_index;
+use crate::error::Error;
+use crate::models::loginattempt_model::*;
+use crate::repository::{DeleteResult, LoginAttemptRepository};
+
+pub struct LoginAttemptManager {
+    pub attempts: Collection<LoginAttempt>,
+    max_attempts: u32,
+    lockout_duration: u64,
+    lockout_max_duration: u64,
+}
+
+impl LoginAttemptManager {
+    pub fn init(attempts: Collection<LoginAttempt>, settings: &Settings) -> Self {
+        Self {
+            attempts,
+            max_attempts: settings.login_max_attempts,
+            lockout_duration: settings.login_lockout_duration,
+            lockout_max_duration: settings.login_lockout_max_duration,
+        }
+    }
+
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        self.attempts
+            .create_index(unique_index(doc! {"key": 1}), None)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for LoginAttemptManager {
+    async fn get_lock(&self, key: &str) -> Result<Option<u64>, Error> {
+        let timestamp = get_current_timestamp();
+        match self.attempts.find_one(doc! {"key": key}, None).await? {
+            Some(attempt) if attempt.is_locked(timestamp) => Ok(Some(attempt.locked_until)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn register_failure(&self, key: &str) -> Result<LoginAttempt, Error> {
+        let timestamp = get_current_timestamp() as i64;
+        let expired = timestamp.saturating_sub(self.lockout_max_duration as i64 * 1000);
+        let (max_attempts, duration) = (self.max_attempts as i64, self.lockout_duration as i64);
+
+        // A single update, concurrent failures each count and the longest lock wins.
+        let pipeline = vec![
+            doc! {"$set": {
+                "key": key,
+                "locked_until": {"$ifNull": ["$locked_until", 0_i64]},
+                // Forget failures older than the longest lockout, unless still locked.
+                "failures": {"$cond": [
+                    {"$and": [
+                        {"$lt": [{"$ifNull": ["$last_failure", 0_i64]}, expired]},
+                        {"$lt": [{"$ifNull": ["$locked_until", 0_i64]}, timestamp]},
+                    ]},
+                    1_i64,
+                    {"$add": [{"$ifNull": ["$failures", 0_i64]}, 1_i64]},
+                ]},
+                "last_failure": timestamp,
+            }},
+            // Same as `LoginAttempt::lockout_duration`.
+            doc! {"$set": {"locked_until": {"$cond": [
+                {"$lt": ["$failures", max_attempts]},
+                "$locked_until",
+                {"$max": ["$locked_until", {"$add": [timestamp, {"$multiply": [1000_i64, {"$min": [
+                    self.lockout_max_duration as i64,
+                    {"$multiply": [duration, {"$pow": [
+                        2_i64,
+                        {"$min": [{"$subtract": ["$failures", max_attempts]}, 32_i64]},
+                    ]}]},
+                ]}]}]}]},
+            ]}}},
+        ];
+        let attempt = self
+            .attempts
+            .find_one_and_update(
+                doc! {"key": key},
+                pipeline,
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .unwrap_or_default();
+        Ok(attempt)
+    }
+
+    async fn clear_failures(&self, key: &str) -> Result<DeleteResult, Error> {
+        self.attempts
+            .delete_one(doc! {"key": key}, None)
+            .await
+            .map(DeleteResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn get_locked_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let timestamp = get_current_timestamp() as i64;
+        let mut cursor = self
+            .attempts
+            .find(doc! {"locked_until": {"$gt": timestamp}}, None)
+            .await?;
+        let mut keys = Vec::new();
+        while cursor.advance().await? {
+            let attempt: LoginAttempt = cursor.deserialize_current()?;
+            if let Some(key) = attempt.key.strip_prefix(prefix) {
+                keys.push(key.to_string());
+            }
+        }
+        Ok(keys)
+    }
+}
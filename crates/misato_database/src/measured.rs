@@ -0,0 +1,58 @@
+//! Repositories wrapped to report how long each of their calls takes, whatever the backend.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use misato_security::password::Password;
+
+use crate::error::Error;
+use crate::models::{
+    apiuser_model::*, export_model::*, invite_model::*, loginattempt_model::*, oauth_model::*,
+    user_model::*,
+};
+use crate::repository::{for_each_repository, *};
+use crate::user_manager::{UserSearch, UserSort};
+
+/// Told about every repository call, `manager` is the store and `method` the trait method.
+pub trait OperationObserver: Send + Sync {
+    fn observe(&self, manager: &'static str, method: &'static str, duration: Duration, ok: bool);
+}
+
+pub struct Measured<T: ?Sized> {
+    inner: Arc<T>,
+    observer: Arc<dyn OperationObserver>,
+}
+
+impl<T: ?Sized> Measured<T> {
+    pub fn new(inner: Arc<T>, observer: Arc<dyn OperationObserver>) -> Self {
+        Self { inner, observer }
+    }
+}
+
+/// Forwards every method of the trait to the wrapped repository and times it.
+macro_rules! measured {
+    ($trait:ident as $manager:literal {
+        $(fn $method:ident($($arg:ident: $type:ty),* $(,)?) -> $result:ty;)*
+    }) => {
+        #[async_trait]
+        impl $trait for Measured<dyn $trait> {
+            $(
+                async fn $method(&self, $($arg: $type),*) -> $result {
+                    let started = Instant::now();
+                    let result = self.inner.$method($($arg),*).await;
+                    self.observer.observe(
+                        $manager,
+                        stringify!($method),
+                        started.elapsed(),
+                        result.is_ok(),
+                    );
+                    result
+                }
+            )*
+        }
+    };
+}
+
+for_each_repository!(measured);
@@ -0,0 +1,159 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::apiuser_model::*;
+use crate::repository::{ApiUserRepository, DeleteResult, UpdateResult};
+
+/// API accounts have no username, looking one up by it finds nothing.
+fn is_selected(apiuser: &ApiUser, username: Option<&str>, uuid: Option<&str>) -> bool {
+    username.is_none() && Some(apiuser.uuid.as_str()) == uuid
+}
+
+fn has_token(apiuser: &ApiUser, token: &str, timestamp: u64) -> bool {
+    matches!(&apiuser.token, Some(candidate) if candidate.token == token && candidate.expiration_timestamp >= timestamp)
+}
+
+#[derive(Default)]
+pub struct MemoryApiUserManager {
+    apiusers: Mutex<Vec<ApiUser>>,
+}
+
+impl MemoryApiUserManager {
+    pub fn init() -> Self {
+        Self::default()
+    }
+
+    fn update(
+        &self,
+        filter: impl Fn(&ApiUser) -> bool,
+        change: impl FnOnce(&mut ApiUser),
+    ) -> UpdateResult {
+        let mut apiusers = self.apiusers.lock().unwrap();
+        match apiusers.iter_mut().find(|apiuser| filter(apiuser)) {
+            Some(apiuser) => {
+                let previous = apiuser.clone();
+                change(apiuser);
+                UpdateResult {
+                    matched_count: 1,
+                    modified_count: (*apiuser != previous) as u64,
+                }
+            }
+            None => UpdateResult::default(),
+        }
+    }
+
+    fn delete(&self, filter: impl Fn(&ApiUser) -> bool) -> DeleteResult {
+        let mut apiusers = self.apiusers.lock().unwrap();
+        match apiusers.iter().position(filter) {
+            Some(index) => {
+                apiusers.remove(index);
+                DeleteResult { deleted_count: 1 }
+            }
+            None => DeleteResult::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiUserRepository for MemoryApiUserManager {
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+        Ok(self
+            .apiusers
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|apiuser| apiuser.uuid == uuid))
+    }
+
+    async fn create_apiuser(&self, apiuser: &ApiUser) -> Result<UpdateResult, Error> {
+        let mut apiusers = self.apiusers.lock().unwrap();
+        match apiusers
+            .iter_mut()
+            .find(|existing| existing.uuid == apiuser.uuid)
+        {
+            Some(existing) => {
+                let modified = *existing != *apiuser;
+                *existing = apiuser.clone();
+                Ok(UpdateResult {
+                    matched_count: 1,
+                    modified_count: modified as u64,
+                })
+            }
+            None => {
+                apiusers.push(apiuser.clone());
+                Ok(UpdateResult::default())
+            }
+        }
+    }
+
+    async fn get_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<ApiUser>, Error> {
+        Ok(self
+            .apiusers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|apiuser| is_selected(apiuser, username, uuid))
+            .cloned())
+    }
+
+    async fn delete_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<DeleteResult>, Error> {
+        if username.is_none() && uuid.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.delete(|apiuser| is_selected(apiuser, username, uuid)),
+        ))
+    }
+
+    async fn delete_apiuser_from_token(&self, token: &str) -> Result<Option<DeleteResult>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(Some(
+            self.delete(|apiuser| has_token(apiuser, token, timestamp)),
+        ))
+    }
+
+    async fn set_token(&self, uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |apiuser| apiuser.uuid == uuid,
+            |apiuser| apiuser.token = Some(token.clone()),
+        ))
+    }
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |apiuser| apiuser.uuid == uuid,
+            |apiuser| apiuser.token = None,
+        ))
+    }
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.update(
+            |apiuser| has_token(apiuser, token, timestamp),
+            |apiuser| apiuser.token = None,
+        ))
+    }
+
+    async fn get_apiuser_from_token(&self, token: &str) -> Result<Option<ApiUser>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .apiusers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|apiuser| has_token(apiuser, token, timestamp))
+            .cloned())
+    }
+}
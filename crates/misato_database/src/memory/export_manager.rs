@@ -0,0 +1,121 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::export_model::*;
+use crate::repository::{ExportRepository, UpdateResult};
+
+#[derive(Default)]
+pub struct MemoryExportManager {
+    exports: Mutex<Vec<Export>>,
+}
+
+impl MemoryExportManager {
+    pub fn init() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ExportRepository for MemoryExportManager {
+    async fn create_export(&self, export: &Export) -> Result<(), Error> {
+        let mut exports = self.exports.lock().unwrap();
+        if export.status == ExportStatus::Pending
+            && exports.iter().any(|pending| {
+                pending.uuid == export.uuid && pending.status == ExportStatus::Pending
+            })
+        {
+            return Err(Error::DuplicateKey(format!(
+                "export of {} already pending",
+                export.uuid
+            )));
+        }
+        exports.push(export.clone());
+        Ok(())
+    }
+
+    async fn get_latest_export(&self, uuid: &str) -> Result<Option<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .exports
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|export| export.uuid == uuid && export.expiration_timestamp > timestamp)
+            .max_by_key(|export| export.timestamp)
+            .cloned())
+    }
+
+    async fn set_status(
+        &self,
+        id: &str,
+        status: &ExportStatus,
+        size: Option<u64>,
+    ) -> Result<UpdateResult, Error> {
+        let mut exports = self.exports.lock().unwrap();
+        Ok(match exports.iter_mut().find(|export| export.id == id) {
+            Some(export) => {
+                let previous = export.clone();
+                export.status = status.clone();
+                if size.is_some() {
+                    export.size = size;
+                }
+                UpdateResult {
+                    matched_count: 1,
+                    modified_count: (*export != previous) as u64,
+                }
+            }
+            None => UpdateResult::default(),
+        })
+    }
+
+    async fn fail_stale_export(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        let mut exports = self.exports.lock().unwrap();
+        Ok(
+            match exports.iter_mut().find(|export| {
+                export.uuid == uuid
+                    && export.status == ExportStatus::Pending
+                    && export.timestamp < timestamp
+            }) {
+                Some(export) => {
+                    export.status = ExportStatus::Failed;
+                    UpdateResult {
+                        matched_count: 1,
+                        modified_count: 1,
+                    }
+                }
+                None => UpdateResult::default(),
+            },
+        )
+    }
+
+    async fn consume_export(&self, id: &str) -> Result<Option<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut exports = self.exports.lock().unwrap();
+        Ok(exports
+            .iter_mut()
+            .find(|export| {
+                export.id == id
+                    && export.status == ExportStatus::Ready
+                    && export.expiration_timestamp > timestamp
+            })
+            .map(|export| {
+                let previous = export.clone();
+                export.status = ExportStatus::Downloaded;
+                previous
+            }))
+    }
+
+    async fn remove_expired(&self) -> Result<Vec<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut exports = self.exports.lock().unwrap();
+        let (expired, kept) = exports
+            .drain(..)
+            .partition(|export| export.expiration_timestamp <= timestamp);
+        *exports = kept;
+        Ok(expired)
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::invite_model::*;
+use crate::repository::{DeleteResult, InviteRepository, UpdateResult};
+
+#[derive(Default)]
+pub struct MemoryInviteManager {
+    invites: Mutex<Vec<Invite>>,
+}
+
+impl MemoryInviteManager {
+    pub fn init() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl InviteRepository for MemoryInviteManager {
+    async fn create_invite(&self, invite: &Invite) -> Result<(), Error> {
+        self.invites.lock().unwrap().push(invite.clone());
+        Ok(())
+    }
+
+    async fn get_invites(&self) -> Result<Vec<Invite>, Error> {
+        Ok(self.invites.lock().unwrap().clone())
+    }
+
+    async fn delete_invite(&self, id: &str) -> Result<DeleteResult, Error> {
+        let mut invites = self.invites.lock().unwrap();
+        let count = invites.len();
+        if let Some(index) = invites.iter().position(|invite| invite.id == id) {
+            invites.remove(index);
+        }
+        Ok(DeleteResult {
+            deleted_count: (count - invites.len()) as u64,
+        })
+    }
+
+    async fn redeem(&self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut invites = self.invites.lock().unwrap();
+        Ok(invites
+            .iter_mut()
+            .find(|invite| {
+                invite.hash == hash
+                    && invite.uses < invite.max_uses
+                    && (invite.expiration_timestamp == 0 || invite.expiration_timestamp > timestamp)
+            })
+            .map(|invite| {
+                invite.uses += 1;
+                invite.redemptions.push(InviteRedemption {
+                    uuid: uuid.to_string(),
+                    timestamp,
+                });
+                invite.clone()
+            }))
+    }
+
+    async fn release(&self, id: &str, uuid: &str) -> Result<UpdateResult, Error> {
+        let mut invites = self.invites.lock().unwrap();
+        Ok(
+            match invites.iter_mut().find(|invite| {
+                invite.id == id
+                    && invite
+                        .redemptions
+                        .iter()
+                        .any(|redemption| redemption.uuid == uuid)
+            }) {
+                Some(invite) => {
+                    invite.uses = invite.uses.saturating_sub(1);
+                    invite
+                        .redemptions
+                        .retain(|redemption| redemption.uuid != uuid);
+                    UpdateResult {
+                        matched_count: 1,
+                        modified_count: 1,
+                    }
+                }
+                None => UpdateResult::default(),
+            },
+        )
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::error::Error;
+use crate::models::loginattempt_model::*;
+use crate::repository::{DeleteResult, LoginAttemptRepository};
+
+pub struct MemoryLoginAttemptManager {
+    attempts: Mutex<HashMap<String, LoginAttempt>>,
+    max_attempts: u32,
+    lockout_duration: u64,
+    lockout_max_duration: u64,
+}
+
+impl MemoryLoginAttemptManager {
+    pub fn init(settings: &Settings) -> Self {
+        Self {
+            attempts: Mutex::new(HashMap::new()),
+            max_attempts: settings.login_max_attempts,
+            lockout_duration: settings.login_lockout_duration,
+            lockout_max_duration: settings.login_lockout_max_duration,
+        }
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for MemoryLoginAttemptManager {
+    async fn get_lock(&self, key: &str) -> Result<Option<u64>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(match self.attempts.lock().unwrap().get(key) {
+            Some(attempt) if attempt.is_locked(timestamp) => Some(attempt.locked_until),
+            _ => None,
+        })
+    }
+
+    async fn register_failure(&self, key: &str) -> Result<LoginAttempt, Error> {
+        let timestamp = get_current_timestamp();
+        let mut attempts = self.attempts.lock().unwrap();
+
+        // Forget failures older than the longest lockout, unless still locked.
+        let expired = timestamp.saturating_sub(self.lockout_max_duration * 1000);
+        if matches!(attempts.get(key), Some(attempt) if attempt.last_failure < expired && attempt.locked_until < timestamp)
+        {
+            attempts.remove(key);
+        }
+
+        let attempt = attempts
+            .entry(key.to_string())
+            .or_insert_with(|| LoginAttempt {
+                key: key.to_string(),
+                ..Default::default()
+            });
+        attempt.failures += 1;
+        attempt.last_failure = timestamp;
+        let duration = LoginAttempt::lockout_duration(
+            attempt.failures,
+            self.max_attempts,
+            self.lockout_duration,
+            self.lockout_max_duration,
+        );
+        if duration > 0 {
+            attempt.locked_until = attempt.locked_until.max(timestamp + duration * 1000);
+        }
+        Ok(attempt.clone())
+    }
+
+    async fn clear_failures(&self, key: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self.attempts.lock().unwrap().remove(key).is_some() as u64,
+        })
+    }
+
+    async fn get_locked_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .attempts
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|attempt| attempt.is_locked(timestamp))
+            .filter_map(|attempt| attempt.key.strip_prefix(prefix))
+            .map(str::to_string)
+            .collect())
+    }
+}
@@ -0,0 +1,8 @@
+//! Stores keeping everything in the process, for tests and throwaway instances.
+
+pub mod api_manager;
+pub mod export_manager;
+pub mod invite_manager;
+pub mod login_manager;
+pub mod oauth_manager;
+pub mod user_manager;
@@ -0,0 +1,196 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::oauth_model::*;
+use crate::repository::{DeleteResult, OAuthRepository};
+
+#[derive(Default)]
+struct OAuthStore {
+    clients: Vec<OAuthClient>,
+    consents: Vec<OAuthConsent>,
+    codes: Vec<OAuthCode>,
+    tokens: Vec<OAuthToken>,
+}
+
+/// Removes the matching items and returns how many there were.
+fn remove<T>(items: &mut Vec<T>, filter: impl Fn(&T) -> bool) -> u64 {
+    let count = items.len();
+    items.retain(|item| !filter(item));
+    (count - items.len()) as u64
+}
+
+/// Every collection sits behind one lock, so cascades are atomic.
+#[derive(Default)]
+pub struct MemoryOAuthManager {
+    store: Mutex<OAuthStore>,
+}
+
+impl MemoryOAuthManager {
+    pub fn init() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OAuthRepository for MemoryOAuthManager {
+    async fn create_client(&self, client: &OAuthClient) -> Result<(), Error> {
+        self.store.lock().unwrap().clients.push(client.clone());
+        Ok(())
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, Error> {
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .clients
+            .iter()
+            .find(|client| client.client_id == client_id)
+            .cloned())
+    }
+
+    async fn get_clients(&self, owner: &str) -> Result<Vec<OAuthClient>, Error> {
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .clients
+            .iter()
+            .filter(|client| client.owner == owner)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_client(&self, owner: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let mut store = self.store.lock().unwrap();
+        let deleted_count = remove(&mut store.clients, |client| {
+            client.owner == owner && client.client_id == client_id
+        });
+        if deleted_count > 0 {
+            remove(&mut store.consents, |consent| {
+                consent.client_id == client_id
+            });
+            remove(&mut store.codes, |code| code.client_id == client_id);
+            remove(&mut store.tokens, |token| token.client_id == client_id);
+        }
+        Ok(DeleteResult { deleted_count })
+    }
+
+    async fn get_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+    ) -> Result<Option<OAuthConsent>, Error> {
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .consents
+            .iter()
+            .find(|consent| consent.uuid == uuid && consent.client_id == client_id)
+            .cloned())
+    }
+
+    async fn get_consents(&self, uuid: &str) -> Result<Vec<OAuthConsent>, Error> {
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .consents
+            .iter()
+            .filter(|consent| consent.uuid == uuid)
+            .cloned()
+            .collect())
+    }
+
+    async fn add_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<Option<OAuthConsent>, Error> {
+        let mut store = self.store.lock().unwrap();
+        let index = match store
+            .consents
+            .iter()
+            .position(|consent| consent.uuid == uuid && consent.client_id == client_id)
+        {
+            Some(index) => index,
+            None => {
+                store.consents.push(OAuthConsent {
+                    uuid: uuid.to_string(),
+                    client_id: client_id.to_string(),
+                    ..Default::default()
+                });
+                store.consents.len() - 1
+            }
+        };
+        let consent = &mut store.consents[index];
+        for scope in scopes {
+            if !consent.scopes.contains(scope) {
+                consent.scopes.push(scope.clone());
+            }
+        }
+        consent.timestamp = get_current_timestamp();
+        Ok(Some(consent.clone()))
+    }
+
+    async fn revoke_consent(&self, uuid: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let mut store = self.store.lock().unwrap();
+        remove(&mut store.codes, |code| {
+            code.uuid == uuid && code.client_id == client_id
+        });
+        remove(&mut store.tokens, |token| {
+            token.uuid == uuid && token.client_id == client_id
+        });
+        Ok(DeleteResult {
+            deleted_count: remove(&mut store.consents, |consent| {
+                consent.uuid == uuid && consent.client_id == client_id
+            }),
+        })
+    }
+
+    async fn save_code(&self, code: &OAuthCode) -> Result<(), Error> {
+        self.store.lock().unwrap().codes.push(code.clone());
+        Ok(())
+    }
+
+    async fn consume_code(&self, hash: &str) -> Result<Option<OAuthCode>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut store = self.store.lock().unwrap();
+        Ok(store
+            .codes
+            .iter()
+            .position(|code| code.hash == hash && code.expiration_timestamp >= timestamp)
+            .map(|index| store.codes.remove(index)))
+    }
+
+    async fn save_token(&self, token: &OAuthToken) -> Result<(), Error> {
+        self.store.lock().unwrap().tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn get_token(&self, hash: &str) -> Result<Option<OAuthToken>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .tokens
+            .iter()
+            .find(|token| token.hash == hash && token.expiration_timestamp >= timestamp)
+            .cloned())
+    }
+
+    async fn revoke_token(&self, hash: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: remove(&mut self.store.lock().unwrap().tokens, |token| {
+                token.hash == hash && token.client_id == client_id
+            }),
+        })
+    }
+}
@@ -0,0 +1,555 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use misato_security::password::Password;
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::error::Error;
+use crate::models::user_model::*;
+use crate::repository::{DeleteResult, UpdateResult, UserRepository};
+use crate::user_manager::{UserSearch, UserSort};
+
+fn has_token(tokens: &Option<Vec<UserToken>>, token: &str, timestamp: u64) -> bool {
+    tokens
+        .iter()
+        .flatten()
+        .any(|candidate| candidate.token == token && candidate.expiration_timestamp >= timestamp)
+}
+
+fn has_secret(secret: &Option<UserSecretToken>, hash: &str, timestamp: u64) -> bool {
+    matches!(secret, Some(secret) if secret.hash == hash && secret.expiration_timestamp >= timestamp)
+}
+
+/// Current or former username, ignoring case, like the unique index of the SQL store.
+/// Usernames are compared ignoring case.
+fn same_username(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+fn holds_username(user: &User, username: &str) -> bool {
+    same_username(&user.username, username)
+        || user
+            .previous_usernames
+            .iter()
+            .flatten()
+            .any(|previous| same_username(&previous.username, username))
+}
+
+pub struct MemoryUserManager {
+    users: Mutex<Vec<User>>,
+    /// Seconds between the deletion of an account and its purge.
+    deletion_grace_period: u64,
+}
+
+impl MemoryUserManager {
+    pub fn init(settings: &Settings) -> Self {
+        Self {
+            users: Mutex::new(Vec::new()),
+            deletion_grace_period: settings.deletion_grace_period,
+        }
+    }
+
+    fn deleted_status(&self) -> UserStatus {
+        let timestamp = get_current_timestamp();
+        UserStatus::Deleted {
+            timestamp,
+            purge_timestamp: timestamp + self.deletion_grace_period * 1000,
+        }
+    }
+
+    fn find(&self, filter: impl Fn(&User) -> bool) -> Option<User> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|user| filter(user))
+            .cloned()
+    }
+
+    /// Applies `change` to the first user matching `filter`, counted as modified only if it changed.
+    fn update(
+        &self,
+        filter: impl Fn(&User) -> bool,
+        change: impl FnOnce(&mut User),
+    ) -> UpdateResult {
+        let mut users = self.users.lock().unwrap();
+        match users.iter_mut().find(|user| filter(user)) {
+            Some(user) => {
+                let previous = user.clone();
+                change(user);
+                UpdateResult {
+                    matched_count: 1,
+                    modified_count: (*user != previous) as u64,
+                }
+            }
+            None => UpdateResult::default(),
+        }
+    }
+
+    fn update_uuid(&self, uuid: &str, change: impl FnOnce(&mut User)) -> UpdateResult {
+        self.update(|user| user.uuid == uuid, change)
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserManager {
+    async fn username_exists(&self, username: &str) -> Result<bool, Error> {
+        Ok(self.find(|user| holds_username(user, username)).is_some())
+    }
+
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+        Ok(self.find(|user| user.uuid == uuid).is_some())
+    }
+
+    async fn create_user(&self, user: &User) -> Result<(), Error> {
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|existing| existing.uuid == user.uuid || holds_username(existing, &user.username))
+        {
+            return Err(Error::DuplicateKey(format!(
+                "username {} already used",
+                user.username
+            )));
+        }
+        if user.email_verified
+            && users
+                .iter()
+                .any(|existing| existing.email_verified && existing.email == user.email)
+        {
+            return Err(Error::DuplicateKey(format!(
+                "{:?} is verified by another account",
+                user.email
+            )));
+        }
+        users.push(user.clone());
+        Ok(())
+    }
+
+    async fn get_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<User>, Error> {
+        Ok(match (username, uuid) {
+            (Some(username), _) => self.find(|user| same_username(&user.username, username)),
+            (None, Some(uuid)) => self.find(|user| user.uuid == uuid),
+            (None, None) => None,
+        })
+    }
+
+    async fn delete_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<UpdateResult>, Error> {
+        if username.is_none() && uuid.is_none() {
+            return Ok(None);
+        }
+        let status = self.deleted_status();
+        Ok(Some(self.update(
+            |user| {
+                let found = match username {
+                    Some(username) => same_username(&user.username, username),
+                    None => Some(user.uuid.as_str()) == uuid,
+                };
+                found
+                    && !matches!(
+                        user.status,
+                        UserStatus::Deleted { .. } | UserStatus::Purged { .. }
+                    )
+            },
+            |user| {
+                user.status = status;
+                user.tokens = None;
+                user.challenges = None;
+            },
+        )))
+    }
+
+    async fn delete_user_from_token(&self, token: &str) -> Result<Option<UpdateResult>, Error> {
+        let timestamp = get_current_timestamp();
+        let status = self.deleted_status();
+        Ok(Some(self.update(
+            |user| has_token(&user.tokens, token, timestamp),
+            |user| {
+                user.status = status;
+                user.tokens = None;
+                user.challenges = None;
+            },
+        )))
+    }
+
+    async fn set_status(&self, uuid: &str, status: &UserStatus) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |user| user.uuid == uuid && !matches!(user.status, UserStatus::Purged { .. }),
+            |user| {
+                user.status = status.clone();
+                if *status != UserStatus::Active {
+                    user.tokens = None;
+                    user.challenges = None;
+                }
+            },
+        ))
+    }
+
+    async fn remove_user(&self, uuid: &str) -> Result<DeleteResult, Error> {
+        let mut users = self.users.lock().unwrap();
+        let count = users.len();
+        users.retain(|user| user.uuid != uuid);
+        Ok(DeleteResult {
+            deleted_count: (count - users.len()) as u64,
+        })
+    }
+
+    async fn purge_deleted_users(&self) -> Result<Vec<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut purged = Vec::new();
+        for user in self.users.lock().unwrap().iter_mut() {
+            if matches!(user.status, UserStatus::Deleted { purge_timestamp, .. } if purge_timestamp <= timestamp)
+            {
+                purged.push(std::mem::replace(user, user.tombstone(timestamp)));
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn count_sessions(&self) -> Result<u64, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|user| user.session_count(timestamp))
+            .sum())
+    }
+
+    async fn search_users(
+        &self,
+        search: &UserSearch,
+        sort: &UserSort,
+        descending: bool,
+        skip: u64,
+        limit: i64,
+    ) -> Result<(Vec<User>, u64), Error> {
+        let mut users: Vec<User> = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|user| search.matches(user))
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| {
+            let order = match sort {
+                UserSort::Username => a.username.to_lowercase().cmp(&b.username.to_lowercase()),
+                UserSort::Created => a.timestamp.cmp(&b.timestamp),
+                UserSort::LastLogin => a.last_login.cmp(&b.last_login),
+            };
+            let order = if descending { order.reverse() } else { order };
+            order.then_with(|| a.uuid.cmp(&b.uuid))
+        });
+        let total = users.len() as u64;
+        let users = users.into_iter().skip(skip as usize);
+        let users = match limit.unsigned_abs() {
+            0 => users.collect(),
+            limit => users.take(limit as usize).collect(),
+        };
+        Ok((users, total))
+    }
+
+    async fn set_last_login(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.last_login = Some(timestamp)))
+    }
+
+    async fn set_role(&self, uuid: &str, role: &UserRoleType) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.access.role = role.clone()))
+    }
+
+    async fn save_token(&self, uuid: &str, token: &UserToken) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.update_uuid(uuid, |user| {
+            let tokens = user.tokens.get_or_insert_with(Vec::new);
+            tokens.retain(|session| session.expiration_timestamp >= timestamp);
+            tokens.push(token.clone());
+        }))
+    }
+
+    async fn add_log(&self, uuid: &str, log: &UserLog) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.logs.get_or_insert_with(Vec::new).push(log.clone())
+        }))
+    }
+
+    async fn set_totp(&self, uuid: &str, totp: &UserTotp) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.totp = Some(totp.clone())))
+    }
+
+    async fn clear_totp(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.totp = None))
+    }
+
+    async fn use_totp_step(&self, uuid: &str, step: u64) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |user| user.uuid == uuid && matches!(&user.totp, Some(totp) if totp.last_step < step),
+            |user| {
+                if let Some(totp) = &mut user.totp {
+                    totp.last_step = step;
+                }
+            },
+        ))
+    }
+
+    async fn remove_recovery_code(&self, uuid: &str, hash: &str) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |user| {
+                user.uuid == uuid
+                    && matches!(&user.totp, Some(totp) if totp.has_recovery_code(hash))
+            },
+            |user| {
+                if let Some(totp) = &mut user.totp {
+                    totp.recovery_codes.retain(|code| code != hash);
+                }
+            },
+        ))
+    }
+
+    async fn save_challenge(
+        &self,
+        uuid: &str,
+        challenge: &UserToken,
+    ) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.update_uuid(uuid, |user| {
+            let challenges = user.challenges.get_or_insert_with(Vec::new);
+            challenges.retain(|challenge| challenge.expiration_timestamp >= timestamp);
+            challenges.push(challenge.clone());
+        }))
+    }
+
+    async fn remove_challenge(&self, uuid: &str, token: &str) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            if let Some(challenges) = &mut user.challenges {
+                challenges.retain(|challenge| challenge.token != token);
+            }
+        }))
+    }
+
+    async fn get_user_from_challenge(&self, token: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.find(|user| has_token(&user.challenges, token, timestamp)))
+    }
+
+    async fn get_user_from_email(&self, email: &str) -> Result<Option<User>, Error> {
+        Ok(self.find(|user| user.email_verified && user.email.as_deref() == Some(email)))
+    }
+
+    async fn set_password(&self, uuid: &str, password: &Password) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.password = Some(password.clone());
+            user.tokens = None;
+        }))
+    }
+
+    async fn change_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+        keep_token: &str,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.password = Some(password.clone());
+            if let Some(tokens) = &mut user.tokens {
+                tokens.retain(|token| token.token == keep_token);
+            }
+        }))
+    }
+
+    async fn change_username(
+        &self,
+        uuid: &str,
+        previous: &str,
+        username: &str,
+    ) -> Result<UpdateResult, Error> {
+        let history = UserPreviousUsername {
+            username: previous.to_string(),
+            timestamp: get_current_timestamp(),
+        };
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|user| user.uuid != uuid && holds_username(user, username))
+        {
+            return Err(Error::DuplicateKey(format!(
+                "username {} already used",
+                username
+            )));
+        }
+        Ok(
+            match users
+                .iter_mut()
+                .find(|user| user.uuid == uuid && user.username == previous)
+            {
+                Some(user) => {
+                    user.username = username.to_string();
+                    user.previous_usernames
+                        .get_or_insert_with(Vec::new)
+                        .push(history);
+                    UpdateResult {
+                        matched_count: 1,
+                        modified_count: 1,
+                    }
+                }
+                None => UpdateResult::default(),
+            },
+        )
+    }
+
+    async fn rehash_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.password = Some(password.clone())))
+    }
+
+    async fn set_profile(&self, uuid: &str, profile: &UserProfile) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.profile = UserProfile {
+                avatar: user.profile.avatar.take(),
+                ..profile.clone()
+            }
+        }))
+    }
+
+    async fn set_avatar(&self, uuid: &str, avatar: Option<&str>) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.profile.avatar = avatar.map(str::to_string)
+        }))
+    }
+
+    async fn get_user_from_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Error> {
+        Ok(self.find(|user| {
+            user.identities
+                .iter()
+                .any(|identity| identity.provider == provider && identity.subject == subject)
+        }))
+    }
+
+    async fn add_identity(
+        &self,
+        uuid: &str,
+        identity: &UserIdentity,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update(
+            |user| {
+                user.uuid == uuid
+                    && !user.identities.iter().any(|linked| {
+                        linked.provider == identity.provider && linked.subject == identity.subject
+                    })
+            },
+            |user| user.identities.push(identity.clone()),
+        ))
+    }
+
+    async fn remove_identity(
+        &self,
+        uuid: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.identities
+                .retain(|identity| identity.provider != provider || identity.subject != subject)
+        }))
+    }
+
+    async fn set_reset_token(
+        &self,
+        uuid: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.reset_token = Some(token.clone())))
+    }
+
+    async fn get_user_from_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.find(|user| has_secret(&user.reset_token, hash, timestamp)))
+    }
+
+    async fn consume_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut users = self.users.lock().unwrap();
+        Ok(users
+            .iter_mut()
+            .find(|user| has_secret(&user.reset_token, hash, timestamp))
+            .map(|user| {
+                let previous = user.clone();
+                user.reset_token = None;
+                previous
+            }))
+    }
+
+    async fn set_email(
+        &self,
+        uuid: &str,
+        email: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| {
+            user.email = Some(email.to_string());
+            user.email_verified = false;
+            user.verification_token = Some(token.clone());
+        }))
+    }
+
+    async fn consume_verification_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut users = self.users.lock().unwrap();
+        let index = users.iter().position(|user| {
+            has_secret(&user.verification_token, hash, timestamp)
+                && matches!(&user.verification_token, Some(token) if token.email.is_some() && token.email == user.email)
+        });
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let email = users[index].email.clone();
+        if users
+            .iter()
+            .any(|user| user.email_verified && user.email == email)
+        {
+            return Err(Error::DuplicateKey(format!(
+                "{:?} is verified by another account",
+                email
+            )));
+        }
+        let user = &mut users[index];
+        user.email_verified = true;
+        user.verification_token = None;
+        Ok(Some(user.clone()))
+    }
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        Ok(self.update_uuid(uuid, |user| user.tokens = None))
+    }
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.update(
+            |user| has_token(&user.tokens, token, timestamp),
+            |user| user.tokens = None,
+        ))
+    }
+
+    async fn get_user_from_token(&self, token: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self.find(|user| has_token(&user.tokens, token, timestamp)))
+    }
+}
@@ -0,0 +1,330 @@
+//! Versioned upgrades of the MongoDB documents, applied at startup before anything reads them.
+//!
+//! Every document carries the `schema_version` of the last migration applied to it, a run
+//! stopped halfway resumes where it was and applying twice changes nothing.
+//! The `migrations` collection records what was applied and holds the lock document
+//! keeping several instances from migrating at once.
+//!
+//! The registry must end at the versions the models write:
+//!
+//! ```
+//! use misato_database::migration::latest_version;
+//! use misato_database::models::{apiuser_model::APIUSER_SCHEMA_VERSION, user_model::USER_SCHEMA_VERSION};
+//!
+//! assert_eq!(latest_version("users"), USER_SCHEMA_VERSION);
+//! assert_eq!(latest_version("apiusers"), APIUSER_SCHEMA_VERSION);
+//! ```
+
+use std::time::Duration;
+
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::UpdateOptions;
+use mongodb::{Collection, Database};
+use uuid::Uuid;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+
+/// Seconds a runner keeps the lock without renewing it, a crashed one blocks no longer.
+const LOCK_DURATION: u64 = 10 * 60;
+
+pub struct Migration {
+    pub collection: &'static str,
+    /// Schema version of the documents once applied, counted from 1 in each collection.
+    pub version: u32,
+    pub name: &'static str,
+    pub up: fn(&mut Document),
+    pub down: fn(&mut Document),
+}
+
+/// Applied in order, a released migration is never edited, later changes get a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        collection: "users",
+        version: 1,
+        name: "backfill_user_defaults",
+        up: backfill_user_defaults,
+        down: keep,
+    },
+    Migration {
+        collection: "apiusers",
+        version: 1,
+        name: "backfill_apiuser_defaults",
+        up: backfill_apiuser_defaults,
+        down: keep,
+    },
+];
+
+fn set_default(document: &mut Document, key: &str, value: impl Into<Bson>) {
+    if !document.contains_key(key) {
+        document.insert(key, value);
+    }
+}
+
+/// Fields the first documents were written without.
+fn backfill_user_defaults(user: &mut Document) {
+    set_default(user, "timestamp", 0_i64);
+    set_default(user, "email_verified", false);
+    set_default(user, "profile", Document::new());
+    set_default(user, "status", doc! {"state": "active"});
+    set_default(user, "access", doc! {"role": "User"});
+}
+
+fn backfill_apiuser_defaults(apiuser: &mut Document) {
+    set_default(apiuser, "timestamp", 0_i64);
+    set_default(apiuser, "access", doc! {"role": "User"});
+}
+
+/// Backfilled fields are read fine by older builds, there is nothing to undo.
+fn keep(_: &mut Document) {}
+
+pub fn latest_version(collection: &str) -> u32 {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.collection == collection)
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+impl Migration {
+    fn id(&self) -> String {
+        format!("{}:{}", self.collection, self.version)
+    }
+
+    /// Documents at `version`, those written before migrations existed have none.
+    fn at_version(version: u32) -> Document {
+        match version {
+            0 => doc! {"$or": [{"schema_version": 0}, {"schema_version": {"$exists": false}}]},
+            version => doc! {"schema_version": version as i64},
+        }
+    }
+}
+
+pub struct MigrationStatus {
+    pub collection: &'static str,
+    pub version: u32,
+    pub name: &'static str,
+    /// When it was applied, if it was.
+    pub applied: Option<u64>,
+    /// Documents still waiting for it.
+    pub pending: u64,
+}
+
+fn records(db: &Database) -> Collection<Document> {
+    db.collection("migrations")
+}
+
+async fn applied(db: &Database) -> Result<Vec<Document>, Error> {
+    let mut cursor = records(db)
+        .find(doc! {"_id": {"$ne": "lock"}}, None)
+        .await?;
+    let mut applied = Vec::new();
+    while cursor.advance().await? {
+        applied.push(cursor.deserialize_current()?);
+    }
+    Ok(applied)
+}
+
+pub async fn status(db: &Database) -> Result<Vec<MigrationStatus>, Error> {
+    let applied = applied(db).await?;
+    let mut statuses = Vec::new();
+    for migration in MIGRATIONS {
+        let record = applied
+            .iter()
+            .find(|record| record.get_str("_id") == Ok(&migration.id()));
+        let pending = db
+            .collection::<Document>(migration.collection)
+            .count_documents(Migration::at_version(migration.version - 1), None)
+            .await?;
+        statuses.push(MigrationStatus {
+            collection: migration.collection,
+            version: migration.version,
+            name: migration.name,
+            applied: record
+                .and_then(|record| record.get_i64("timestamp").ok())
+                .map(|timestamp| timestamp as u64),
+            pending,
+        });
+    }
+    Ok(statuses)
+}
+
+/// Fails when the database was migrated by a newer build, its documents may not be readable.
+pub async fn check(db: &Database) -> Result<(), Error> {
+    for record in applied(db).await? {
+        let id = record.get_str("_id").unwrap_or_default();
+        if !MIGRATIONS.iter().any(|migration| migration.id() == id) {
+            return Err(Error::Invalid(format!(
+                "migration {} is unknown to this build, roll it back with the build that applied it",
+                id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Takes the lock, or renews it for `owner`, waiting for another runner to finish.
+async fn lock(db: &Database, owner: &str) -> Result<(), Error> {
+    let deadline = get_current_timestamp() + LOCK_DURATION * 1000;
+    loop {
+        let timestamp = get_current_timestamp();
+        let result = records(db)
+            .update_one(
+                doc! {"_id": "lock", "$or": [
+                    {"owner": owner},
+                    {"expiration_timestamp": {"$lt": timestamp as i64}},
+                ]},
+                doc! {"$set": {
+                    "owner": owner,
+                    "expiration_timestamp": (timestamp + LOCK_DURATION * 1000) as i64,
+                }},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(Error::from);
+        match result {
+            Ok(_) => return Ok(()),
+            Err(Error::DuplicateKey(_)) if timestamp < deadline => {
+                tracing::info!("Waiting for another instance to finish its migrations");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(Error::DuplicateKey(_)) => {
+                return Err(Error::Unavailable(
+                    "the migrations are still locked by another instance".to_string(),
+                ))
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+async fn unlock(db: &Database, owner: &str) -> Result<(), Error> {
+    records(db)
+        .delete_one(doc! {"_id": "lock", "owner": owner}, None)
+        .await?;
+    Ok(())
+}
+
+/// Rewrites the documents at `from` with `change`, each one only if nobody changed its version.
+async fn rewrite(
+    collection: &Collection<Document>,
+    from: u32,
+    to: u32,
+    change: fn(&mut Document),
+) -> Result<u64, Error> {
+    let mut cursor = collection.find(Migration::at_version(from), None).await?;
+    let mut count = 0;
+    while cursor.advance().await? {
+        let mut document: Document = cursor.deserialize_current()?;
+        let mut filter = Migration::at_version(from);
+        filter.insert("_id", document.get("_id").cloned().unwrap_or(Bson::Null));
+        change(&mut document);
+        document.insert("schema_version", to as i64);
+        count += collection
+            .replace_one(filter, document, None)
+            .await?
+            .modified_count;
+    }
+    Ok(count)
+}
+
+/// Applies every pending migration in order and returns them.
+pub async fn apply(db: &Database) -> Result<Vec<&'static Migration>, Error> {
+    let owner = Uuid::new_v4().to_string();
+    lock(db, &owner).await?;
+    let result = apply_locked(db, &owner).await;
+    unlock(db, &owner).await?;
+    result
+}
+
+async fn apply_locked(db: &Database, owner: &str) -> Result<Vec<&'static Migration>, Error> {
+    let applied = applied(db).await?;
+    let mut migrations = Vec::new();
+    for migration in MIGRATIONS {
+        if applied
+            .iter()
+            .any(|record| record.get_str("_id") == Ok(&migration.id()))
+        {
+            continue;
+        }
+        lock(db, owner).await?;
+        let count = rewrite(
+            &db.collection(migration.collection),
+            migration.version - 1,
+            migration.version,
+            migration.up,
+        )
+        .await?;
+        records(db)
+            .insert_one(
+                doc! {
+                    "_id": migration.id(),
+                    "collection": migration.collection,
+                    "version": migration.version as i64,
+                    "name": migration.name,
+                    "timestamp": get_current_timestamp() as i64,
+                },
+                None,
+            )
+            .await?;
+        tracing::info!(
+            migration = %migration.id(),
+            name = migration.name,
+            documents = count,
+            "Applied migration"
+        );
+        migrations.push(migration);
+    }
+    Ok(migrations)
+}
+
+/// Reverts the latest migration applied to `collection` and returns it.
+pub async fn rollback(
+    db: &Database,
+    collection: &str,
+) -> Result<Option<&'static Migration>, Error> {
+    let owner = Uuid::new_v4().to_string();
+    lock(db, &owner).await?;
+    let result = rollback_locked(db, collection).await;
+    unlock(db, &owner).await?;
+    result
+}
+
+async fn rollback_locked(
+    db: &Database,
+    collection: &str,
+) -> Result<Option<&'static Migration>, Error> {
+    let applied = applied(db).await?;
+    let migration = MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|migration| migration.collection == collection)
+        .find(|migration| {
+            applied
+                .iter()
+                .any(|record| record.get_str("_id") == Ok(&migration.id()))
+        });
+    let migration = match migration {
+        Some(migration) => migration,
+        None => return Ok(None),
+    };
+    let count = rewrite(
+        &db.collection(migration.collection),
+        migration.version,
+        migration.version - 1,
+        migration.down,
+    )
+    .await?;
+    records(db)
+        .delete_one(doc! {"_id": migration.id()}, None)
+        .await?;
+    tracing::info!(
+        migration = %migration.id(),
+        name = migration.name,
+        documents = count,
+        "Rolled back migration"
+    );
+    Ok(Some(migration))
+}
@@ -10,8 +10,17 @@ pub struct ApiUserToken {
     pub expiration_timestamp: u64,
 }
 
+/// Uuid of the account of the admin token, the only API user no user owns.
+pub const DEFAULT_APIUSER_UUID: &str = "admin";
+
+/// Version of the API user documents written by this build, see `migration`.
+pub const APIUSER_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
 pub struct ApiUser {
+    /// 0 for documents written before migrations existed.
+    #[serde(default)]
+    pub schema_version: u32,
     pub timestamp: u64,
     pub uuid: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -22,8 +31,9 @@ pub struct ApiUser {
 impl ApiUser {
     pub fn create_default(token: String) -> Self {
         Self {
+            schema_version: APIUSER_SCHEMA_VERSION,
             timestamp: 0,
-            uuid: "admin".to_string(),
+            uuid: DEFAULT_APIUSER_UUID.to_string(),
             token: Some(ApiUserToken {
                 token,
                 timestamp: get_current_timestamp(),
@@ -37,6 +47,7 @@ impl ApiUser {
     }
     pub fn create(uuid: String) -> Self {
         Self {
+            schema_version: APIUSER_SCHEMA_VERSION,
             timestamp: get_current_timestamp(),
             uuid,
             ..Default::default()
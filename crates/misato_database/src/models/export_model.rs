@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use misato_utils::get_current_timestamp;
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    #[default]
+    Pending,
+    Ready,
+    Failed,
+    Downloaded,
+}
+
+/// Archive of the data tied to a user, stored as `<id>.zip` in the export directory.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Export {
+    pub id: String,
+    pub uuid: String,
+    pub timestamp: u64,
+    /// The archive is removed after this, downloaded or not.
+    pub expiration_timestamp: u64,
+    pub status: ExportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl Export {
+    pub fn create(uuid: &str, seconds: u64) -> Self {
+        let timestamp = get_current_timestamp();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            uuid: uuid.to_string(),
+            timestamp,
+            expiration_timestamp: timestamp + seconds * 1000,
+            ..Default::default()
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.zip", self.id)
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use misato_security::{generate_token, hash_token};
+use misato_utils::get_current_timestamp;
+
+use crate::models::user_model::UserAccess;
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct InviteRedemption {
+    pub uuid: String,
+    pub timestamp: u64,
+}
+
+/// Invite code accepted by the signup, only its hash is stored.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Invite {
+    pub id: String,
+    pub hash: String,
+    pub timestamp: u64,
+    pub max_uses: u32,
+    pub uses: u32,
+    /// 0 when the code never expires.
+    pub expiration_timestamp: u64,
+    /// Given to the users signing up with the code.
+    pub access: UserAccess,
+    pub redemptions: Vec<InviteRedemption>,
+}
+
+impl Invite {
+    /// Returns the code to hand out alongside the record to store.
+    pub fn create(max_uses: u32, seconds: Option<u64>, access: UserAccess) -> (String, Self) {
+        let code = generate_token(24);
+        let timestamp = get_current_timestamp();
+        let invite = Self {
+            id: Uuid::new_v4().to_string(),
+            hash: hash_token(&code),
+            timestamp,
+            max_uses,
+            uses: 0,
+            expiration_timestamp: seconds.map_or(0, |seconds| timestamp + seconds * 1000),
+            access,
+            redemptions: Vec::new(),
+        };
+        (code, invite)
+    }
+}
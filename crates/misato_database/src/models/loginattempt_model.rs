@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct LoginAttempt {
+    pub key: String,
+    pub failures: u32,
+    pub last_failure: u64,
+    pub locked_until: u64,
+}
+
+impl LoginAttempt {
+    /// Usernames are compared ignoring case, so are their counters.
+    pub fn account_key(username: &str) -> String {
+        format!("account:{}", username.to_lowercase())
+    }
+
+    pub fn ip_key(ip: &str) -> String {
+        format!("ip:{}", ip)
+    }
+
+    /// Lockout in seconds once `failures` reached `max_attempts`.
+    /// The duration doubles on every extra failure and is capped to `max_duration`.
+    pub fn lockout_duration(
+        failures: u32,
+        max_attempts: u32,
+        duration: u64,
+        max_duration: u64,
+    ) -> u64 {
+        if failures < max_attempts {
+            return 0;
+        }
+        let exponent = (failures - max_attempts).min(32);
+        duration.saturating_mul(1 << exponent).min(max_duration)
+    }
+
+    pub fn is_locked(&self, timestamp: u64) -> bool {
+        self.locked_until > timestamp
+    }
+}
@@ -1,3 +1,7 @@
 pub mod apiuser_model;
 pub mod data_model;
+pub mod export_model;
+pub mod invite_model;
+pub mod loginattempt_model;
+pub mod oauth_model;
 pub mod user_model;
@@ -0,0 +1,162 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use misato_security::{generate_token, hash_token};
+use misato_utils::get_current_timestamp;
+
+use crate::models::apiuser_model::ApiUserAccess;
+
+/// Scopes third-party applications can ask for.
+pub const OAUTH_SCOPES: &[&str] = &["profile", "profile:write", "email", "identities"];
+
+/// Third-party application registered by a user.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    /// Hash of the secret of confidential clients, public clients have none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// Scopes the client may ask for.
+    pub scopes: Vec<String>,
+    /// Uuid of the user who registered it.
+    pub owner: String,
+    pub timestamp: u64,
+}
+
+impl OAuthClient {
+    /// Returns the secret to hand out when the client is confidential.
+    pub fn create(
+        owner: &str,
+        name: String,
+        redirect_uris: Vec<String>,
+        scopes: Vec<String>,
+        confidential: bool,
+    ) -> (Option<String>, Self) {
+        let secret = if confidential {
+            Some(generate_token(64))
+        } else {
+            None
+        };
+        let client = Self {
+            client_id: Uuid::new_v4().to_string(),
+            secret_hash: secret.as_deref().map(hash_token),
+            name,
+            redirect_uris,
+            scopes,
+            owner: owner.to_string(),
+            timestamp: get_current_timestamp(),
+        };
+        (secret, client)
+    }
+
+    /// Public clients authenticate with PKCE alone.
+    pub fn is_correct_secret(&self, secret: Option<&str>) -> bool {
+        match (&self.secret_hash, secret) {
+            (None, _) => true,
+            (Some(hash), Some(secret)) => *hash == hash_token(secret),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Scopes a user agreed to grant to a client, asked again only for new scopes.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OAuthConsent {
+    pub uuid: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub timestamp: u64,
+}
+
+impl OAuthConsent {
+    pub fn covers(&self, scopes: &[String]) -> bool {
+        scopes.iter().all(|scope| self.scopes.contains(scope))
+    }
+}
+
+/// Single-use authorization code, only its hash is stored.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OAuthCode {
+    pub hash: String,
+    pub client_id: String,
+    pub uuid: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    /// S256 PKCE challenge.
+    pub code_challenge: String,
+    pub expiration_timestamp: u64,
+    /// Copy of `expiration_timestamp` the TTL index of MongoDB removes the record at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+}
+
+impl OAuthCode {
+    pub fn create(
+        client_id: &str,
+        uuid: &str,
+        redirect_uri: &str,
+        scopes: Vec<String>,
+        code_challenge: &str,
+        seconds: u64,
+    ) -> (String, Self) {
+        let code = generate_token(64);
+        let expiration_timestamp = get_current_timestamp() + seconds * 1000;
+        let record = Self {
+            hash: hash_token(&code),
+            client_id: client_id.to_string(),
+            uuid: uuid.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            scopes,
+            code_challenge: code_challenge.to_string(),
+            expiration_timestamp,
+            expires_at: Some(DateTime::from_millis(expiration_timestamp as i64)),
+        };
+        (code, record)
+    }
+}
+
+/// Access token delegated to a client, limited to its scopes and the API access of the user.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct OAuthToken {
+    pub hash: String,
+    pub client_id: String,
+    pub uuid: String,
+    pub scopes: Vec<String>,
+    pub access: ApiUserAccess,
+    pub timestamp: u64,
+    pub expiration_timestamp: u64,
+    /// Copy of `expiration_timestamp` the TTL index of MongoDB removes the record at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+}
+
+impl OAuthToken {
+    pub fn create(
+        client_id: &str,
+        uuid: &str,
+        scopes: Vec<String>,
+        access: ApiUserAccess,
+        seconds: u64,
+    ) -> (String, Self) {
+        let token = generate_token(128);
+        let timestamp = get_current_timestamp();
+        let record = Self {
+            hash: hash_token(&token),
+            client_id: client_id.to_string(),
+            uuid: uuid.to_string(),
+            scopes,
+            access,
+            timestamp,
+            expiration_timestamp: timestamp + seconds * 1000,
+            expires_at: Some(DateTime::from_millis((timestamp + seconds * 1000) as i64)),
+        };
+        (token, record)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+}
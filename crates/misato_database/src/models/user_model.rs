@@ -1,7 +1,8 @@
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use misato_security::{generate_token, password::*};
+use misato_security::{generate_token, hash_token, password::*};
 use misato_utils::get_current_timestamp;
 
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
@@ -17,17 +18,193 @@ pub struct UserToken {
     pub expiration_timestamp: u64,
 }
 
+/// Session as MongoDB stores it, in a collection of its own whose TTL index removes it.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct UserSession {
+    pub uuid: String,
+    pub token: String,
+    pub timestamp: u64,
+    pub expiration_timestamp: u64,
+    /// Copy of `expiration_timestamp` the TTL index of MongoDB removes the record at.
+    pub expires_at: DateTime,
+}
+
+impl UserSession {
+    pub fn create(uuid: &str, token: &UserToken) -> Self {
+        Self {
+            uuid: uuid.to_string(),
+            token: token.token.clone(),
+            timestamp: token.timestamp,
+            expiration_timestamp: token.expiration_timestamp,
+            expires_at: DateTime::from_millis(token.expiration_timestamp as i64),
+        }
+    }
+
+    pub fn to_token(&self) -> UserToken {
+        UserToken {
+            token: self.token.clone(),
+            timestamp: self.timestamp,
+            expiration_timestamp: self.expiration_timestamp,
+        }
+    }
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UserPreviousUsername {
+    pub username: String,
+    pub timestamp: u64,
+}
+
+/// Single-use token sent by email, only its hash is stored.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UserSecretToken {
+    pub hash: String,
+    pub timestamp: u64,
+    pub expiration_timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl UserSecretToken {
+    /// Returns the token to send alongside the record to store.
+    pub fn create(seconds: u64, email: Option<String>) -> (String, Self) {
+        let token = generate_token(64);
+        let secret = Self {
+            hash: hash_token(&token),
+            timestamp: get_current_timestamp(),
+            expiration_timestamp: get_current_timestamp() + (seconds * 1000),
+            email,
+        };
+        (token, secret)
+    }
+}
+
+/// Account at an external OpenID Connect provider, identified by its `sub` claim.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UserTotp {
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: u64,
+    /// SHA-256 of the unused recovery codes, see [`recovery_code_hashes`].
+    #[serde(deserialize_with = "recovery_code_hashes")]
+    pub recovery_codes: Vec<String>,
+}
+
+impl UserTotp {
+    pub fn has_recovery_code(&self, hash: &str) -> bool {
+        self.recovery_codes.iter().any(|code| code == hash)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRecoveryCode {
+    Hash(String),
+    Argon2(serde::de::IgnoredAny),
+}
+
+/// Codes hashed with argon2 by earlier builds are dropped: a wrong code had to be checked
+/// against each of them. The user makes new ones by enrolling again.
+fn recovery_code_hashes<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let codes: Vec<StoredRecoveryCode> = Deserialize::deserialize(deserializer)?;
+    Ok(codes
+        .into_iter()
+        .filter_map(|code| match code {
+            StoredRecoveryCode::Hash(hash) => Some(hash),
+            StoredRecoveryCode::Argon2(_) => None,
+        })
+        .collect())
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum UserStatus {
+    #[default]
+    Active,
+    /// Lifted automatically once `expiration_timestamp` is reached, if any.
+    Suspended {
+        reason: String,
+        timestamp: u64,
+        expiration_timestamp: Option<u64>,
+    },
+    /// Can still be reactivated until the purge.
+    Deleted {
+        timestamp: u64,
+        purge_timestamp: u64,
+    },
+    /// Personal data removed, only the uuid is kept for what refers to it.
+    Purged { timestamp: u64 },
+}
+
+/// Fields the user edits, shown on their public profile except `locale` and `timezone`.
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct UserProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    /// Name of the uploaded file in the avatar directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+/// Version of the user documents written by this build, see `migration`.
+pub const USER_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
 pub struct User {
+    /// 0 for documents written before migrations existed.
+    #[serde(default)]
+    pub schema_version: u32,
     pub timestamp: u64,
     pub uuid: String,
     pub username: String,
+    /// Former usernames stay reserved to their owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_usernames: Option<Vec<UserPreviousUsername>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub profile: UserProfile,
+    /// Only active accounts can log in and use their sessions.
+    #[serde(default)]
+    pub status: UserStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_login: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logs: Option<Vec<UserLog>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<Password>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identities: Vec<UserIdentity>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tokens: Option<Vec<UserToken>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totp: Option<UserTotp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenges: Option<Vec<UserToken>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_token: Option<UserSecretToken>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_token: Option<UserSecretToken>,
     pub access: UserAccess,
 }
 
@@ -43,6 +220,7 @@ impl User {
             },
             username,
             password: Some(password),
+            schema_version: USER_SCHEMA_VERSION,
             ..Default::default()
         }
     }
@@ -62,6 +240,64 @@ impl User {
         self.tokens = Some(tokens);
         token
     }
+
+    /// Short-lived token proving the password step of a two-factor login.
+    pub fn new_challenge(&mut self, seconds: u64) -> UserToken {
+        let challenge = UserToken {
+            token: generate_token(128),
+            timestamp: get_current_timestamp(),
+            expiration_timestamp: get_current_timestamp() + (seconds * 1000),
+        };
+        let mut challenges = self.challenges.clone().unwrap_or_default();
+        challenges.push(challenge.clone());
+        self.challenges = Some(challenges);
+        challenge
+    }
+
+    /// Ignoring case, as usernames are compared everywhere.
+    pub fn had_username(&self, username: &str) -> bool {
+        let username = username.to_lowercase();
+        self.previous_usernames
+            .iter()
+            .flatten()
+            .any(|previous| previous.username.to_lowercase() == username)
+    }
+
+    pub fn is_active(&self, timestamp: u64) -> bool {
+        match &self.status {
+            UserStatus::Active => true,
+            UserStatus::Suspended {
+                expiration_timestamp: Some(expiration_timestamp),
+                ..
+            } => *expiration_timestamp <= timestamp,
+            _ => false,
+        }
+    }
+
+    /// Tokens of the account that have not expired at `timestamp`.
+    pub fn session_count(&self, timestamp: u64) -> u64 {
+        self.tokens
+            .iter()
+            .flatten()
+            .filter(|token| token.expiration_timestamp >= timestamp)
+            .count() as u64
+    }
+
+    /// What is left of a purged account.
+    pub fn tombstone(&self, timestamp: u64) -> Self {
+        Self {
+            timestamp: self.timestamp,
+            uuid: self.uuid.clone(),
+            username: format!("deleted-{}", self.uuid),
+            status: UserStatus::Purged { timestamp },
+            schema_version: USER_SCHEMA_VERSION,
+            ..Default::default()
+        }
+    }
+
+    pub fn has_totp(&self) -> bool {
+        matches!(&self.totp, Some(totp) if totp.enabled)
+    }
 }
 
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
@@ -69,6 +305,8 @@ pub struct UserAccess {
     pub role: UserRoleType,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub permissions: Option<Vec<UserPermissionType>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
 }
 
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use mongodb::{
+    bson::doc,
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Collection,
+};
+
+use misato_utils::get_current_timestamp;
+
+use crate::database::{expiry_index, lookup_index, unique_index};
+use crate::error::Error;
+use crate::models::oauth_model::*;
+use crate::repository::{DeleteResult, OAuthRepository};
+
+pub struct OAuthManager {
+    pub clients: Collection<OAuthClient>,
+    pub consents: Collection<OAuthConsent>,
+    pub codes: Collection<OAuthCode>,
+    pub tokens: Collection<OAuthToken>,
+}
+
+impl OAuthManager {
+    pub fn init(
+        clients: Collection<OAuthClient>,
+        consents: Collection<OAuthConsent>,
+        codes: Collection<OAuthCode>,
+        tokens: Collection<OAuthToken>,
+    ) -> Self {
+        Self {
+            clients,
+            consents,
+            codes,
+            tokens,
+        }
+    }
+
+    /// Codes and tokens are removed by MongoDB once expired, lookups still check the expiry.
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        self.clients
+            .create_indexes(
+                [
+                    unique_index(doc! {"client_id": 1}),
+                    lookup_index(doc! {"owner": 1}),
+                ],
+                None,
+            )
+            .await?;
+        self.consents
+            .create_index(unique_index(doc! {"uuid": 1, "client_id": 1}), None)
+            .await?;
+        self.codes
+            .create_indexes(
+                [
+                    unique_index(doc! {"hash": 1}),
+                    lookup_index(doc! {"client_id": 1}),
+                    expiry_index(),
+                ],
+                None,
+            )
+            .await?;
+        self.tokens
+            .create_indexes(
+                [
+                    unique_index(doc! {"hash": 1}),
+                    lookup_index(doc! {"uuid": 1, "client_id": 1}),
+                    lookup_index(doc! {"client_id": 1}),
+                    expiry_index(),
+                ],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OAuthRepository for OAuthManager {
+    async fn create_client(&self, client: &OAuthClient) -> Result<(), Error> {
+        self.clients.insert_one(client, None).await?;
+        Ok(())
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, Error> {
+        self.clients
+            .find_one(doc! {"client_id": client_id}, None)
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn get_clients(&self, owner: &str) -> Result<Vec<OAuthClient>, Error> {
+        let mut cursor = self.clients.find(doc! {"owner": owner}, None).await?;
+        let mut clients = Vec::new();
+        while cursor.advance().await? {
+            clients.push(cursor.deserialize_current()?);
+        }
+        Ok(clients)
+    }
+
+    async fn delete_client(&self, owner: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let result = self
+            .clients
+            .delete_one(doc! {"owner": owner, "client_id": client_id}, None)
+            .await?;
+        if result.deleted_count > 0 {
+            let filter = doc! {"client_id": client_id};
+            self.consents.delete_many(filter.clone(), None).await?;
+            self.codes.delete_many(filter.clone(), None).await?;
+            self.tokens.delete_many(filter, None).await?;
+        }
+        Ok(result.into())
+    }
+
+    async fn get_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+    ) -> Result<Option<OAuthConsent>, Error> {
+        self.consents
+            .find_one(doc! {"uuid": uuid, "client_id": client_id}, None)
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn get_consents(&self, uuid: &str) -> Result<Vec<OAuthConsent>, Error> {
+        let mut cursor = self.consents.find(doc! {"uuid": uuid}, None).await?;
+        let mut consents = Vec::new();
+        while cursor.advance().await? {
+            consents.push(cursor.deserialize_current()?);
+        }
+        Ok(consents)
+    }
+
+    async fn add_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<Option<OAuthConsent>, Error> {
+        self.consents
+            .find_one_and_update(
+                doc! {"uuid": uuid, "client_id": client_id},
+                doc! {
+                    "$addToSet": {"scopes": {"$each": scopes}},
+                    "$set": {"timestamp": get_current_timestamp() as i64},
+                },
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn revoke_consent(&self, uuid: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let filter = doc! {"uuid": uuid, "client_id": client_id};
+        self.codes.delete_many(filter.clone(), None).await?;
+        self.tokens.delete_many(filter.clone(), None).await?;
+        self.consents
+            .delete_one(filter, None)
+            .await
+            .map(DeleteResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn save_code(&self, code: &OAuthCode) -> Result<(), Error> {
+        self.codes.insert_one(code, None).await?;
+        Ok(())
+    }
+
+    async fn consume_code(&self, hash: &str) -> Result<Option<OAuthCode>, Error> {
+        self.codes
+            .find_one_and_delete(
+                doc! {"hash": hash, "expiration_timestamp": {"$gte": get_current_timestamp() as i64}},
+                None,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn save_token(&self, token: &OAuthToken) -> Result<(), Error> {
+        self.tokens.insert_one(token, None).await?;
+        Ok(())
+    }
+
+    async fn get_token(&self, hash: &str) -> Result<Option<OAuthToken>, Error> {
+        self.tokens
+            .find_one(
+                doc! {"hash": hash, "expiration_timestamp": {"$gte": get_current_timestamp() as i64}},
+                None,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn revoke_token(&self, hash: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        self.tokens
+            .delete_one(doc! {"hash": hash, "client_id": client_id}, None)
+            .await
+            .map(DeleteResult::from)
+            .map_err(Error::from)
+    }
+}
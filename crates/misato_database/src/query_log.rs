@@ -0,0 +1,57 @@
+//! Logs the queries of every backend, the slow ones as warnings.
+//!
+//! Both drivers report from the future that issued the query, so the events land in the span
+//! of the request that made it and slow queries can be traced back to it.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::LevelFilter;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandSucceededEvent};
+use mongodb::options::ClientOptions;
+use mongodb::Client;
+use sqlx::any::AnyConnectOptions;
+use sqlx::ConnectOptions;
+
+use crate::error::Error;
+
+pub struct CommandLog {
+    slow: Duration,
+}
+
+impl CommandEventHandler for CommandLog {
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        let duration_ms = event.duration.as_millis() as u64;
+        if event.duration >= self.slow {
+            tracing::warn!(command = %event.command_name, duration_ms, "Slow query");
+        } else {
+            tracing::debug!(command = %event.command_name, duration_ms, "Query");
+        }
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        tracing::warn!(
+            command = %event.command_name,
+            duration_ms = event.duration.as_millis() as u64,
+            error = %event.failure,
+            "Query failed"
+        );
+    }
+}
+
+/// Client for `uri`, queries slower than `slow` are logged as warnings.
+pub async fn mongodb_client(uri: &str, slow: Duration) -> Result<Client, Error> {
+    let mut options = ClientOptions::parse(uri).await?;
+    options.command_event_handler = Some(Arc::new(CommandLog { slow }));
+    Ok(Client::with_options(options)?)
+}
+
+/// Connection options for `url`, statements slower than `slow` are logged as warnings.
+pub fn sql_options(url: &str, slow: Duration) -> Result<AnyConnectOptions, Error> {
+    let mut options = AnyConnectOptions::from_str(url)?;
+    options
+        .log_statements(LevelFilter::Debug)
+        .log_slow_statements(LevelFilter::Warn, slow);
+    Ok(options)
+}
@@ -0,0 +1,485 @@
+//! Operations of every store, whatever the backend keeps the documents in.
+//!
+//! Each store has a MongoDB implementation next to it (`*_manager.rs`), a SQL one in `sql`
+//! and an in-memory one in `memory`, new stores such as the wiki content are added the same way.
+
+use async_trait::async_trait;
+
+use misato_security::password::Password;
+
+use crate::error::Error;
+use crate::models::{
+    apiuser_model::*, export_model::*, invite_model::*, loginattempt_model::*, oauth_model::*,
+    user_model::*,
+};
+use crate::user_manager::{UserSearch, UserSort};
+
+#[derive(Eq, PartialEq, Debug, Default, Clone, Copy)]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+#[derive(Eq, PartialEq, Debug, Default, Clone, Copy)]
+pub struct DeleteResult {
+    pub deleted_count: u64,
+}
+
+impl From<mongodb::results::UpdateResult> for UpdateResult {
+    fn from(result: mongodb::results::UpdateResult) -> Self {
+        Self {
+            matched_count: result.matched_count,
+            modified_count: result.modified_count,
+        }
+    }
+}
+
+impl From<mongodb::results::DeleteResult> for DeleteResult {
+    fn from(result: mongodb::results::DeleteResult) -> Self {
+        Self {
+            deleted_count: result.deleted_count,
+        }
+    }
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Case-insensitive, and also true for former usernames,
+    /// so nobody can impersonate another user.
+    async fn username_exists(&self, username: &str) -> Result<bool, Error>;
+
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error>;
+
+    /// Fails with `Error::DuplicateKey` if the uuid or the username is already used.
+    async fn create_user(&self, user: &User) -> Result<(), Error>;
+
+    async fn get_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<User>, Error>;
+
+    /// Marks the account as deleted and closes its sessions, it is purged after the grace period.
+    async fn delete_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<UpdateResult>, Error>;
+
+    async fn delete_user_from_token(&self, token: &str) -> Result<Option<UpdateResult>, Error>;
+
+    /// Removes the document at once, only to undo a creation that could not be completed.
+    async fn remove_user(&self, uuid: &str) -> Result<DeleteResult, Error>;
+
+    /// Suspends, or reactivates with `UserStatus::Active`, any account not purged yet.
+    async fn set_status(&self, uuid: &str, status: &UserStatus) -> Result<UpdateResult, Error>;
+
+    /// Replaces the accounts deleted before their grace period by a tombstone and returns
+    /// them as they were, for their files to be removed.
+    /// Content outside this store only refers to the uuid, which is kept.
+    async fn purge_deleted_users(&self) -> Result<Vec<User>, Error>;
+
+    /// Tokens that have not expired yet, over every account.
+    async fn count_sessions(&self) -> Result<u64, Error>;
+
+    /// Returns a page of the matching users and how many match in total.
+    async fn search_users(
+        &self,
+        search: &UserSearch,
+        sort: &UserSort,
+        descending: bool,
+        skip: u64,
+        limit: i64,
+    ) -> Result<(Vec<User>, u64), Error>;
+
+    async fn set_last_login(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error>;
+
+    async fn set_role(&self, uuid: &str, role: &UserRoleType) -> Result<UpdateResult, Error>;
+
+    async fn save_token(&self, uuid: &str, token: &UserToken) -> Result<UpdateResult, Error>;
+
+    async fn add_log(&self, uuid: &str, log: &UserLog) -> Result<UpdateResult, Error>;
+
+    async fn set_totp(&self, uuid: &str, totp: &UserTotp) -> Result<UpdateResult, Error>;
+
+    async fn clear_totp(&self, uuid: &str) -> Result<UpdateResult, Error>;
+
+    /// Atomically records the last accepted step, fails to match if the code was already used.
+    async fn use_totp_step(&self, uuid: &str, step: u64) -> Result<UpdateResult, Error>;
+
+    /// Fails to match unless `hash` is one of the unused recovery codes.
+    async fn remove_recovery_code(&self, uuid: &str, hash: &str) -> Result<UpdateResult, Error>;
+
+    /// Also drops the expired challenges of the user.
+    async fn save_challenge(
+        &self,
+        uuid: &str,
+        challenge: &UserToken,
+    ) -> Result<UpdateResult, Error>;
+
+    async fn remove_challenge(&self, uuid: &str, token: &str) -> Result<UpdateResult, Error>;
+
+    async fn get_user_from_challenge(&self, token: &str) -> Result<Option<User>, Error>;
+
+    /// The account that verified `email`, an address is verified by a single account.
+    async fn get_user_from_email(&self, email: &str) -> Result<Option<User>, Error>;
+
+    /// Revokes every session, a new password must not leave old tokens alive.
+    async fn set_password(&self, uuid: &str, password: &Password) -> Result<UpdateResult, Error>;
+
+    /// Sets a new password and revokes every session except `keep_token`.
+    async fn change_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+        keep_token: &str,
+    ) -> Result<UpdateResult, Error>;
+
+    /// Renames the user if its username is still `previous`, keeping the old one in history.
+    /// Fails with `Error::DuplicateKey` if another user took `username` meanwhile.
+    async fn change_username(
+        &self,
+        uuid: &str,
+        previous: &str,
+        username: &str,
+    ) -> Result<UpdateResult, Error>;
+
+    /// Replaces the hash of an unchanged password, sessions stay valid.
+    async fn rehash_password(&self, uuid: &str, password: &Password)
+        -> Result<UpdateResult, Error>;
+
+    /// Leaves the avatar alone, it is only changed by uploads.
+    async fn set_profile(&self, uuid: &str, profile: &UserProfile) -> Result<UpdateResult, Error>;
+
+    async fn set_avatar(&self, uuid: &str, avatar: Option<&str>) -> Result<UpdateResult, Error>;
+
+    async fn get_user_from_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Error>;
+
+    /// Links the identity unless this user already has it.
+    async fn add_identity(
+        &self,
+        uuid: &str,
+        identity: &UserIdentity,
+    ) -> Result<UpdateResult, Error>;
+
+    async fn remove_identity(
+        &self,
+        uuid: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UpdateResult, Error>;
+
+    async fn set_reset_token(
+        &self,
+        uuid: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error>;
+
+    async fn get_user_from_reset_token(&self, hash: &str) -> Result<Option<User>, Error>;
+
+    /// Removes the reset token matching `hash` and returns its owner, at most once.
+    async fn consume_reset_token(&self, hash: &str) -> Result<Option<User>, Error>;
+
+    /// Stores the new address as unverified until the token is consumed.
+    async fn set_email(
+        &self,
+        uuid: &str,
+        email: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error>;
+
+    /// Verifies the address the token was sent to, if it is still the one of the user.
+    async fn consume_verification_token(&self, hash: &str) -> Result<Option<User>, Error>;
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error>;
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error>;
+
+    async fn get_user_from_token(&self, token: &str) -> Result<Option<User>, Error>;
+}
+
+#[async_trait]
+pub trait ApiUserRepository: Send + Sync {
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error>;
+
+    /// Replaces the API account of the same uuid, if any.
+    async fn create_apiuser(&self, apiuser: &ApiUser) -> Result<UpdateResult, Error>;
+
+    async fn get_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<ApiUser>, Error>;
+
+    async fn delete_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<DeleteResult>, Error>;
+
+    async fn delete_apiuser_from_token(&self, token: &str) -> Result<Option<DeleteResult>, Error>;
+
+    async fn set_token(&self, uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error>;
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error>;
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error>;
+
+    async fn get_apiuser_from_token(&self, token: &str) -> Result<Option<ApiUser>, Error>;
+}
+
+#[async_trait]
+pub trait LoginAttemptRepository: Send + Sync {
+    /// Returns the timestamp until which the key is locked, if it currently is.
+    async fn get_lock(&self, key: &str) -> Result<Option<u64>, Error>;
+
+    async fn register_failure(&self, key: &str) -> Result<LoginAttempt, Error>;
+
+    async fn clear_failures(&self, key: &str) -> Result<DeleteResult, Error>;
+
+    /// Keys starting with `prefix` that are currently locked, without the prefix.
+    async fn get_locked_keys(&self, prefix: &str) -> Result<Vec<String>, Error>;
+}
+
+#[async_trait]
+pub trait InviteRepository: Send + Sync {
+    async fn create_invite(&self, invite: &Invite) -> Result<(), Error>;
+
+    async fn get_invites(&self) -> Result<Vec<Invite>, Error>;
+
+    async fn delete_invite(&self, id: &str) -> Result<DeleteResult, Error>;
+
+    /// Counts a use for `uuid` in a single update, so concurrent signups
+    /// can never redeem a code more than `max_uses` times.
+    async fn redeem(&self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error>;
+
+    /// Gives the use back when the account could not be created.
+    async fn release(&self, id: &str, uuid: &str) -> Result<UpdateResult, Error>;
+}
+
+#[async_trait]
+pub trait ExportRepository: Send + Sync {
+    /// Fails with `DuplicateKey` while another export of the user is pending.
+    async fn create_export(&self, export: &Export) -> Result<(), Error>;
+
+    async fn get_latest_export(&self, uuid: &str) -> Result<Option<Export>, Error>;
+
+    async fn set_status(
+        &self,
+        id: &str,
+        status: &ExportStatus,
+        size: Option<u64>,
+    ) -> Result<UpdateResult, Error>;
+
+    /// Marks the pending export of the user as failed if created before `timestamp`, its
+    /// preparation was interrupted.
+    async fn fail_stale_export(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error>;
+
+    /// Marks a ready export as downloaded and returns it, at most once.
+    async fn consume_export(&self, id: &str) -> Result<Option<Export>, Error>;
+
+    /// Removes the expired exports and returns them, their archives are left to the caller.
+    async fn remove_expired(&self) -> Result<Vec<Export>, Error>;
+}
+
+#[async_trait]
+pub trait OAuthRepository: Send + Sync {
+    async fn create_client(&self, client: &OAuthClient) -> Result<(), Error>;
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, Error>;
+
+    async fn get_clients(&self, owner: &str) -> Result<Vec<OAuthClient>, Error>;
+
+    /// Also removes everything the client was granted.
+    async fn delete_client(&self, owner: &str, client_id: &str) -> Result<DeleteResult, Error>;
+
+    async fn get_consent(&self, uuid: &str, client_id: &str)
+        -> Result<Option<OAuthConsent>, Error>;
+
+    async fn get_consents(&self, uuid: &str) -> Result<Vec<OAuthConsent>, Error>;
+
+    /// Adds the scopes to the consent of the user, creating it if needed.
+    async fn add_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<Option<OAuthConsent>, Error>;
+
+    /// Withdraws the consent and revokes the tokens it allowed.
+    async fn revoke_consent(&self, uuid: &str, client_id: &str) -> Result<DeleteResult, Error>;
+
+    async fn save_code(&self, code: &OAuthCode) -> Result<(), Error>;
+
+    /// Removes the code and returns it, at most once.
+    async fn consume_code(&self, hash: &str) -> Result<Option<OAuthCode>, Error>;
+
+    async fn save_token(&self, token: &OAuthToken) -> Result<(), Error>;
+
+    /// Only tokens that are still valid.
+    async fn get_token(&self, hash: &str) -> Result<Option<OAuthToken>, Error>;
+
+    /// A client can only revoke its own tokens.
+    async fn revoke_token(&self, hash: &str, client_id: &str) -> Result<DeleteResult, Error>;
+}
+
+/// Calls `$callback!` with the signature of every method of each repository trait, for the
+/// wrappers that forward them all.
+macro_rules! for_each_repository {
+    ($callback:ident) => {
+        $callback! {
+            UserRepository as "users" {
+                fn username_exists(username: &str) -> Result<bool, Error>;
+                fn uuid_exists(uuid: &str) -> Result<bool, Error>;
+                fn create_user(user: &User) -> Result<(), Error>;
+                fn get_user(
+                    username: Option<&str>,
+                    uuid: Option<&str>,
+                ) -> Result<Option<User>, Error>;
+                fn delete_user(
+                    username: Option<&str>,
+                    uuid: Option<&str>,
+                ) -> Result<Option<UpdateResult>, Error>;
+                fn delete_user_from_token(token: &str) -> Result<Option<UpdateResult>, Error>;
+                fn remove_user(uuid: &str) -> Result<DeleteResult, Error>;
+                fn set_status(uuid: &str, status: &UserStatus) -> Result<UpdateResult, Error>;
+                fn purge_deleted_users() -> Result<Vec<User>, Error>;
+                fn count_sessions() -> Result<u64, Error>;
+                fn search_users(
+                    search: &UserSearch,
+                    sort: &UserSort,
+                    descending: bool,
+                    skip: u64,
+                    limit: i64,
+                ) -> Result<(Vec<User>, u64), Error>;
+                fn set_last_login(uuid: &str, timestamp: u64) -> Result<UpdateResult, Error>;
+                fn set_role(uuid: &str, role: &UserRoleType) -> Result<UpdateResult, Error>;
+                fn save_token(uuid: &str, token: &UserToken) -> Result<UpdateResult, Error>;
+                fn add_log(uuid: &str, log: &UserLog) -> Result<UpdateResult, Error>;
+                fn set_totp(uuid: &str, totp: &UserTotp) -> Result<UpdateResult, Error>;
+                fn clear_totp(uuid: &str) -> Result<UpdateResult, Error>;
+                fn use_totp_step(uuid: &str, step: u64) -> Result<UpdateResult, Error>;
+                fn remove_recovery_code(uuid: &str, hash: &str) -> Result<UpdateResult, Error>;
+                fn save_challenge(uuid: &str, challenge: &UserToken) -> Result<UpdateResult, Error>;
+                fn remove_challenge(uuid: &str, token: &str) -> Result<UpdateResult, Error>;
+                fn get_user_from_challenge(token: &str) -> Result<Option<User>, Error>;
+                fn get_user_from_email(email: &str) -> Result<Option<User>, Error>;
+                fn set_password(uuid: &str, password: &Password) -> Result<UpdateResult, Error>;
+                fn change_password(
+                    uuid: &str,
+                    password: &Password,
+                    keep_token: &str,
+                ) -> Result<UpdateResult, Error>;
+                fn change_username(
+                    uuid: &str,
+                    previous: &str,
+                    username: &str,
+                ) -> Result<UpdateResult, Error>;
+                fn rehash_password(uuid: &str, password: &Password) -> Result<UpdateResult, Error>;
+                fn set_profile(uuid: &str, profile: &UserProfile) -> Result<UpdateResult, Error>;
+                fn set_avatar(uuid: &str, avatar: Option<&str>) -> Result<UpdateResult, Error>;
+                fn get_user_from_identity(
+                    provider: &str,
+                    subject: &str,
+                ) -> Result<Option<User>, Error>;
+                fn add_identity(uuid: &str, identity: &UserIdentity) -> Result<UpdateResult, Error>;
+                fn remove_identity(
+                    uuid: &str,
+                    provider: &str,
+                    subject: &str,
+                ) -> Result<UpdateResult, Error>;
+                fn set_reset_token(
+                    uuid: &str,
+                    token: &UserSecretToken,
+                ) -> Result<UpdateResult, Error>;
+                fn get_user_from_reset_token(hash: &str) -> Result<Option<User>, Error>;
+                fn consume_reset_token(hash: &str) -> Result<Option<User>, Error>;
+                fn set_email(
+                    uuid: &str,
+                    email: &str,
+                    token: &UserSecretToken,
+                ) -> Result<UpdateResult, Error>;
+                fn consume_verification_token(hash: &str) -> Result<Option<User>, Error>;
+                fn clear_tokens(uuid: &str) -> Result<UpdateResult, Error>;
+                fn clear_tokens_from_token(token: &str) -> Result<UpdateResult, Error>;
+                fn get_user_from_token(token: &str) -> Result<Option<User>, Error>;
+            }
+        }
+        $callback! {
+            ApiUserRepository as "apiusers" {
+                fn uuid_exists(uuid: &str) -> Result<bool, Error>;
+                fn create_apiuser(apiuser: &ApiUser) -> Result<UpdateResult, Error>;
+                fn get_apiuser(
+                    username: Option<&str>,
+                    uuid: Option<&str>,
+                ) -> Result<Option<ApiUser>, Error>;
+                fn delete_apiuser(
+                    username: Option<&str>,
+                    uuid: Option<&str>,
+                ) -> Result<Option<DeleteResult>, Error>;
+                fn delete_apiuser_from_token(token: &str) -> Result<Option<DeleteResult>, Error>;
+                fn set_token(uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error>;
+                fn clear_tokens(uuid: &str) -> Result<UpdateResult, Error>;
+                fn clear_tokens_from_token(token: &str) -> Result<UpdateResult, Error>;
+                fn get_apiuser_from_token(token: &str) -> Result<Option<ApiUser>, Error>;
+            }
+        }
+        $callback! {
+            LoginAttemptRepository as "loginattempts" {
+                fn get_lock(key: &str) -> Result<Option<u64>, Error>;
+                fn register_failure(key: &str) -> Result<LoginAttempt, Error>;
+                fn clear_failures(key: &str) -> Result<DeleteResult, Error>;
+                fn get_locked_keys(prefix: &str) -> Result<Vec<String>, Error>;
+            }
+        }
+        $callback! {
+            InviteRepository as "invites" {
+                fn create_invite(invite: &Invite) -> Result<(), Error>;
+                fn get_invites() -> Result<Vec<Invite>, Error>;
+                fn delete_invite(id: &str) -> Result<DeleteResult, Error>;
+                fn redeem(hash: &str, uuid: &str) -> Result<Option<Invite>, Error>;
+                fn release(id: &str, uuid: &str) -> Result<UpdateResult, Error>;
+            }
+        }
+        $callback! {
+            ExportRepository as "exports" {
+                fn create_export(export: &Export) -> Result<(), Error>;
+                fn get_latest_export(uuid: &str) -> Result<Option<Export>, Error>;
+                fn set_status(
+                    id: &str,
+                    status: &ExportStatus,
+                    size: Option<u64>,
+                ) -> Result<UpdateResult, Error>;
+                fn fail_stale_export(uuid: &str, timestamp: u64) -> Result<UpdateResult, Error>;
+                fn consume_export(id: &str) -> Result<Option<Export>, Error>;
+                fn remove_expired() -> Result<Vec<Export>, Error>;
+            }
+        }
+        $callback! {
+            OAuthRepository as "oauth" {
+                fn create_client(client: &OAuthClient) -> Result<(), Error>;
+                fn get_client(client_id: &str) -> Result<Option<OAuthClient>, Error>;
+                fn get_clients(owner: &str) -> Result<Vec<OAuthClient>, Error>;
+                fn delete_client(owner: &str, client_id: &str) -> Result<DeleteResult, Error>;
+                fn get_consent(uuid: &str, client_id: &str) -> Result<Option<OAuthConsent>, Error>;
+                fn get_consents(uuid: &str) -> Result<Vec<OAuthConsent>, Error>;
+                fn add_consent(
+                    uuid: &str,
+                    client_id: &str,
+                    scopes: &[String],
+                ) -> Result<Option<OAuthConsent>, Error>;
+                fn revoke_consent(uuid: &str, client_id: &str) -> Result<DeleteResult, Error>;
+                fn save_code(code: &OAuthCode) -> Result<(), Error>;
+                fn consume_code(hash: &str) -> Result<Option<OAuthCode>, Error>;
+                fn save_token(token: &OAuthToken) -> Result<(), Error>;
+                fn get_token(hash: &str) -> Result<Option<OAuthToken>, Error>;
+                fn revoke_token(hash: &str, client_id: &str) -> Result<DeleteResult, Error>;
+            }
+        }
+    };
+}
+pub(crate) use for_each_repository;
@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::apiuser_model::*;
+use crate::repository::{ApiUserRepository, DeleteResult, UpdateResult};
+use crate::sql::{update_result, Collection, Document, Lookup};
+
+fn has_token(apiuser: &ApiUser, token: &str, timestamp: u64) -> bool {
+    matches!(&apiuser.token, Some(candidate) if candidate.token == token && candidate.expiration_timestamp >= timestamp)
+}
+
+impl Document for ApiUser {
+    fn id(&self) -> String {
+        self.uuid.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        self.token
+            .iter()
+            .map(|token| ("token", token.token.clone()))
+            .collect()
+    }
+}
+
+pub struct SqlApiUserManager {
+    apiusers: Collection<ApiUser>,
+}
+
+impl SqlApiUserManager {
+    pub fn init(pool: &AnyPool) -> Self {
+        Self {
+            apiusers: Collection::new(pool, "apiusers"),
+        }
+    }
+
+    async fn take(
+        &self,
+        lookup: &Lookup<'_>,
+        filter: impl Fn(&ApiUser) -> bool,
+    ) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self.apiusers.take(lookup, filter).await?.is_some() as u64,
+        })
+    }
+}
+
+#[async_trait]
+impl ApiUserRepository for SqlApiUserManager {
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+        Ok(!self.apiusers.find(&Lookup::Id(uuid)).await?.is_empty())
+    }
+
+    async fn create_apiuser(&self, apiuser: &ApiUser) -> Result<UpdateResult, Error> {
+        let (previous, current) = self
+            .apiusers
+            .upsert(
+                &apiuser.uuid,
+                || apiuser.clone(),
+                |existing| *existing = apiuser.clone(),
+            )
+            .await?;
+        Ok(match previous {
+            Some(previous) => update_result(&Some((previous, current))),
+            None => UpdateResult::default(),
+        })
+    }
+
+    /// API accounts have no username, looking one up by it finds nothing.
+    async fn get_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<ApiUser>, Error> {
+        match (username, uuid) {
+            (None, Some(uuid)) => self.apiusers.find_one(&Lookup::Id(uuid), |_| true).await,
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_apiuser(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<DeleteResult>, Error> {
+        match (username, uuid) {
+            (None, None) => Ok(None),
+            (None, Some(uuid)) => Ok(Some(self.take(&Lookup::Id(uuid), |_| true).await?)),
+            (Some(_), _) => Ok(Some(DeleteResult::default())),
+        }
+    }
+
+    async fn delete_apiuser_from_token(&self, token: &str) -> Result<Option<DeleteResult>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(Some(
+            self.take(&Lookup::Key("token", token), |apiuser| {
+                has_token(apiuser, token, timestamp)
+            })
+            .await?,
+        ))
+    }
+
+    async fn set_token(&self, uuid: &str, token: &ApiUserToken) -> Result<UpdateResult, Error> {
+        let updated = self
+            .apiusers
+            .update(
+                &Lookup::Id(uuid),
+                |_| true,
+                |apiuser| apiuser.token = Some(token.clone()),
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        let updated = self
+            .apiusers
+            .update(&Lookup::Id(uuid), |_| true, |apiuser| apiuser.token = None)
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .apiusers
+            .update(
+                &Lookup::Key("token", token),
+                |apiuser| has_token(apiuser, token, timestamp),
+                |apiuser| apiuser.token = None,
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn get_apiuser_from_token(&self, token: &str) -> Result<Option<ApiUser>, Error> {
+        let timestamp = get_current_timestamp();
+        self.apiusers
+            .find_one(&Lookup::Key("token", token), |apiuser| {
+                has_token(apiuser, token, timestamp)
+            })
+            .await
+    }
+}
@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::export_model::*;
+use crate::repository::{ExportRepository, UpdateResult};
+use crate::sql::{update_result, Collection, Document, Lookup};
+
+impl Document for Export {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        let mut keys = vec![("uuid", self.uuid.clone())];
+        if self.status == ExportStatus::Pending {
+            keys.push(("pending", self.uuid.clone()));
+        }
+        keys
+    }
+}
+
+pub struct SqlExportManager {
+    exports: Collection<Export>,
+}
+
+impl SqlExportManager {
+    pub fn init(pool: &AnyPool) -> Self {
+        Self {
+            exports: Collection::new(pool, "exports"),
+        }
+    }
+}
+
+#[async_trait]
+impl ExportRepository for SqlExportManager {
+    async fn create_export(&self, export: &Export) -> Result<(), Error> {
+        self.exports.insert(export).await?;
+        Ok(())
+    }
+
+    async fn get_latest_export(&self, uuid: &str) -> Result<Option<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .exports
+            .find(&Lookup::Key("uuid", uuid))
+            .await?
+            .into_iter()
+            .filter(|export| export.expiration_timestamp > timestamp)
+            .max_by_key(|export| export.timestamp))
+    }
+
+    async fn set_status(
+        &self,
+        id: &str,
+        status: &ExportStatus,
+        size: Option<u64>,
+    ) -> Result<UpdateResult, Error> {
+        let updated = self
+            .exports
+            .update(
+                &Lookup::Id(id),
+                |_| true,
+                |export| {
+                    export.status = status.clone();
+                    if size.is_some() {
+                        export.size = size;
+                    }
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn fail_stale_export(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        let updated = self
+            .exports
+            .update(
+                &Lookup::Key("pending", uuid),
+                |export| export.timestamp < timestamp,
+                |export| export.status = ExportStatus::Failed,
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn consume_export(&self, id: &str) -> Result<Option<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .exports
+            .update(
+                &Lookup::Id(id),
+                |export| {
+                    export.status == ExportStatus::Ready && export.expiration_timestamp > timestamp
+                },
+                |export| export.status = ExportStatus::Downloaded,
+            )
+            .await?;
+        Ok(updated.map(|(previous, _)| previous))
+    }
+
+    async fn remove_expired(&self) -> Result<Vec<Export>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut expired = Vec::new();
+        for export in self.exports.find(&Lookup::All).await? {
+            if export.expiration_timestamp > timestamp {
+                continue;
+            }
+            if let Some(export) = self.exports.take(&Lookup::Id(&export.id), |_| true).await? {
+                expired.push(export);
+            }
+        }
+        Ok(expired)
+    }
+}
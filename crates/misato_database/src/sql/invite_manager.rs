@@ -0,0 +1,104 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::invite_model::*;
+use crate::repository::{DeleteResult, InviteRepository, UpdateResult};
+use crate::sql::{update_result, Collection, Document, Lookup};
+
+impl Document for Invite {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        vec![("hash", self.hash.clone())]
+    }
+}
+
+pub(crate) fn redeemable(invite: &Invite, timestamp: u64) -> bool {
+    invite.uses < invite.max_uses
+        && (invite.expiration_timestamp == 0 || invite.expiration_timestamp > timestamp)
+}
+
+pub(crate) fn add_redemption(invite: &mut Invite, uuid: &str, timestamp: u64) {
+    invite.uses += 1;
+    invite.redemptions.push(InviteRedemption {
+        uuid: uuid.to_string(),
+        timestamp,
+    });
+}
+
+pub struct SqlInviteManager {
+    invites: Collection<Invite>,
+}
+
+impl SqlInviteManager {
+    pub fn init(pool: &AnyPool) -> Self {
+        Self {
+            invites: Collection::new(pool, "invites"),
+        }
+    }
+}
+
+#[async_trait]
+impl InviteRepository for SqlInviteManager {
+    async fn create_invite(&self, invite: &Invite) -> Result<(), Error> {
+        self.invites.insert(invite).await?;
+        Ok(())
+    }
+
+    /// Oldest first, as they were created.
+    async fn get_invites(&self) -> Result<Vec<Invite>, Error> {
+        let mut invites = self.invites.find(&Lookup::All).await?;
+        invites.sort_by_key(|invite| invite.timestamp);
+        Ok(invites)
+    }
+
+    async fn delete_invite(&self, id: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self
+                .invites
+                .take(&Lookup::Id(id), |_| true)
+                .await?
+                .is_some() as u64,
+        })
+    }
+
+    async fn redeem(&self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .invites
+            .update(
+                &Lookup::Key("hash", hash),
+                |invite| redeemable(invite, timestamp),
+                |invite| add_redemption(invite, uuid, timestamp),
+            )
+            .await?;
+        Ok(updated.map(|(_, current)| current))
+    }
+
+    async fn release(&self, id: &str, uuid: &str) -> Result<UpdateResult, Error> {
+        let updated = self
+            .invites
+            .update(
+                &Lookup::Id(id),
+                |invite| {
+                    invite
+                        .redemptions
+                        .iter()
+                        .any(|redemption| redemption.uuid == uuid)
+                },
+                |invite| {
+                    invite.uses = invite.uses.saturating_sub(1);
+                    invite
+                        .redemptions
+                        .retain(|redemption| redemption.uuid != uuid);
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+}
@@ -0,0 +1,104 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::error::Error;
+use crate::models::loginattempt_model::*;
+use crate::repository::{DeleteResult, LoginAttemptRepository};
+use crate::sql::{Collection, Document, Lookup};
+
+impl Document for LoginAttempt {
+    fn id(&self) -> String {
+        self.key.clone()
+    }
+}
+
+pub struct SqlLoginAttemptManager {
+    attempts: Collection<LoginAttempt>,
+    max_attempts: u32,
+    lockout_duration: u64,
+    lockout_max_duration: u64,
+}
+
+impl SqlLoginAttemptManager {
+    pub fn init(pool: &AnyPool, settings: &Settings) -> Self {
+        Self {
+            attempts: Collection::new(pool, "loginattempts"),
+            max_attempts: settings.login_max_attempts,
+            lockout_duration: settings.login_lockout_duration,
+            lockout_max_duration: settings.login_lockout_max_duration,
+        }
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for SqlLoginAttemptManager {
+    async fn get_lock(&self, key: &str) -> Result<Option<u64>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .attempts
+            .find_one(&Lookup::Id(key), |attempt| attempt.is_locked(timestamp))
+            .await?
+            .map(|attempt| attempt.locked_until))
+    }
+
+    async fn register_failure(&self, key: &str) -> Result<LoginAttempt, Error> {
+        let timestamp = get_current_timestamp();
+        let expired = timestamp.saturating_sub(self.lockout_max_duration * 1000);
+        let (_, attempt) = self
+            .attempts
+            .upsert(
+                key,
+                || LoginAttempt {
+                    key: key.to_string(),
+                    ..Default::default()
+                },
+                |attempt| {
+                    // Forget failures older than the longest lockout, unless still locked.
+                    if attempt.last_failure < expired && attempt.locked_until < timestamp {
+                        *attempt = LoginAttempt {
+                            key: key.to_string(),
+                            ..Default::default()
+                        };
+                    }
+                    attempt.failures += 1;
+                    attempt.last_failure = timestamp;
+                    let duration = LoginAttempt::lockout_duration(
+                        attempt.failures,
+                        self.max_attempts,
+                        self.lockout_duration,
+                        self.lockout_max_duration,
+                    );
+                    if duration > 0 {
+                        attempt.locked_until =
+                            attempt.locked_until.max(timestamp + duration * 1000);
+                    }
+                },
+            )
+            .await?;
+        Ok(attempt)
+    }
+
+    async fn clear_failures(&self, key: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self
+                .attempts
+                .take(&Lookup::Id(key), |_| true)
+                .await?
+                .is_some() as u64,
+        })
+    }
+
+    async fn get_locked_keys(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .attempts
+            .find(&Lookup::All)
+            .await?
+            .into_iter()
+            .filter(|attempt| attempt.is_locked(timestamp))
+            .filter_map(|attempt| attempt.key.strip_prefix(prefix).map(str::to_string))
+            .collect())
+    }
+}
@@ -0,0 +1,137 @@
+-- Every store keeps its documents as JSON, next to the values they are looked up by.
+-- Only portable SQL here, the same script runs on SQLite and PostgreSQL.
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE users_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX users_keys_lookup ON users_keys (kind, lookup);
+CREATE INDEX users_keys_id ON users_keys (id);
+
+CREATE TABLE apiusers (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE apiusers_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX apiusers_keys_lookup ON apiusers_keys (kind, lookup);
+CREATE INDEX apiusers_keys_id ON apiusers_keys (id);
+
+CREATE TABLE loginattempts (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE loginattempts_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX loginattempts_keys_lookup ON loginattempts_keys (kind, lookup);
+CREATE INDEX loginattempts_keys_id ON loginattempts_keys (id);
+
+CREATE TABLE invites (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE invites_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX invites_keys_lookup ON invites_keys (kind, lookup);
+CREATE INDEX invites_keys_id ON invites_keys (id);
+
+CREATE TABLE exports (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE exports_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX exports_keys_lookup ON exports_keys (kind, lookup);
+CREATE INDEX exports_keys_id ON exports_keys (id);
+
+CREATE TABLE oauthclients (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE oauthclients_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX oauthclients_keys_lookup ON oauthclients_keys (kind, lookup);
+CREATE INDEX oauthclients_keys_id ON oauthclients_keys (id);
+
+CREATE TABLE oauthconsents (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE oauthconsents_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX oauthconsents_keys_lookup ON oauthconsents_keys (kind, lookup);
+CREATE INDEX oauthconsents_keys_id ON oauthconsents_keys (id);
+
+CREATE TABLE oauthcodes (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE oauthcodes_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX oauthcodes_keys_lookup ON oauthcodes_keys (kind, lookup);
+CREATE INDEX oauthcodes_keys_id ON oauthcodes_keys (id);
+
+CREATE TABLE oauthtokens (
+    id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    document TEXT NOT NULL
+);
+
+CREATE TABLE oauthtokens_keys (
+    kind TEXT NOT NULL,
+    lookup TEXT NOT NULL,
+    id TEXT NOT NULL
+);
+
+CREATE INDEX oauthtokens_keys_lookup ON oauthtokens_keys (kind, lookup);
+CREATE INDEX oauthtokens_keys_id ON oauthtokens_keys (id);
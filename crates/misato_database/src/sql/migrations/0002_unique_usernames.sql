@@ -0,0 +1,2 @@
+-- A username, current or former, belongs to a single user.
+CREATE UNIQUE INDEX users_keys_username ON users_keys (lookup) WHERE kind = 'username';
@@ -0,0 +1,2 @@
+-- A verified address belongs to a single user, password resets are sent to it.
+CREATE UNIQUE INDEX users_keys_verified_email ON users_keys (lookup) WHERE kind = 'verified_email';
@@ -0,0 +1,2 @@
+-- A single export per user is prepared at a time.
+CREATE UNIQUE INDEX exports_keys_pending ON exports_keys (lookup) WHERE kind = 'pending';
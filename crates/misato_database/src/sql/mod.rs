@@ -0,0 +1,504 @@
+//! Stores backed by SQLite or PostgreSQL, picked by the scheme of `MISATO_SQL_URL`.
+//!
+//! Every store keeps its documents as JSON in its own table, the values they are looked up by
+//! (tokens, hashes, owners...) are copied to a `<table>_keys` table rewritten on every change.
+//! Writes check the version they read and start over if the document changed in between,
+//! so the stores keep the guarantees of the single-document updates of MongoDB.
+
+pub mod api_manager;
+pub mod export_manager;
+pub mod invite_manager;
+pub mod login_manager;
+pub mod oauth_manager;
+pub mod user_manager;
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::any::{AnyConnection, AnyPool, AnyPoolOptions, AnyRow};
+use sqlx::{Connection, Executor, Row};
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::query_log::sql_options;
+use crate::repository::UpdateResult;
+
+/// Applied in order on startup, a released migration is never edited.
+const MIGRATIONS: &[(i64, &str, &str)] = &[
+    (1, "initial", include_str!("migrations/0001_initial.sql")),
+    (
+        2,
+        "unique_usernames",
+        include_str!("migrations/0002_unique_usernames.sql"),
+    ),
+    (
+        3,
+        "unique_verified_emails",
+        include_str!("migrations/0003_unique_verified_emails.sql"),
+    ),
+    (
+        4,
+        "single_pending_export",
+        include_str!("migrations/0004_single_pending_export.sql"),
+    ),
+];
+
+/// Opens the pool and brings the schema up to date, statements slower than `slow` are logged.
+pub async fn connect(url: &str, slow: Duration) -> Result<AnyPool, Error> {
+    let mut options = AnyPoolOptions::new();
+    // Every connection to an in-memory SQLite database opens a new empty one.
+    if url.contains(":memory:") || url.contains("mode=memory") {
+        options = options
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+    }
+    let pool = options.connect_with(sql_options(url, slow)?).await?;
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+/// Runs the embedded migrations not applied yet and returns their versions.
+pub async fn migrate(pool: &AnyPool) -> Result<Vec<i64>, Error> {
+    let mut connection = pool.acquire().await?;
+    connection
+        .execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                timestamp BIGINT NOT NULL
+            )",
+        )
+        .await?;
+    let applied = sqlx::query("SELECT version FROM schema_migrations")
+        .fetch_all(&mut *connection)
+        .await?
+        .iter()
+        .map(|row| row.try_get::<i64, _>("version"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut versions = Vec::new();
+    for (version, name, script) in MIGRATIONS {
+        if applied.contains(version) {
+            continue;
+        }
+        let mut transaction = connection.begin().await?;
+        transaction.execute(*script).await?;
+        sqlx::query("INSERT INTO schema_migrations (version, name, timestamp) VALUES ($1, $2, $3)")
+            .bind(*version)
+            .bind(*name)
+            .bind(get_current_timestamp() as i64)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        versions.push(*version);
+    }
+    Ok(versions)
+}
+
+/// Migrations of this build not applied yet, as `schema_migrations:<version>`.
+pub async fn pending_migrations(pool: &AnyPool) -> Result<Vec<String>, Error> {
+    let applied = sqlx::query("SELECT version FROM schema_migrations")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.try_get::<i64, _>("version"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|(version, _, _)| !applied.contains(version))
+        .map(|(version, _, _)| format!("schema_migrations:{}", version))
+        .collect())
+}
+
+pub(crate) trait Document:
+    Serialize + DeserializeOwned + Clone + PartialEq + Send + Sync
+{
+    fn id(&self) -> String;
+
+    /// Values the document can be found by, as `(kind, lookup)`.
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+}
+
+pub(crate) enum Lookup<'a> {
+    Id(&'a str),
+    Key(&'static str, &'a str),
+    All,
+}
+
+/// Counted as modified only if the change did something, like MongoDB does.
+pub(crate) fn update_result<T: PartialEq>(updated: &Option<(T, T)>) -> UpdateResult {
+    match updated {
+        Some((previous, current)) => UpdateResult {
+            matched_count: 1,
+            modified_count: (previous != current) as u64,
+        },
+        None => UpdateResult::default(),
+    }
+}
+
+pub(crate) struct Collection<T> {
+    pool: AnyPool,
+    table: &'static str,
+    document: PhantomData<fn() -> T>,
+}
+
+impl<T: Document> Collection<T> {
+    pub fn new(pool: &AnyPool, table: &'static str) -> Self {
+        Self {
+            pool: pool.clone(),
+            table,
+            document: PhantomData,
+        }
+    }
+
+    pub fn pool(&self) -> &AnyPool {
+        &self.pool
+    }
+
+    /// The matching documents with the version they were read at, ordered by id.
+    pub async fn load(&self, lookup: &Lookup<'_>) -> Result<Vec<(i64, T)>, Error> {
+        let mut connection = self.pool.acquire().await?;
+        self.load_in(&mut connection, lookup).await
+    }
+
+    pub async fn load_in(
+        &self,
+        connection: &mut AnyConnection,
+        lookup: &Lookup<'_>,
+    ) -> Result<Vec<(i64, T)>, Error> {
+        let rows: Vec<AnyRow> = match lookup {
+            Lookup::Id(id) => {
+                let sql = format!("SELECT version, document FROM {} WHERE id = $1", self.table);
+                sqlx::query(&sql).bind(*id).fetch_all(connection).await
+            }
+            Lookup::Key(kind, lookup) => {
+                let sql = format!(
+                    "SELECT d.version, d.document FROM {0} d JOIN {0}_keys k ON k.id = d.id
+                    WHERE k.kind = $1 AND k.lookup = $2 ORDER BY d.id",
+                    self.table
+                );
+                sqlx::query(&sql)
+                    .bind(*kind)
+                    .bind(*lookup)
+                    .fetch_all(connection)
+                    .await
+            }
+            Lookup::All => {
+                let sql = format!("SELECT version, document FROM {} ORDER BY id", self.table);
+                sqlx::query(&sql).fetch_all(connection).await
+            }
+        }?;
+        rows.iter()
+            .map(|row| {
+                let version: i64 = row.try_get("version")?;
+                let document: String = row.try_get("document")?;
+                Ok((version, serde_json::from_str(&document)?))
+            })
+            .collect()
+    }
+
+    pub async fn find(&self, lookup: &Lookup<'_>) -> Result<Vec<T>, Error> {
+        Ok(self
+            .load(lookup)
+            .await?
+            .into_iter()
+            .map(|(_, document)| document)
+            .collect())
+    }
+
+    pub async fn find_one(
+        &self,
+        lookup: &Lookup<'_>,
+        filter: impl Fn(&T) -> bool,
+    ) -> Result<Option<T>, Error> {
+        Ok(self
+            .find(lookup)
+            .await?
+            .into_iter()
+            .find(|document| filter(document)))
+    }
+
+    /// False when a document with the same id already exists.
+    pub async fn insert(&self, document: &T) -> Result<bool, Error> {
+        let mut transaction = self.pool.begin().await?;
+        if !self.insert_in(&mut transaction, document).await? {
+            return Ok(false);
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    /// Same as `insert`, in the transaction `connection` is in.
+    pub async fn insert_in(
+        &self,
+        connection: &mut AnyConnection,
+        document: &T,
+    ) -> Result<bool, Error> {
+        let sql = format!(
+            "INSERT INTO {} (id, version, document) VALUES ($1, 0, $2) ON CONFLICT (id) DO NOTHING",
+            self.table
+        );
+        let result = sqlx::query(&sql)
+            .bind(document.id())
+            .bind(serde_json::to_string(document)?)
+            .execute(&mut *connection)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.write_keys(connection, document).await?;
+        Ok(true)
+    }
+
+    /// Replaces the document if it is still at `version`.
+    async fn replace(&self, version: i64, document: &T) -> Result<bool, Error> {
+        let mut transaction = self.pool.begin().await?;
+        if !self.replace_in(&mut transaction, version, document).await? {
+            return Ok(false);
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn replace_in(
+        &self,
+        connection: &mut AnyConnection,
+        version: i64,
+        document: &T,
+    ) -> Result<bool, Error> {
+        let sql = format!(
+            "UPDATE {} SET version = version + 1, document = $1 WHERE id = $2 AND version = $3",
+            self.table
+        );
+        let result = sqlx::query(&sql)
+            .bind(serde_json::to_string(document)?)
+            .bind(document.id())
+            .bind(version)
+            .execute(&mut *connection)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        self.delete_keys(connection, &document.id()).await?;
+        self.write_keys(connection, document).await?;
+        Ok(true)
+    }
+
+    async fn write_keys(&self, connection: &mut AnyConnection, document: &T) -> Result<(), Error> {
+        let mut keys = document.keys();
+        keys.sort();
+        keys.dedup();
+        let sql = format!(
+            "INSERT INTO {}_keys (kind, lookup, id) VALUES ($1, $2, $3)",
+            self.table
+        );
+        for (kind, lookup) in keys {
+            sqlx::query(&sql)
+                .bind(kind)
+                .bind(lookup)
+                .bind(document.id())
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_keys(&self, connection: &mut AnyConnection, id: &str) -> Result<(), Error> {
+        let sql = format!("DELETE FROM {}_keys WHERE id = $1", self.table);
+        sqlx::query(&sql).bind(id).execute(connection).await?;
+        Ok(())
+    }
+
+    /// Applies `change` to the first document matching `filter` and returns it before and after.
+    pub async fn update(
+        &self,
+        lookup: &Lookup<'_>,
+        filter: impl Fn(&T) -> bool,
+        change: impl Fn(&mut T),
+    ) -> Result<Option<(T, T)>, Error> {
+        loop {
+            let found = self
+                .load(lookup)
+                .await?
+                .into_iter()
+                .find(|(_, document)| filter(document));
+            let (version, previous) = match found {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            let mut current = previous.clone();
+            change(&mut current);
+            if current == previous || self.replace(version, &current).await? {
+                return Ok(Some((previous, current)));
+            }
+        }
+    }
+
+    /// Same as `update`, in the transaction `connection` is in.
+    pub async fn update_in(
+        &self,
+        connection: &mut AnyConnection,
+        lookup: &Lookup<'_>,
+        filter: impl Fn(&T) -> bool,
+        change: impl Fn(&mut T),
+    ) -> Result<Option<(T, T)>, Error> {
+        loop {
+            let found = self
+                .load_in(connection, lookup)
+                .await?
+                .into_iter()
+                .find(|(_, document)| filter(document));
+            let (version, previous) = match found {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            let mut current = previous.clone();
+            change(&mut current);
+            if current == previous || self.replace_in(connection, version, &current).await? {
+                return Ok(Some((previous, current)));
+            }
+        }
+    }
+
+    /// Applies `change` to the document `id`, created with `create` if missing,
+    /// and returns it before and after.
+    pub async fn upsert(
+        &self,
+        id: &str,
+        create: impl Fn() -> T,
+        change: impl Fn(&mut T),
+    ) -> Result<(Option<T>, T), Error> {
+        loop {
+            match self.load(&Lookup::Id(id)).await?.pop() {
+                Some((version, previous)) => {
+                    let mut current = previous.clone();
+                    change(&mut current);
+                    if current == previous || self.replace(version, &current).await? {
+                        return Ok((Some(previous), current));
+                    }
+                }
+                None => {
+                    let mut current = create();
+                    change(&mut current);
+                    if self.insert(&current).await? {
+                        return Ok((None, current));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as `upsert`, in the transaction `connection` is in.
+    pub async fn upsert_in(
+        &self,
+        connection: &mut AnyConnection,
+        id: &str,
+        create: impl Fn() -> T,
+        change: impl Fn(&mut T),
+    ) -> Result<(Option<T>, T), Error> {
+        loop {
+            match self.load_in(connection, &Lookup::Id(id)).await?.pop() {
+                Some((version, previous)) => {
+                    let mut current = previous.clone();
+                    change(&mut current);
+                    if current == previous || self.replace_in(connection, version, &current).await?
+                    {
+                        return Ok((Some(previous), current));
+                    }
+                }
+                None => {
+                    let mut current = create();
+                    change(&mut current);
+                    if self.insert_in(connection, &current).await? {
+                        return Ok((None, current));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the first document matching `filter` and returns it, at most once.
+    pub async fn take(
+        &self,
+        lookup: &Lookup<'_>,
+        filter: impl Fn(&T) -> bool,
+    ) -> Result<Option<T>, Error> {
+        loop {
+            let found = self
+                .load(lookup)
+                .await?
+                .into_iter()
+                .find(|(_, document)| filter(document));
+            let (version, document) = match found {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            let mut transaction = self.pool.begin().await?;
+            let deleted = self
+                .delete_in(&mut transaction, &document.id(), Some(version))
+                .await?;
+            transaction.commit().await?;
+            if deleted > 0 {
+                return Ok(Some(document));
+            }
+        }
+    }
+
+    /// Deletes the document `id`, only if it is still at `version` when given.
+    pub async fn delete_in(
+        &self,
+        connection: &mut AnyConnection,
+        id: &str,
+        version: Option<i64>,
+    ) -> Result<u64, Error> {
+        let sql = match version {
+            Some(_) => format!("DELETE FROM {} WHERE id = $1 AND version = $2", self.table),
+            None => format!("DELETE FROM {} WHERE id = $1", self.table),
+        };
+        let mut query = sqlx::query(&sql).bind(id);
+        if let Some(version) = version {
+            query = query.bind(version);
+        }
+        let deleted = query.execute(&mut *connection).await?.rows_affected();
+        if deleted > 0 {
+            self.delete_keys(connection, id).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes every document found by the key, writing first so SQLite locks the database
+    /// right away instead of failing to upgrade a read lock.
+    pub async fn delete_keyed_in(
+        &self,
+        connection: &mut AnyConnection,
+        kind: &str,
+        lookup: &str,
+    ) -> Result<u64, Error> {
+        let sql = format!(
+            "DELETE FROM {0} WHERE id IN (SELECT id FROM {0}_keys WHERE kind = $1 AND lookup = $2)",
+            self.table
+        );
+        let deleted = sqlx::query(&sql)
+            .bind(kind)
+            .bind(lookup)
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+        let sql = format!(
+            "DELETE FROM {0}_keys WHERE id IN (SELECT id FROM {0}_keys WHERE kind = $1 AND lookup = $2)",
+            self.table
+        );
+        sqlx::query(&sql)
+            .bind(kind)
+            .bind(lookup)
+            .execute(connection)
+            .await?;
+        Ok(deleted)
+    }
+}
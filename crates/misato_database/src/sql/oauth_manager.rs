@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::models::oauth_model::*;
+use crate::repository::{DeleteResult, OAuthRepository};
+use crate::sql::{Collection, Document, Lookup};
+
+/// Identifies what a user granted to a client.
+fn grant_key(uuid: &str, client_id: &str) -> String {
+    format!("{}\n{}", uuid, client_id)
+}
+
+impl Document for OAuthClient {
+    fn id(&self) -> String {
+        self.client_id.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        vec![("owner", self.owner.clone())]
+    }
+}
+
+impl Document for OAuthConsent {
+    fn id(&self) -> String {
+        grant_key(&self.uuid, &self.client_id)
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("uuid", self.uuid.clone()),
+            ("client", self.client_id.clone()),
+        ]
+    }
+}
+
+impl Document for OAuthCode {
+    fn id(&self) -> String {
+        self.hash.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("client", self.client_id.clone()),
+            ("grant", grant_key(&self.uuid, &self.client_id)),
+        ]
+    }
+}
+
+impl Document for OAuthToken {
+    fn id(&self) -> String {
+        self.hash.clone()
+    }
+
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("client", self.client_id.clone()),
+            ("grant", grant_key(&self.uuid, &self.client_id)),
+        ]
+    }
+}
+
+/// Cascades run in a single transaction.
+pub struct SqlOAuthManager {
+    clients: Collection<OAuthClient>,
+    consents: Collection<OAuthConsent>,
+    codes: Collection<OAuthCode>,
+    tokens: Collection<OAuthToken>,
+}
+
+impl SqlOAuthManager {
+    pub fn init(pool: &AnyPool) -> Self {
+        Self {
+            clients: Collection::new(pool, "oauthclients"),
+            consents: Collection::new(pool, "oauthconsents"),
+            codes: Collection::new(pool, "oauthcodes"),
+            tokens: Collection::new(pool, "oauthtokens"),
+        }
+    }
+}
+
+#[async_trait]
+impl OAuthRepository for SqlOAuthManager {
+    async fn create_client(&self, client: &OAuthClient) -> Result<(), Error> {
+        self.clients.insert(client).await?;
+        Ok(())
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, Error> {
+        self.clients
+            .find_one(&Lookup::Id(client_id), |_| true)
+            .await
+    }
+
+    async fn get_clients(&self, owner: &str) -> Result<Vec<OAuthClient>, Error> {
+        self.clients.find(&Lookup::Key("owner", owner)).await
+    }
+
+    async fn delete_client(&self, owner: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let found = self
+            .clients
+            .load(&Lookup::Id(client_id))
+            .await?
+            .into_iter()
+            .find(|(_, client)| client.owner == owner);
+        let version = match found {
+            Some((version, _)) => version,
+            None => return Ok(DeleteResult::default()),
+        };
+        let mut transaction = self.clients.pool().begin().await?;
+        let deleted_count = self
+            .clients
+            .delete_in(&mut transaction, client_id, Some(version))
+            .await?;
+        if deleted_count > 0 {
+            self.consents
+                .delete_keyed_in(&mut transaction, "client", client_id)
+                .await?;
+            self.codes
+                .delete_keyed_in(&mut transaction, "client", client_id)
+                .await?;
+            self.tokens
+                .delete_keyed_in(&mut transaction, "client", client_id)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(DeleteResult { deleted_count })
+    }
+
+    async fn get_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+    ) -> Result<Option<OAuthConsent>, Error> {
+        self.consents
+            .find_one(&Lookup::Id(&grant_key(uuid, client_id)), |_| true)
+            .await
+    }
+
+    async fn get_consents(&self, uuid: &str) -> Result<Vec<OAuthConsent>, Error> {
+        self.consents.find(&Lookup::Key("uuid", uuid)).await
+    }
+
+    async fn add_consent(
+        &self,
+        uuid: &str,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<Option<OAuthConsent>, Error> {
+        let timestamp = get_current_timestamp();
+        let (_, consent) = self
+            .consents
+            .upsert(
+                &grant_key(uuid, client_id),
+                || OAuthConsent {
+                    uuid: uuid.to_string(),
+                    client_id: client_id.to_string(),
+                    ..Default::default()
+                },
+                |consent| {
+                    for scope in scopes {
+                        if !consent.scopes.contains(scope) {
+                            consent.scopes.push(scope.clone());
+                        }
+                    }
+                    consent.timestamp = timestamp;
+                },
+            )
+            .await?;
+        Ok(Some(consent))
+    }
+
+    async fn revoke_consent(&self, uuid: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        let grant = grant_key(uuid, client_id);
+        let mut transaction = self.consents.pool().begin().await?;
+        self.codes
+            .delete_keyed_in(&mut transaction, "grant", &grant)
+            .await?;
+        self.tokens
+            .delete_keyed_in(&mut transaction, "grant", &grant)
+            .await?;
+        let deleted_count = self
+            .consents
+            .delete_in(&mut transaction, &grant, None)
+            .await?;
+        transaction.commit().await?;
+        Ok(DeleteResult { deleted_count })
+    }
+
+    async fn save_code(&self, code: &OAuthCode) -> Result<(), Error> {
+        self.codes.insert(code).await?;
+        Ok(())
+    }
+
+    async fn consume_code(&self, hash: &str) -> Result<Option<OAuthCode>, Error> {
+        let timestamp = get_current_timestamp();
+        self.codes
+            .take(&Lookup::Id(hash), |code| {
+                code.expiration_timestamp >= timestamp
+            })
+            .await
+    }
+
+    async fn save_token(&self, token: &OAuthToken) -> Result<(), Error> {
+        self.tokens.insert(token).await?;
+        Ok(())
+    }
+
+    async fn get_token(&self, hash: &str) -> Result<Option<OAuthToken>, Error> {
+        let timestamp = get_current_timestamp();
+        self.tokens
+            .find_one(&Lookup::Id(hash), |token| {
+                token.expiration_timestamp >= timestamp
+            })
+            .await
+    }
+
+    async fn revoke_token(&self, hash: &str, client_id: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self
+                .tokens
+                .take(&Lookup::Id(hash), |token| token.client_id == client_id)
+                .await?
+                .is_some() as u64,
+        })
+    }
+}
@@ -0,0 +1,609 @@
+use async_trait::async_trait;
+use sqlx::any::AnyPool;
+
+use misato_security::password::Password;
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::error::Error;
+use crate::models::user_model::*;
+use crate::repository::{DeleteResult, UpdateResult, UserRepository};
+use crate::sql::{update_result, Collection, Document, Lookup};
+use crate::user_manager::{UserSearch, UserSort};
+
+fn has_token(tokens: &Option<Vec<UserToken>>, token: &str, timestamp: u64) -> bool {
+    tokens
+        .iter()
+        .flatten()
+        .any(|candidate| candidate.token == token && candidate.expiration_timestamp >= timestamp)
+}
+
+fn has_secret(secret: &Option<UserSecretToken>, hash: &str, timestamp: u64) -> bool {
+    matches!(secret, Some(secret) if secret.hash == hash && secret.expiration_timestamp >= timestamp)
+}
+
+/// Expired sessions are dropped on the way, nothing else removes them.
+pub(crate) fn add_session(user: &mut User, token: &UserToken, timestamp: u64) {
+    let tokens = user.tokens.get_or_insert_with(Vec::new);
+    tokens.retain(|session| session.expiration_timestamp >= timestamp);
+    tokens.push(token.clone());
+}
+
+fn identity_key(provider: &str, subject: &str) -> String {
+    format!("{}\n{}", provider, subject)
+}
+
+impl Document for User {
+    fn id(&self) -> String {
+        self.uuid.clone()
+    }
+
+    /// Usernames are stored lowercased, former ones included.
+    fn keys(&self) -> Vec<(&'static str, String)> {
+        let mut keys = vec![("username", self.username.to_lowercase())];
+        for previous in self.previous_usernames.iter().flatten() {
+            keys.push(("username", previous.username.to_lowercase()));
+        }
+        if let Some(email) = &self.email {
+            keys.push(("email", email.clone()));
+            if self.email_verified {
+                keys.push(("verified_email", email.clone()));
+            }
+        }
+        for token in self.tokens.iter().flatten() {
+            keys.push(("token", token.token.clone()));
+        }
+        for challenge in self.challenges.iter().flatten() {
+            keys.push(("challenge", challenge.token.clone()));
+        }
+        if let Some(token) = &self.reset_token {
+            keys.push(("reset", token.hash.clone()));
+        }
+        if let Some(token) = &self.verification_token {
+            keys.push(("verification", token.hash.clone()));
+        }
+        for identity in &self.identities {
+            keys.push((
+                "identity",
+                identity_key(&identity.provider, &identity.subject),
+            ));
+        }
+        keys
+    }
+}
+
+pub struct SqlUserManager {
+    users: Collection<User>,
+    /// Seconds between the deletion of an account and its purge.
+    deletion_grace_period: u64,
+}
+
+impl SqlUserManager {
+    pub fn init(pool: &AnyPool, settings: &Settings) -> Self {
+        Self {
+            users: Collection::new(pool, "users"),
+            deletion_grace_period: settings.deletion_grace_period,
+        }
+    }
+
+    fn deleted_status(&self) -> UserStatus {
+        let timestamp = get_current_timestamp();
+        UserStatus::Deleted {
+            timestamp,
+            purge_timestamp: timestamp + self.deletion_grace_period * 1000,
+        }
+    }
+
+    async fn update_uuid(
+        &self,
+        uuid: &str,
+        change: impl Fn(&mut User),
+    ) -> Result<UpdateResult, Error> {
+        let updated = self
+            .users
+            .update(&Lookup::Id(uuid), |_| true, change)
+            .await?;
+        Ok(update_result(&updated))
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlUserManager {
+    async fn username_exists(&self, username: &str) -> Result<bool, Error> {
+        let username = username.to_lowercase();
+        Ok(!self
+            .users
+            .find(&Lookup::Key("username", &username))
+            .await?
+            .is_empty())
+    }
+
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+        Ok(!self.users.find(&Lookup::Id(uuid)).await?.is_empty())
+    }
+
+    async fn create_user(&self, user: &User) -> Result<(), Error> {
+        match self.users.insert(user).await? {
+            true => Ok(()),
+            false => Err(Error::DuplicateKey(format!(
+                "user {} already exists",
+                user.uuid
+            ))),
+        }
+    }
+
+    async fn get_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<User>, Error> {
+        match (username, uuid) {
+            (Some(username), _) => {
+                self.users
+                    .find_one(&Lookup::Key("username", &username.to_lowercase()), |user| {
+                        user.username.to_lowercase() == username.to_lowercase()
+                    })
+                    .await
+            }
+            (None, Some(uuid)) => self.users.find_one(&Lookup::Id(uuid), |_| true).await,
+            (None, None) => Ok(None),
+        }
+    }
+
+    async fn delete_user(
+        &self,
+        username: Option<&str>,
+        uuid: Option<&str>,
+    ) -> Result<Option<UpdateResult>, Error> {
+        let lowercase = username.map(str::to_lowercase);
+        let lookup = match (&lowercase, uuid) {
+            (Some(username), _) => Lookup::Key("username", username),
+            (None, Some(uuid)) => Lookup::Id(uuid),
+            (None, None) => return Ok(None),
+        };
+        let status = self.deleted_status();
+        let updated = self
+            .users
+            .update(
+                &lookup,
+                |user| {
+                    lowercase
+                        .as_ref()
+                        .is_none_or(|username| user.username.to_lowercase() == *username)
+                        && !matches!(
+                            user.status,
+                            UserStatus::Deleted { .. } | UserStatus::Purged { .. }
+                        )
+                },
+                |user| {
+                    user.status = status.clone();
+                    user.tokens = None;
+                    user.challenges = None;
+                },
+            )
+            .await?;
+        Ok(Some(update_result(&updated)))
+    }
+
+    async fn delete_user_from_token(&self, token: &str) -> Result<Option<UpdateResult>, Error> {
+        let timestamp = get_current_timestamp();
+        let status = self.deleted_status();
+        let updated = self
+            .users
+            .update(
+                &Lookup::Key("token", token),
+                |user| has_token(&user.tokens, token, timestamp),
+                |user| {
+                    user.status = status.clone();
+                    user.tokens = None;
+                    user.challenges = None;
+                },
+            )
+            .await?;
+        Ok(Some(update_result(&updated)))
+    }
+
+    async fn set_status(&self, uuid: &str, status: &UserStatus) -> Result<UpdateResult, Error> {
+        let updated = self
+            .users
+            .update(
+                &Lookup::Id(uuid),
+                |user| !matches!(user.status, UserStatus::Purged { .. }),
+                |user| {
+                    user.status = status.clone();
+                    if *status != UserStatus::Active {
+                        user.tokens = None;
+                        user.challenges = None;
+                    }
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn remove_user(&self, uuid: &str) -> Result<DeleteResult, Error> {
+        Ok(DeleteResult {
+            deleted_count: self
+                .users
+                .take(&Lookup::Id(uuid), |_| true)
+                .await?
+                .is_some() as u64,
+        })
+    }
+
+    async fn purge_deleted_users(&self) -> Result<Vec<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let is_due = |user: &User| matches!(user.status, UserStatus::Deleted { purge_timestamp, .. } if purge_timestamp <= timestamp);
+        let mut purged = Vec::new();
+        for user in self.users.find(&Lookup::All).await? {
+            if !is_due(&user) {
+                continue;
+            }
+            let updated = self
+                .users
+                .update(&Lookup::Id(&user.uuid), is_due, |user| {
+                    *user = user.tombstone(timestamp)
+                })
+                .await?;
+            purged.extend(updated.map(|(previous, _)| previous));
+        }
+        Ok(purged)
+    }
+
+    async fn count_sessions(&self) -> Result<u64, Error> {
+        let timestamp = get_current_timestamp();
+        Ok(self
+            .users
+            .find(&Lookup::All)
+            .await?
+            .iter()
+            .map(|user| user.session_count(timestamp))
+            .sum())
+    }
+
+    /// Filtered and sorted after loading every user, like the in-memory store.
+    async fn search_users(
+        &self,
+        search: &UserSearch,
+        sort: &UserSort,
+        descending: bool,
+        skip: u64,
+        limit: i64,
+    ) -> Result<(Vec<User>, u64), Error> {
+        let mut users: Vec<User> = self
+            .users
+            .find(&Lookup::All)
+            .await?
+            .into_iter()
+            .filter(|user| search.matches(user))
+            .collect();
+        users.sort_by(|a, b| {
+            let order = match sort {
+                UserSort::Username => a.username.to_lowercase().cmp(&b.username.to_lowercase()),
+                UserSort::Created => a.timestamp.cmp(&b.timestamp),
+                UserSort::LastLogin => a.last_login.cmp(&b.last_login),
+            };
+            let order = if descending { order.reverse() } else { order };
+            order.then_with(|| a.uuid.cmp(&b.uuid))
+        });
+        let total = users.len() as u64;
+        let users = users.into_iter().skip(skip as usize);
+        let users = match limit.unsigned_abs() {
+            0 => users.collect(),
+            limit => users.take(limit as usize).collect(),
+        };
+        Ok((users, total))
+    }
+
+    async fn set_last_login(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.last_login = Some(timestamp))
+            .await
+    }
+
+    async fn set_role(&self, uuid: &str, role: &UserRoleType) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.access.role = role.clone())
+            .await
+    }
+
+    async fn save_token(&self, uuid: &str, token: &UserToken) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        self.update_uuid(uuid, |user| add_session(user, token, timestamp))
+            .await
+    }
+
+    async fn add_log(&self, uuid: &str, log: &UserLog) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.logs.get_or_insert_with(Vec::new).push(log.clone())
+        })
+        .await
+    }
+
+    async fn set_totp(&self, uuid: &str, totp: &UserTotp) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.totp = Some(totp.clone()))
+            .await
+    }
+
+    async fn clear_totp(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.totp = None).await
+    }
+
+    async fn use_totp_step(&self, uuid: &str, step: u64) -> Result<UpdateResult, Error> {
+        let updated = self
+            .users
+            .update(
+                &Lookup::Id(uuid),
+                |user| matches!(&user.totp, Some(totp) if totp.last_step < step),
+                |user| {
+                    if let Some(totp) = &mut user.totp {
+                        totp.last_step = step;
+                    }
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn remove_recovery_code(&self, uuid: &str, hash: &str) -> Result<UpdateResult, Error> {
+        let updated = self
+            .users
+            .update(
+                &Lookup::Id(uuid),
+                |user| matches!(&user.totp, Some(totp) if totp.has_recovery_code(hash)),
+                |user| {
+                    if let Some(totp) = &mut user.totp {
+                        totp.recovery_codes.retain(|code| code != hash);
+                    }
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn save_challenge(
+        &self,
+        uuid: &str,
+        challenge: &UserToken,
+    ) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        self.update_uuid(uuid, |user| {
+            let challenges = user.challenges.get_or_insert_with(Vec::new);
+            challenges.retain(|challenge| challenge.expiration_timestamp >= timestamp);
+            challenges.push(challenge.clone());
+        })
+        .await
+    }
+
+    async fn remove_challenge(&self, uuid: &str, token: &str) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            if let Some(challenges) = &mut user.challenges {
+                challenges.retain(|challenge| challenge.token != token);
+            }
+        })
+        .await
+    }
+
+    async fn get_user_from_challenge(&self, token: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        self.users
+            .find_one(&Lookup::Key("challenge", token), |user| {
+                has_token(&user.challenges, token, timestamp)
+            })
+            .await
+    }
+
+    async fn get_user_from_email(&self, email: &str) -> Result<Option<User>, Error> {
+        self.users
+            .find_one(&Lookup::Key("email", email), |user| user.email_verified)
+            .await
+    }
+
+    async fn set_password(&self, uuid: &str, password: &Password) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.password = Some(password.clone());
+            user.tokens = None;
+        })
+        .await
+    }
+
+    async fn change_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+        keep_token: &str,
+    ) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.password = Some(password.clone());
+            if let Some(tokens) = &mut user.tokens {
+                tokens.retain(|token| token.token == keep_token);
+            }
+        })
+        .await
+    }
+
+    async fn change_username(
+        &self,
+        uuid: &str,
+        previous: &str,
+        username: &str,
+    ) -> Result<UpdateResult, Error> {
+        let history = UserPreviousUsername {
+            username: previous.to_string(),
+            timestamp: get_current_timestamp(),
+        };
+        let updated = self
+            .users
+            .update(
+                &Lookup::Id(uuid),
+                |user| user.username == previous,
+                |user| {
+                    user.username = username.to_string();
+                    user.previous_usernames
+                        .get_or_insert_with(Vec::new)
+                        .push(history.clone());
+                },
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn rehash_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+    ) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.password = Some(password.clone()))
+            .await
+    }
+
+    async fn set_profile(&self, uuid: &str, profile: &UserProfile) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.profile = UserProfile {
+                avatar: user.profile.avatar.take(),
+                ..profile.clone()
+            }
+        })
+        .await
+    }
+
+    async fn set_avatar(&self, uuid: &str, avatar: Option<&str>) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.profile.avatar = avatar.map(str::to_string)
+        })
+        .await
+    }
+
+    async fn get_user_from_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Error> {
+        self.users
+            .find_one(
+                &Lookup::Key("identity", &identity_key(provider, subject)),
+                |_| true,
+            )
+            .await
+    }
+
+    async fn add_identity(
+        &self,
+        uuid: &str,
+        identity: &UserIdentity,
+    ) -> Result<UpdateResult, Error> {
+        let updated = self
+            .users
+            .update(
+                &Lookup::Id(uuid),
+                |user| {
+                    !user.identities.iter().any(|linked| {
+                        linked.provider == identity.provider && linked.subject == identity.subject
+                    })
+                },
+                |user| user.identities.push(identity.clone()),
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn remove_identity(
+        &self,
+        uuid: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.identities
+                .retain(|identity| identity.provider != provider || identity.subject != subject)
+        })
+        .await
+    }
+
+    async fn set_reset_token(
+        &self,
+        uuid: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.reset_token = Some(token.clone()))
+            .await
+    }
+
+    async fn get_user_from_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        self.users
+            .find_one(&Lookup::Key("reset", hash), |user| {
+                has_secret(&user.reset_token, hash, timestamp)
+            })
+            .await
+    }
+
+    async fn consume_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .users
+            .update(
+                &Lookup::Key("reset", hash),
+                |user| has_secret(&user.reset_token, hash, timestamp),
+                |user| user.reset_token = None,
+            )
+            .await?;
+        Ok(updated.map(|(previous, _)| previous))
+    }
+
+    async fn set_email(
+        &self,
+        uuid: &str,
+        email: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| {
+            user.email = Some(email.to_string());
+            user.email_verified = false;
+            user.verification_token = Some(token.clone());
+        })
+        .await
+    }
+
+    async fn consume_verification_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .users
+            .update(
+                &Lookup::Key("verification", hash),
+                |user| {
+                    has_secret(&user.verification_token, hash, timestamp)
+                        && matches!(&user.verification_token, Some(token) if token.email.is_some() && token.email == user.email)
+                },
+                |user| {
+                    user.email_verified = true;
+                    user.verification_token = None;
+                },
+            )
+            .await?;
+        Ok(updated.map(|(_, current)| current))
+    }
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        self.update_uuid(uuid, |user| user.tokens = None).await
+    }
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .users
+            .update(
+                &Lookup::Key("token", token),
+                |user| has_token(&user.tokens, token, timestamp),
+                |user| user.tokens = None,
+            )
+            .await?;
+        Ok(update_result(&updated))
+    }
+
+    async fn get_user_from_token(&self, token: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp();
+        self.users
+            .find_one(&Lookup::Key("token", token), |user| {
+                has_token(&user.tokens, token, timestamp)
+            })
+            .await
+    }
+}
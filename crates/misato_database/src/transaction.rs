@@ -0,0 +1,383 @@
+//! Units of work: writes spanning several documents, applied together or not at all.
+//!
+//! On a MongoDB replica set (or behind mongos) they run in a transaction of one session, on
+//! SQLite and PostgreSQL in a transaction of one connection. On a standalone MongoDB and the
+//! in-memory store, every write is applied at once and `abort` undoes the ones done so far
+//! in reverse order.
+//!
+//! ```text
+//! let mut work = db.begin().await?;
+//! let result = async {
+//!     work.create_user(&user).await?;
+//!     work.save_token(&user.uuid, &token).await
+//! }
+//! .await;
+//! match result {
+//!     Ok(()) => work.commit().await?,
+//!     Err(error) => work.abort().await,
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use mongodb::{
+    bson::doc,
+    options::{FindOneAndUpdateOptions, ReplaceOptions, ReturnDocument},
+    Client, ClientSession, Collection,
+};
+use sqlx::any::AnyPool;
+
+use misato_utils::get_current_timestamp;
+
+use crate::error::Error;
+use crate::invite_manager::redemption;
+use crate::models::{apiuser_model::*, invite_model::*, user_model::*};
+use crate::repository::{ApiUserRepository, InviteRepository, UserRepository};
+use crate::sql::invite_manager::{add_redemption, redeemable};
+use crate::sql::user_manager::add_session;
+use crate::sql::{self, Lookup};
+use crate::user_manager::split_sessions;
+
+#[async_trait]
+pub trait UnitOfWork: Send {
+    /// Redeems the invite matching `hash` for `uuid`, `None` if it has no uses left.
+    async fn redeem_invite(&mut self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error>;
+
+    async fn create_user(&mut self, user: &User) -> Result<(), Error>;
+
+    /// Fails with `Error::NotFound` if there is no such user.
+    async fn save_token(&mut self, uuid: &str, token: &UserToken) -> Result<(), Error>;
+
+    /// Creates the API account of `apiuser.uuid`, or replaces the existing one.
+    async fn create_apiuser(&mut self, apiuser: &ApiUser) -> Result<(), Error>;
+
+    /// Fails with `Error::NotFound` if there is no such API account.
+    async fn set_apiuser_token(&mut self, uuid: &str, token: &ApiUserToken) -> Result<(), Error>;
+
+    async fn commit(self: Box<Self>) -> Result<(), Error>;
+
+    /// Discards the writes, failures are only logged: the error that led here matters more.
+    async fn abort(self: Box<Self>);
+}
+
+/// A write matching nothing fails the whole unit, it must not commit half done.
+fn found(matched_count: u64, what: &str, uuid: &str) -> Result<(), Error> {
+    match matched_count {
+        0 => Err(Error::NotFound(format!("{} {}", what, uuid))),
+        _ => Ok(()),
+    }
+}
+
+/// Writes of one transaction on a replica set, invisible to others until committed.
+pub struct MongoUnitOfWork {
+    session: ClientSession,
+    users: Collection<User>,
+    sessions: Collection<UserSession>,
+    apiusers: Collection<ApiUser>,
+    invites: Collection<Invite>,
+}
+
+impl MongoUnitOfWork {
+    pub async fn start(client: &Client, db: &mongodb::Database) -> Result<Self, Error> {
+        let mut session = client.start_session(None).await?;
+        session.start_transaction(None).await?;
+        Ok(Self {
+            session,
+            users: db.collection("users"),
+            sessions: db.collection("sessions"),
+            apiusers: db.collection("apiusers"),
+            invites: db.collection("invites"),
+        })
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for MongoUnitOfWork {
+    async fn redeem_invite(&mut self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let (filter, update) = redemption(hash, uuid);
+        self.invites
+            .find_one_and_update_with_session(
+                filter,
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+                &mut self.session,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn create_user(&mut self, user: &User) -> Result<(), Error> {
+        let (user, sessions) = split_sessions(user);
+        self.users
+            .insert_one_with_session(&user, None, &mut self.session)
+            .await?;
+        if !sessions.is_empty() {
+            self.sessions
+                .insert_many_with_session(sessions, None, &mut self.session)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn save_token(&mut self, uuid: &str, token: &UserToken) -> Result<(), Error> {
+        let matched_count = self
+            .users
+            .count_documents_with_session(doc! {"uuid": uuid}, None, &mut self.session)
+            .await?;
+        found(matched_count, "user", uuid)?;
+        self.sessions
+            .insert_one_with_session(UserSession::create(uuid, token), None, &mut self.session)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_apiuser(&mut self, apiuser: &ApiUser) -> Result<(), Error> {
+        self.apiusers
+            .replace_one_with_session(
+                doc! {"uuid": &apiuser.uuid},
+                apiuser,
+                ReplaceOptions::builder().upsert(true).build(),
+                &mut self.session,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_apiuser_token(&mut self, uuid: &str, token: &ApiUserToken) -> Result<(), Error> {
+        let doc = mongodb::bson::to_document(token)?;
+        let result = self
+            .apiusers
+            .update_one_with_session(
+                doc! {"uuid": uuid},
+                doc! {"$set": {"token": doc}},
+                None,
+                &mut self.session,
+            )
+            .await?;
+        found(result.matched_count, "API account", uuid)
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<(), Error> {
+        self.session.commit_transaction().await.map_err(Error::from)
+    }
+
+    async fn abort(mut self: Box<Self>) {
+        if let Err(error) = self.session.abort_transaction().await {
+            tracing::error!(%error, "Cannot abort the transaction");
+        }
+    }
+}
+
+/// Writes of one SQL transaction, invisible to others until committed.
+pub struct SqlUnitOfWork {
+    transaction: sqlx::Transaction<'static, sqlx::Any>,
+    users: sql::Collection<User>,
+    apiusers: sql::Collection<ApiUser>,
+    invites: sql::Collection<Invite>,
+}
+
+impl SqlUnitOfWork {
+    pub async fn start(pool: &AnyPool) -> Result<Self, Error> {
+        Ok(Self {
+            transaction: pool.begin().await?,
+            users: sql::Collection::new(pool, "users"),
+            apiusers: sql::Collection::new(pool, "apiusers"),
+            invites: sql::Collection::new(pool, "invites"),
+        })
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for SqlUnitOfWork {
+    async fn redeem_invite(&mut self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .invites
+            .update_in(
+                &mut self.transaction,
+                &Lookup::Key("hash", hash),
+                |invite| redeemable(invite, timestamp),
+                |invite| add_redemption(invite, uuid, timestamp),
+            )
+            .await?;
+        Ok(updated.map(|(_, current)| current))
+    }
+
+    async fn create_user(&mut self, user: &User) -> Result<(), Error> {
+        match self.users.insert_in(&mut self.transaction, user).await? {
+            true => Ok(()),
+            false => Err(Error::DuplicateKey(format!(
+                "user {} already exists",
+                user.uuid
+            ))),
+        }
+    }
+
+    async fn save_token(&mut self, uuid: &str, token: &UserToken) -> Result<(), Error> {
+        let timestamp = get_current_timestamp();
+        let updated = self
+            .users
+            .update_in(
+                &mut self.transaction,
+                &Lookup::Id(uuid),
+                |_| true,
+                |user| add_session(user, token, timestamp),
+            )
+            .await?;
+        found(updated.is_some() as u64, "user", uuid)
+    }
+
+    async fn create_apiuser(&mut self, apiuser: &ApiUser) -> Result<(), Error> {
+        self.apiusers
+            .upsert_in(
+                &mut self.transaction,
+                &apiuser.uuid,
+                || apiuser.clone(),
+                |existing| *existing = apiuser.clone(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_apiuser_token(&mut self, uuid: &str, token: &ApiUserToken) -> Result<(), Error> {
+        let updated = self
+            .apiusers
+            .update_in(
+                &mut self.transaction,
+                &Lookup::Id(uuid),
+                |_| true,
+                |apiuser| apiuser.token = Some(token.clone()),
+            )
+            .await?;
+        found(updated.is_some() as u64, "API account", uuid)
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.transaction.commit().await.map_err(Error::from)
+    }
+
+    async fn abort(self: Box<Self>) {
+        if let Err(error) = self.transaction.rollback().await {
+            tracing::error!(%error, "Cannot roll back the transaction");
+        }
+    }
+}
+
+enum Undo {
+    RemoveUser(String),
+    RestoreApiUser(String, Option<ApiUser>),
+    RestoreApiUserToken(String, Option<ApiUserToken>),
+    ReleaseInvite(String, String),
+}
+
+/// Applies every write at once and remembers how to undo it, for the stores without
+/// transactions.
+pub struct CompensatingUnitOfWork {
+    usermanager: Arc<dyn UserRepository>,
+    apiusermanager: Arc<dyn ApiUserRepository>,
+    invitemanager: Arc<dyn InviteRepository>,
+    undo: Vec<Undo>,
+}
+
+impl CompensatingUnitOfWork {
+    pub fn new(
+        usermanager: Arc<dyn UserRepository>,
+        apiusermanager: Arc<dyn ApiUserRepository>,
+        invitemanager: Arc<dyn InviteRepository>,
+    ) -> Self {
+        Self {
+            usermanager,
+            apiusermanager,
+            invitemanager,
+            undo: Vec::new(),
+        }
+    }
+
+    async fn revert(&self, undo: Undo) -> Result<(), Error> {
+        match undo {
+            Undo::RemoveUser(uuid) => {
+                self.usermanager.remove_user(&uuid).await?;
+            }
+            Undo::RestoreApiUser(_, Some(apiuser)) => {
+                self.apiusermanager.create_apiuser(&apiuser).await?;
+            }
+            Undo::RestoreApiUser(uuid, None) => {
+                self.apiusermanager
+                    .delete_apiuser(None, Some(&uuid))
+                    .await?;
+            }
+            Undo::RestoreApiUserToken(uuid, Some(token)) => {
+                self.apiusermanager.set_token(&uuid, &token).await?;
+            }
+            Undo::RestoreApiUserToken(uuid, None) => {
+                self.apiusermanager.clear_tokens(&uuid).await?;
+            }
+            Undo::ReleaseInvite(id, uuid) => {
+                self.invitemanager.release(&id, &uuid).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for CompensatingUnitOfWork {
+    async fn redeem_invite(&mut self, hash: &str, uuid: &str) -> Result<Option<Invite>, Error> {
+        let invite = self.invitemanager.redeem(hash, uuid).await?;
+        if let Some(invite) = &invite {
+            self.undo
+                .push(Undo::ReleaseInvite(invite.id.clone(), uuid.to_string()));
+        }
+        Ok(invite)
+    }
+
+    async fn create_user(&mut self, user: &User) -> Result<(), Error> {
+        self.usermanager.create_user(user).await?;
+        self.undo.push(Undo::RemoveUser(user.uuid.clone()));
+        Ok(())
+    }
+
+    /// Not undone, a token nobody received cannot be used and just expires.
+    async fn save_token(&mut self, uuid: &str, token: &UserToken) -> Result<(), Error> {
+        let result = self.usermanager.save_token(uuid, token).await?;
+        found(result.matched_count, "user", uuid)
+    }
+
+    async fn create_apiuser(&mut self, apiuser: &ApiUser) -> Result<(), Error> {
+        let previous = self
+            .apiusermanager
+            .get_apiuser(None, Some(&apiuser.uuid))
+            .await?;
+        self.apiusermanager.create_apiuser(apiuser).await?;
+        self.undo
+            .push(Undo::RestoreApiUser(apiuser.uuid.clone(), previous));
+        Ok(())
+    }
+
+    async fn set_apiuser_token(&mut self, uuid: &str, token: &ApiUserToken) -> Result<(), Error> {
+        let previous = self
+            .apiusermanager
+            .get_apiuser(None, Some(uuid))
+            .await?
+            .and_then(|apiuser| apiuser.token);
+        let result = self.apiusermanager.set_token(uuid, token).await?;
+        self.undo
+            .push(Undo::RestoreApiUserToken(uuid.to_string(), previous));
+        found(result.matched_count, "API account", uuid)
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn abort(mut self: Box<Self>) {
+        while let Some(undo) = self.undo.pop() {
+            if let Err(error) = self.revert(undo).await {
+                tracing::error!(%error, "Cannot undo a write of the unit of work");
+            }
+        }
+    }
+}
@@ -1,32 +1,340 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
 use mongodb::{
-    bson::{doc, Document},
-    error::Error,
-    results::{DeleteResult, InsertOneResult, UpdateResult},
-    Collection,
+    bson::{doc, Document, Regex},
+    options::{
+        Collation, CollationStrength, CountOptions, FindOneAndUpdateOptions, FindOneOptions,
+        FindOptions, IndexOptions, ReturnDocument, UpdateOptions,
+    },
+    Collection, IndexModel,
 };
 
-use misato_utils::get_current_timestamp;
+use misato_security::password::Password;
+use misato_utils::{get_current_timestamp, settings::Settings};
 
+use crate::database::{expiry_index, lookup_index, unique_index};
+use crate::error::Error;
 use crate::models::user_model::*;
+use crate::repository::{DeleteResult, UpdateResult, UserRepository};
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    Username,
+    #[default]
+    Created,
+    LastLogin,
+}
+
+/// Usernames are compared ignoring case everywhere, lookups use the collation of their index.
+fn username_collation() -> Collation {
+    Collation::builder()
+        .locale("en".to_string())
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+/// Filters of the admin listing, every one is optional.
+#[derive(Debug, Default, Clone)]
+pub struct UserSearch {
+    pub role: Option<UserRoleType>,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub last_login_after: Option<u64>,
+    pub last_login_before: Option<u64>,
+    /// Case-insensitive.
+    pub username_prefix: Option<String>,
+    /// Case-insensitive.
+    pub include_usernames: Option<Vec<String>>,
+    /// Case-insensitive.
+    pub exclude_usernames: Vec<String>,
+}
+
+impl UserSearch {
+    fn to_document(&self) -> Document {
+        let mut filter = Document::new();
+        if let Some(role) = &self.role {
+            filter.insert("access.role", mongodb::bson::to_bson(role).unwrap());
+        }
+        let range = |after: Option<u64>, before: Option<u64>| {
+            let mut range = Document::new();
+            if let Some(after) = after {
+                range.insert("$gte", after as i64);
+            }
+            if let Some(before) = before {
+                range.insert("$lte", before as i64);
+            }
+            range
+        };
+        let created = range(self.created_after, self.created_before);
+        if !created.is_empty() {
+            filter.insert("timestamp", created);
+        }
+        let last_login = range(self.last_login_after, self.last_login_before);
+        if !last_login.is_empty() {
+            filter.insert("last_login", last_login);
+        }
+        let mut username = Document::new();
+        if let Some(prefix) = &self.username_prefix {
+            let pattern: String = prefix
+                .chars()
+                .flat_map(|c| match c {
+                    '\\' | '^' | '$' | '.' | '|' | '?' | '*' | '+' | '(' | ')' | '[' | ']'
+                    | '{' | '}' => vec!['\\', c],
+                    _ => vec![c],
+                })
+                .collect();
+            username.insert(
+                "$regex",
+                Regex {
+                    pattern: format!("^{}", pattern),
+                    options: "i".to_string(),
+                },
+            );
+        }
+        if let Some(usernames) = &self.include_usernames {
+            username.insert("$in", usernames);
+        }
+        if !self.exclude_usernames.is_empty() {
+            username.insert("$nin", &self.exclude_usernames);
+        }
+        if !username.is_empty() {
+            filter.insert("username", username);
+        }
+        filter
+    }
+
+    /// Same filter as `to_document`, for the stores that cannot run it.
+    pub fn matches(&self, user: &User) -> bool {
+        let in_range = |value: Option<u64>, after: Option<u64>, before: Option<u64>| {
+            if after.is_none() && before.is_none() {
+                return true;
+            }
+            match value {
+                Some(value) => {
+                    after.is_none_or(|after| value >= after)
+                        && before.is_none_or(|before| value <= before)
+                }
+                None => false,
+            }
+        };
+        let username = user.username.to_lowercase();
+        self.role
+            .as_ref()
+            .is_none_or(|role| user.access.role == *role)
+            && in_range(
+                Some(user.timestamp),
+                self.created_after,
+                self.created_before,
+            )
+            && in_range(
+                user.last_login,
+                self.last_login_after,
+                self.last_login_before,
+            )
+            && self
+                .username_prefix
+                .as_ref()
+                .is_none_or(|prefix| username.starts_with(&prefix.to_lowercase()))
+            && self.include_usernames.as_ref().is_none_or(|usernames| {
+                usernames.iter().any(|name| name.to_lowercase() == username)
+            })
+            && !self
+                .exclude_usernames
+                .iter()
+                .any(|name| name.to_lowercase() == username)
+    }
+}
+
+/// The user without its sessions, and the sessions to store apart.
+pub(crate) fn split_sessions(user: &User) -> (User, Vec<UserSession>) {
+    let sessions = user
+        .tokens
+        .iter()
+        .flatten()
+        .map(|token| UserSession::create(&user.uuid, token))
+        .collect();
+    let user = User {
+        tokens: None,
+        ..user.clone()
+    };
+    (user, sessions)
+}
+
+fn valid_session(token: &str) -> Document {
+    doc! {"token": token, "expiration_timestamp": { "$gte": get_current_timestamp() as i64 }}
+}
 
+/// Sessions are kept in their own collection, its TTL index drops them once expired.
+/// The users read one at a time get them back in `tokens`.
+#[derive(Clone)]
 pub struct UserManager {
     pub users: Collection<User>,
+    pub sessions: Collection<UserSession>,
+    /// Seconds between the deletion of an account and its purge.
+    deletion_grace_period: u64,
 }
 
 impl UserManager {
-    pub fn init(users: Collection<User>) -> Self {
-        Self { users }
+    pub fn init(
+        users: Collection<User>,
+        sessions: Collection<UserSession>,
+        settings: &Settings,
+    ) -> Self {
+        Self {
+            users,
+            sessions,
+            deletion_grace_period: settings.deletion_grace_period,
+        }
+    }
+
+    /// Usernames are unique regardless of case, the way `username_exists` compares them,
+    /// and a verified address belongs to a single user.
+    pub async fn create_indexes(&self) -> Result<(), Error> {
+        let collation = username_collation();
+        let username = |keys, unique| {
+            IndexModel::builder()
+                .keys(keys)
+                .options(
+                    IndexOptions::builder()
+                        .unique(unique)
+                        .collation(collation.clone())
+                        .build(),
+                )
+                .build()
+        };
+        self.users
+            .create_indexes(
+                [
+                    unique_index(doc! {"uuid": 1}),
+                    username(doc! {"username": 1}, true),
+                    username(doc! {"previous_usernames.username": 1}, false),
+                    lookup_index(doc! {"email": 1}),
+                    IndexModel::builder()
+                        .keys(doc! {"email": 1, "email_verified": 1})
+                        .options(
+                            IndexOptions::builder()
+                                .unique(true)
+                                .partial_filter_expression(doc! {"email_verified": true})
+                                .build(),
+                        )
+                        .build(),
+                    lookup_index(doc! {"challenges.token": 1}),
+                    lookup_index(doc! {"reset_token.hash": 1}),
+                    lookup_index(doc! {"verification_token.hash": 1}),
+                    lookup_index(doc! {"identities.provider": 1, "identities.subject": 1}),
+                ],
+                None,
+            )
+            .await?;
+        self.sessions
+            .create_indexes(
+                [
+                    unique_index(doc! {"token": 1}),
+                    lookup_index(doc! {"uuid": 1}),
+                    expiry_index(),
+                ],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the sessions earlier versions kept in the `tokens` array of the users to their
+    /// collection, the expired ones are dropped. Applying it twice changes nothing.
+    pub async fn move_sessions(&self) -> Result<(), Error> {
+        let timestamp = get_current_timestamp();
+        let mut cursor = self
+            .users
+            .find(doc! {"tokens": {"$exists": true}}, None)
+            .await?;
+        while cursor.advance().await? {
+            let user: User = cursor.deserialize_current()?;
+            let (_, sessions) = split_sessions(&user);
+            for session in sessions {
+                if session.expiration_timestamp < timestamp {
+                    continue;
+                }
+                let session = mongodb::bson::to_document(&session)?;
+                self.sessions
+                    .update_one(
+                        doc! {"token": session.get_str("token").unwrap_or_default()},
+                        doc! {"$setOnInsert": session},
+                        UpdateOptions::builder().upsert(true).build(),
+                    )
+                    .await?;
+            }
+            self.users
+                .update_one(
+                    doc! {"uuid": &user.uuid},
+                    doc! {"$unset": {"tokens": ""}},
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
     }
 
-    pub async fn username_exists(&self, username: &str) -> Result<bool, Error> {
+    /// Fills `tokens` with the sessions of the user that have not expired.
+    async fn with_sessions(&self, user: Option<User>) -> Result<Option<User>, Error> {
+        let mut user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let mut cursor = self
+            .sessions
+            .find(
+                doc! {"uuid": &user.uuid, "expiration_timestamp": { "$gte": get_current_timestamp() as i64 }},
+                FindOptions::builder().sort(doc! {"_id": 1}).build(),
+            )
+            .await?;
+        let mut tokens = Vec::new();
+        while cursor.advance().await? {
+            tokens.push(cursor.deserialize_current()?.to_token());
+        }
+        user.tokens = if tokens.is_empty() {
+            None
+        } else {
+            Some(tokens)
+        };
+        Ok(Some(user))
+    }
+
+    async fn drop_sessions(&self, uuid: &str) -> Result<u64, Error> {
+        Ok(self
+            .sessions
+            .delete_many(doc! {"uuid": uuid}, None)
+            .await?
+            .deleted_count)
+    }
+
+    fn deleted_status(&self) -> Document {
+        let timestamp = get_current_timestamp();
+        let status = UserStatus::Deleted {
+            timestamp,
+            purge_timestamp: timestamp + self.deletion_grace_period * 1000,
+        };
+        mongodb::bson::to_document(&status).unwrap()
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserManager {
+    async fn username_exists(&self, username: &str) -> Result<bool, Error> {
         Ok(self
             .users
-            .count_documents(doc! { "username": username }, None)
+            .count_documents(
+                doc! { "$or": [{"username": username}, {"previous_usernames.username": username}] },
+                CountOptions::builder()
+                    .collation(username_collation())
+                    .build(),
+            )
             .await?
             != 0)
     }
 
-    pub async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
+    async fn uuid_exists(&self, uuid: &str) -> Result<bool, Error> {
         Ok(self
             .users
             .count_documents(doc! { "uuid": uuid }, None)
@@ -34,12 +342,16 @@ impl UserManager {
             != 0)
     }
 
-    pub async fn create_user(&self, user: &User) -> Result<InsertOneResult, Error> {
-        let target = self.users.insert_one(user, None).await?;
-        Ok(target)
+    async fn create_user(&self, user: &User) -> Result<(), Error> {
+        let (user, sessions) = split_sessions(user);
+        self.users.insert_one(&user, None).await?;
+        if !sessions.is_empty() {
+            self.sessions.insert_many(sessions, None).await?;
+        }
+        Ok(())
     }
 
-    pub async fn get_user(
+    async fn get_user(
         &self,
         username: Option<&str>,
         uuid: Option<&str>,
@@ -54,17 +366,18 @@ impl UserManager {
         if doc.is_empty() {
             return Ok(None);
         }
-        match self.users.find_one(doc, None).await? {
-            Some(user) => Ok(Some(user)),
-            None => Ok(None),
-        }
+        let options = FindOneOptions::builder()
+            .collation(username.map(|_| username_collation()))
+            .build();
+        let user = self.users.find_one(doc, options).await?;
+        self.with_sessions(user).await
     }
 
-    pub async fn delete_user(
+    async fn delete_user(
         &self,
         username: Option<&str>,
         uuid: Option<&str>,
-    ) -> Result<Option<DeleteResult>, Error> {
+    ) -> Result<Option<UpdateResult>, Error> {
         let mut doc: Document = Document::new();
         if uuid.is_some() {
             doc = doc! {"uuid": uuid.unwrap()};
@@ -75,53 +388,557 @@ impl UserManager {
         if doc.is_empty() {
             return Ok(None);
         }
-        Ok(Some(self.users.delete_one(doc, None).await?))
+        doc.insert("status.state", doc! {"$nin": ["deleted", "purged"]});
+        let update = doc! {
+            "$set": {"status": self.deleted_status()},
+            "$unset": {"challenges": ""},
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .collation(username.map(|_| username_collation()))
+            .build();
+        let deleted = match self.users.find_one_and_update(doc, update, options).await? {
+            Some(user) => {
+                self.drop_sessions(&user.uuid).await?;
+                1
+            }
+            None => 0,
+        };
+        Ok(Some(UpdateResult {
+            matched_count: deleted,
+            modified_count: deleted,
+        }))
     }
 
-    pub async fn delete_user_from_token(&self, token: &str) -> Result<Option<DeleteResult>, Error> {
-        Ok(Some(
-            self.users
-                .delete_one(doc! {"tokens.token": token, "tokens.expiration_timestamp": { "$gte": get_current_timestamp() as i64 } }, None)
-                .await?,
-        ))
+    async fn delete_user_from_token(&self, token: &str) -> Result<Option<UpdateResult>, Error> {
+        let session = match self.sessions.find_one(valid_session(token), None).await? {
+            Some(session) => session,
+            None => return Ok(Some(UpdateResult::default())),
+        };
+        let update = doc! {
+            "$set": {"status": self.deleted_status()},
+            "$unset": {"challenges": ""},
+        };
+        let result = self
+            .users
+            .update_one(doc! {"uuid": &session.uuid}, update, None)
+            .await?;
+        self.drop_sessions(&session.uuid).await?;
+        Ok(Some(result.into()))
     }
 
-    pub async fn save_token(&self, uuid: &str, token: &UserToken) -> Result<UpdateResult, Error> {
-        let doc = mongodb::bson::to_document(token).unwrap();
-        let update = doc! {"$push": {"tokens": doc} };
-        Ok(self
+    async fn set_status(&self, uuid: &str, status: &UserStatus) -> Result<UpdateResult, Error> {
+        let mut update = doc! {"$set": {"status": mongodb::bson::to_document(status).unwrap()}};
+        let active = *status == UserStatus::Active;
+        if !active {
+            update.insert("$unset", doc! {"challenges": ""});
+        }
+        let result = self
             .users
+            .update_one(
+                doc! {"uuid": uuid, "status.state": {"$ne": "purged"}},
+                update,
+                None,
+            )
+            .await?;
+        if !active && result.matched_count != 0 {
+            self.drop_sessions(uuid).await?;
+        }
+        Ok(result.into())
+    }
+
+    async fn remove_user(&self, uuid: &str) -> Result<DeleteResult, Error> {
+        let result = self.users.delete_one(doc! {"uuid": uuid}, None).await?;
+        self.drop_sessions(uuid).await?;
+        Ok(result.into())
+    }
+
+    async fn purge_deleted_users(&self) -> Result<Vec<User>, Error> {
+        let timestamp = get_current_timestamp();
+        let mut cursor = self
+            .users
+            .find(
+                doc! {"status.state": "deleted", "status.purge_timestamp": {"$lte": timestamp as i64}},
+                None,
+            )
+            .await?;
+        let mut purged = Vec::new();
+        while cursor.advance().await? {
+            let user: User = cursor.deserialize_current()?;
+            let result = self
+                .users
+                .replace_one(
+                    doc! {"uuid": &user.uuid, "status.state": "deleted"},
+                    user.tombstone(timestamp),
+                    None,
+                )
+                .await?;
+            if result.modified_count == 1 {
+                purged.push(user);
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn count_sessions(&self) -> Result<u64, Error> {
+        self.sessions
+            .count_documents(
+                doc! {"expiration_timestamp": {"$gte": get_current_timestamp() as i64}},
+                None,
+            )
+            .await
+            .map_err(Error::from)
+    }
+
+    async fn search_users(
+        &self,
+        search: &UserSearch,
+        sort: &UserSort,
+        descending: bool,
+        skip: u64,
+        limit: i64,
+    ) -> Result<(Vec<User>, u64), Error> {
+        let filter = search.to_document();
+        let total = self
+            .users
+            .count_documents(
+                filter.clone(),
+                CountOptions::builder()
+                    .collation(username_collation())
+                    .build(),
+            )
+            .await?;
+        let field = match sort {
+            UserSort::Username => "username",
+            UserSort::Created => "timestamp",
+            UserSort::LastLogin => "last_login",
+        };
+        let order = if descending { -1 } else { 1 };
+        let options = FindOptions::builder()
+            .collation(username_collation())
+            .sort(doc! {field: order, "uuid": 1})
+            .skip(skip)
+            .limit(limit)
+            .build();
+        let mut cursor = self.users.find(filter, options).await?;
+        let mut users = Vec::new();
+        while cursor.advance().await? {
+            users.push(cursor.deserialize_current()?);
+        }
+        Ok((users, total))
+    }
+
+    async fn set_last_login(&self, uuid: &str, timestamp: u64) -> Result<UpdateResult, Error> {
+        let update = doc! {"$set": {"last_login": timestamp as i64} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn set_role(&self, uuid: &str, role: &UserRoleType) -> Result<UpdateResult, Error> {
+        let role = mongodb::bson::to_bson(role).unwrap();
+        let update = doc! {"$set": {"access.role": role} };
+        self.users
             .update_one(doc! {"uuid": uuid}, update, None)
-            .await?)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn save_token(&self, uuid: &str, token: &UserToken) -> Result<UpdateResult, Error> {
+        if self
+            .users
+            .count_documents(doc! {"uuid": uuid}, None)
+            .await?
+            == 0
+        {
+            return Ok(UpdateResult::default());
+        }
+        self.sessions
+            .insert_one(UserSession::create(uuid, token), None)
+            .await?;
+        Ok(UpdateResult {
+            matched_count: 1,
+            modified_count: 1,
+        })
     }
 
-    pub async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
-        let update = doc! {"$unset": {"tokens": ""} };
+    async fn add_log(&self, uuid: &str, log: &UserLog) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(log).unwrap();
+        let update = doc! {"$push": {"logs": doc} };
         Ok(self
             .users
             .update_one(doc! {"uuid": uuid}, update, None)
-            .await?)
+            .await?
+            .into())
     }
 
-    pub async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
-        let update = doc! {"$unset": {"tokens": ""} };
-        Ok(self
+    async fn set_totp(&self, uuid: &str, totp: &UserTotp) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(totp).unwrap();
+        let update = doc! {"$set": {"totp": doc} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn clear_totp(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        let update = doc! {"$unset": {"totp": ""} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn use_totp_step(&self, uuid: &str, step: u64) -> Result<UpdateResult, Error> {
+        let update = doc! {"$set": {"totp.last_step": step as i64} };
+        self.users
+            .update_one(
+                doc! {"uuid": uuid, "totp.last_step": { "$lt": step as i64 }},
+                update,
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn remove_recovery_code(&self, uuid: &str, hash: &str) -> Result<UpdateResult, Error> {
+        let update = doc! {"$pull": {"totp.recovery_codes": hash} };
+        self.users
+            .update_one(
+                doc! {"uuid": uuid, "totp.recovery_codes": hash},
+                update,
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn save_challenge(
+        &self,
+        uuid: &str,
+        challenge: &UserToken,
+    ) -> Result<UpdateResult, Error> {
+        let timestamp = get_current_timestamp() as i64;
+        let doc = mongodb::bson::to_document(challenge).unwrap();
+        // Expired challenges are dropped on the way.
+        self.users
+            .update_one(
+                doc! {"uuid": uuid},
+                doc! {"$pull": {"challenges": {"expiration_timestamp": { "$lt": timestamp }}}},
+                None,
+            )
+            .await?;
+        let update = doc! {"$push": {"challenges": doc} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn remove_challenge(&self, uuid: &str, token: &str) -> Result<UpdateResult, Error> {
+        let update = doc! {"$pull": {"challenges": {"token": token}} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn get_user_from_challenge(&self, token: &str) -> Result<Option<User>, Error> {
+        let user = self
+            .users
+            .find_one(
+                doc! {"challenges": {"$elemMatch": {"token": token, "expiration_timestamp": { "$gte": get_current_timestamp() as i64 }}}},
+                None,
+            )
+            .await?;
+        self.with_sessions(user).await
+    }
+
+    async fn get_user_from_email(&self, email: &str) -> Result<Option<User>, Error> {
+        let user = self
+            .users
+            .find_one(doc! {"email": email, "email_verified": true}, None)
+            .await?;
+        self.with_sessions(user).await
+    }
+
+    async fn set_password(&self, uuid: &str, password: &Password) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(password).unwrap();
+        let update = doc! {"$set": {"password": doc} };
+        let result = self
+            .users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await?;
+        if result.matched_count != 0 {
+            self.drop_sessions(uuid).await?;
+        }
+        Ok(result.into())
+    }
+
+    async fn change_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+        keep_token: &str,
+    ) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(password).unwrap();
+        let update = doc! {"$set": {"password": doc} };
+        let result = self
+            .users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await?;
+        if result.matched_count != 0 {
+            self.sessions
+                .delete_many(doc! {"uuid": uuid, "token": {"$ne": keep_token}}, None)
+                .await?;
+        }
+        Ok(result.into())
+    }
+
+    async fn change_username(
+        &self,
+        uuid: &str,
+        previous: &str,
+        username: &str,
+    ) -> Result<UpdateResult, Error> {
+        let history = mongodb::bson::to_document(&UserPreviousUsername {
+            username: previous.to_string(),
+            timestamp: get_current_timestamp(),
+        })
+        .unwrap();
+        let update =
+            doc! {"$set": {"username": username}, "$push": {"previous_usernames": history} };
+        self.users
+            .update_one(doc! {"uuid": uuid, "username": previous}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn rehash_password(
+        &self,
+        uuid: &str,
+        password: &Password,
+    ) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(password).unwrap();
+        let update = doc! {"$set": {"password": doc} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn set_profile(&self, uuid: &str, profile: &UserProfile) -> Result<UpdateResult, Error> {
+        let mut update = Document::new();
+        let mut unset = Document::new();
+        let fields = [
+            ("profile.display_name", &profile.display_name),
+            ("profile.bio", &profile.bio),
+            ("profile.locale", &profile.locale),
+            ("profile.timezone", &profile.timezone),
+        ];
+        for (field, value) in fields {
+            match value {
+                Some(value) => update.insert(field, value),
+                None => unset.insert(field, ""),
+            };
+        }
+        // Every field is either set or unset, so at least one operator is present.
+        let mut changes = Document::new();
+        if !update.is_empty() {
+            changes.insert("$set", update);
+        }
+        if !unset.is_empty() {
+            changes.insert("$unset", unset);
+        }
+        self.users
+            .update_one(doc! {"uuid": uuid}, changes, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn set_avatar(&self, uuid: &str, avatar: Option<&str>) -> Result<UpdateResult, Error> {
+        let update = match avatar {
+            Some(avatar) => doc! {"$set": {"profile.avatar": avatar}},
+            None => doc! {"$unset": {"profile.avatar": ""}},
+        };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn get_user_from_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, Error> {
+        let user = self
             .users
-            .update_one(doc! {"tokens.token": token, "tokens.expiration_timestamp": { "$gte": get_current_timestamp() as i64 } }, update, None)
-            .await?)
+            .find_one(
+                doc! {"identities": {"$elemMatch": {"provider": provider, "subject": subject}}},
+                None,
+            )
+            .await?;
+        self.with_sessions(user).await
     }
 
-    pub async fn get_user_from_token(&self, token: &str) -> Result<Option<User>, Error> {
-        match self
+    async fn add_identity(
+        &self,
+        uuid: &str,
+        identity: &UserIdentity,
+    ) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(identity).unwrap();
+        self.users
+            .update_one(
+                doc! {
+                    "uuid": uuid,
+                    "identities": {"$not": {"$elemMatch": {"provider": &identity.provider, "subject": &identity.subject}}},
+                },
+                doc! {"$push": {"identities": doc}},
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn remove_identity(
+        &self,
+        uuid: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<UpdateResult, Error> {
+        self.users
+            .update_one(
+                doc! {"uuid": uuid},
+                doc! {"$pull": {"identities": {"provider": provider, "subject": subject}}},
+                None,
+            )
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn set_reset_token(
+        &self,
+        uuid: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(token).unwrap();
+        let update = doc! {"$set": {"reset_token": doc} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn get_user_from_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let user = self
             .users
             .find_one(
-                doc! {"tokens.token": token, "tokens.expiration_timestamp": { "$gte": get_current_timestamp() as i64 } },
+                doc! {"reset_token.hash": hash, "reset_token.expiration_timestamp": { "$gte": get_current_timestamp() as i64 }},
                 None,
             )
-            .await?
-        {
-            Some(user) => Ok(Some(user)),
-            None => Ok(None),
+            .await?;
+        self.with_sessions(user).await
+    }
+
+    async fn consume_reset_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let user = self
+            .users
+            .find_one_and_update(
+                doc! {"reset_token.hash": hash, "reset_token.expiration_timestamp": { "$gte": get_current_timestamp() as i64 }},
+                doc! {"$unset": {"reset_token": ""}},
+                None,
+            )
+            .await?;
+        self.with_sessions(user).await
+    }
+
+    async fn set_email(
+        &self,
+        uuid: &str,
+        email: &str,
+        token: &UserSecretToken,
+    ) -> Result<UpdateResult, Error> {
+        let doc = mongodb::bson::to_document(token).unwrap();
+        let update =
+            doc! {"$set": {"email": email, "email_verified": false, "verification_token": doc} };
+        self.users
+            .update_one(doc! {"uuid": uuid}, update, None)
+            .await
+            .map(UpdateResult::from)
+            .map_err(Error::from)
+    }
+
+    async fn consume_verification_token(&self, hash: &str) -> Result<Option<User>, Error> {
+        let timestamp = get_current_timestamp() as i64;
+        let user = self
+            .users
+            .find_one(
+                doc! {"verification_token.hash": hash, "verification_token.expiration_timestamp": { "$gte": timestamp }},
+                None,
+            )
+            .await?;
+        let email = match user.and_then(|user| user.verification_token?.email) {
+            Some(email) => email,
+            None => return Ok(None),
+        };
+        // The address must still be the one the token was sent to.
+        let user = self
+            .users
+            .find_one_and_update(
+                doc! {"verification_token.hash": hash, "email": &email},
+                doc! {"$set": {"email_verified": true}, "$unset": {"verification_token": ""}},
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?;
+        self.with_sessions(user).await
+    }
+
+    async fn clear_tokens(&self, uuid: &str) -> Result<UpdateResult, Error> {
+        let matched_count = self
+            .users
+            .count_documents(doc! {"uuid": uuid}, None)
+            .await?;
+        let removed = self.drop_sessions(uuid).await?;
+        Ok(UpdateResult {
+            matched_count,
+            modified_count: (removed != 0) as u64,
+        })
+    }
+
+    async fn clear_tokens_from_token(&self, token: &str) -> Result<UpdateResult, Error> {
+        match self.sessions.find_one(valid_session(token), None).await? {
+            Some(session) => self.clear_tokens(&session.uuid).await,
+            None => Ok(UpdateResult::default()),
         }
     }
+
+    async fn get_user_from_token(&self, token: &str) -> Result<Option<User>, Error> {
+        let session = match self.sessions.find_one(valid_session(token), None).await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        let user = self
+            .users
+            .find_one(doc! {"uuid": &session.uuid}, None)
+            .await?;
+        self.with_sessions(user).await
+    }
 }
@@ -0,0 +1,29 @@
+use sqlx::any::AnyPoolOptions;
+
+use misato_database::Error;
+
+#[tokio::test]
+async fn unclassified_sql_errors_are_not_transient() {
+    let pool = AnyPoolOptions::new()
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let error = sqlx::query("SELECT * FROM missing_table")
+        .execute(&pool)
+        .await
+        .unwrap_err();
+    assert!(matches!(Error::from(error), Error::Invalid(_)));
+
+    pool.close().await;
+    let error = sqlx::query("SELECT 1").execute(&pool).await.unwrap_err();
+    assert!(matches!(Error::from(error), Error::Unavailable(_)));
+}
+
+#[test]
+fn only_network_mongodb_errors_are_transient() {
+    let error = mongodb::error::Error::from(std::io::ErrorKind::ConnectionReset);
+    assert!(matches!(Error::from(error), Error::Unavailable(_)));
+
+    let error = mongodb::error::Error::custom("unexpected");
+    assert!(matches!(Error::from(error), Error::Invalid(_)));
+}
@@ -0,0 +1,26 @@
+use misato_database::models::loginattempt_model::LoginAttempt;
+
+#[test]
+fn no_lockout_below_the_attempts() {
+    assert_eq!(LoginAttempt::lockout_duration(0, 5, 60, 3600), 0);
+    assert_eq!(LoginAttempt::lockout_duration(4, 5, 60, 3600), 0);
+}
+
+#[test]
+fn lockout_doubles_on_every_extra_failure() {
+    assert_eq!(LoginAttempt::lockout_duration(5, 5, 60, 3600), 60);
+    assert_eq!(LoginAttempt::lockout_duration(6, 5, 60, 3600), 120);
+    assert_eq!(LoginAttempt::lockout_duration(7, 5, 60, 3600), 240);
+    assert_eq!(LoginAttempt::lockout_duration(10, 5, 60, 3600), 1920);
+}
+
+#[test]
+fn lockout_is_capped() {
+    assert_eq!(LoginAttempt::lockout_duration(11, 5, 60, 3600), 3600);
+    assert_eq!(LoginAttempt::lockout_duration(40, 5, 60, 3600), 3600);
+    assert_eq!(LoginAttempt::lockout_duration(u32::MAX, 5, 60, 3600), 3600);
+    assert_eq!(
+        LoginAttempt::lockout_duration(u32::MAX, 5, u64::MAX, u64::MAX),
+        u64::MAX
+    );
+}
@@ -0,0 +1,1143 @@
+//! Runs the same scenarios against every backend, they must all behave alike.
+//!
+//! The in-memory store and an in-process SQLite database are always tested, MongoDB and
+//! PostgreSQL only when `MISATO_TEST_MONGODB_URI` or `MISATO_TEST_POSTGRES_URL` is set.
+
+use std::env;
+
+use uuid::Uuid;
+
+use misato_database::database::*;
+use misato_database::models::{
+    apiuser_model::*, export_model::*, invite_model::*, oauth_model::*, user_model::*,
+};
+use misato_database::user_manager::{UserSearch, UserSort};
+use misato_database::Error;
+use misato_security::{hash_token, password::Password};
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+async fn backends() -> Vec<(&'static str, Database)> {
+    let settings = Settings {
+        deletion_grace_period: 0,
+        sql_url: "sqlite::memory:".to_string(),
+        ..Settings::init()
+    };
+    let mut backends = vec![
+        ("memory", Database::memory(&settings)),
+        ("sqlite", Database::sql(&settings).await.unwrap()),
+    ];
+    if let Ok(uri) = env::var("MISATO_TEST_MONGODB_URI") {
+        let settings = Settings {
+            mongodb_uri: uri,
+            mongodb_name: "misato_parity".to_string(),
+            ..settings.clone()
+        };
+        backends.push(("mongodb", Database::mongodb(&settings).await.unwrap()));
+    }
+    if let Ok(url) = env::var("MISATO_TEST_POSTGRES_URL") {
+        let settings = Settings {
+            sql_url: url,
+            ..settings.clone()
+        };
+        backends.push(("postgres", Database::sql(&settings).await.unwrap()));
+    }
+    backends
+}
+
+/// Unique across runs, shared databases keep what earlier runs left.
+fn unique(name: &str) -> String {
+    format!("{}-{}", name, &Uuid::new_v4().simple().to_string()[..12])
+}
+
+fn user(username: &str) -> User {
+    User::create(username.to_string(), Password::default(), None)
+}
+
+fn token(seconds: i64) -> UserToken {
+    UserToken {
+        token: unique("token"),
+        timestamp: get_current_timestamp(),
+        expiration_timestamp: (get_current_timestamp() as i64 + seconds * 1000) as u64,
+    }
+}
+
+#[tokio::test]
+async fn users_are_found_by_name_uuid_and_token() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let name = unique("Misato");
+        let created = user(&name);
+        users.create_user(&created).await.unwrap();
+
+        assert!(
+            users.username_exists(&name.to_uppercase()).await.unwrap(),
+            "{}",
+            backend
+        );
+        assert!(
+            users.uuid_exists(&created.uuid).await.unwrap(),
+            "{}",
+            backend
+        );
+        let found = users.get_user(Some(&name), None).await.unwrap();
+        assert_eq!(found.as_ref(), Some(&created), "{}", backend);
+        let found = users.get_user(None, Some(&created.uuid)).await.unwrap();
+        assert_eq!(found.as_ref(), Some(&created), "{}", backend);
+        let found = users
+            .get_user(Some(&name.to_lowercase()), None)
+            .await
+            .unwrap();
+        assert_eq!(found.as_ref(), Some(&created), "{}", backend);
+
+        let valid = token(60);
+        let expired = token(-60);
+        users.save_token(&created.uuid, &valid).await.unwrap();
+        users.save_token(&created.uuid, &expired).await.unwrap();
+        let found = users.get_user_from_token(&valid.token).await.unwrap();
+        assert_eq!(
+            found.map(|user| user.uuid),
+            Some(created.uuid.clone()),
+            "{}",
+            backend
+        );
+        let found = users.get_user_from_token(&expired.token).await.unwrap();
+        assert_eq!(found, None, "{}", backend);
+
+        let result = users.clear_tokens_from_token(&valid.token).await.unwrap();
+        assert_eq!(result.modified_count, 1, "{}", backend);
+        let found = users.get_user_from_token(&valid.token).await.unwrap();
+        assert_eq!(found, None, "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn updates_count_matches_and_changes() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("ritsuko"));
+        users.create_user(&created).await.unwrap();
+
+        let result = users
+            .set_role(&created.uuid, &UserRoleType::Admin)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            UpdateResult {
+                matched_count: 1,
+                modified_count: 1
+            },
+            "{}",
+            backend
+        );
+        let result = users
+            .set_role(&created.uuid, &UserRoleType::Admin)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            UpdateResult {
+                matched_count: 1,
+                modified_count: 0
+            },
+            "{}",
+            backend
+        );
+        let result = users
+            .set_role(&unique("nobody"), &UserRoleType::Admin)
+            .await
+            .unwrap();
+        assert_eq!(result, UpdateResult::default(), "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn former_usernames_stay_taken() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let previous = unique("shinji");
+        let renamed = unique("ikari");
+        let created = user(&previous);
+        users.create_user(&created).await.unwrap();
+
+        let result = users
+            .change_username(&created.uuid, &previous, &renamed)
+            .await
+            .unwrap();
+        assert_eq!(result.modified_count, 1, "{}", backend);
+        assert!(
+            users.username_exists(&previous).await.unwrap(),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .get_user(Some(&previous), None)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .get_user(Some(&renamed), None)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+
+        // Renaming again from the stale username does nothing.
+        let result = users
+            .change_username(&created.uuid, &previous, &unique("third"))
+            .await
+            .unwrap();
+        assert_eq!(result.matched_count, 0, "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn usernames_are_unique_regardless_of_case() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let name = unique("Asuka");
+        let first = user(&name);
+        users.create_user(&first).await.unwrap();
+
+        let error = users
+            .create_user(&user(&name.to_uppercase()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::DuplicateKey(_)), "{}", backend);
+        let error = users.create_user(&first).await.unwrap_err();
+        assert!(matches!(error, Error::DuplicateKey(_)), "{}", backend);
+
+        let second = user(&unique("langley"));
+        users.create_user(&second).await.unwrap();
+        let error = users
+            .change_username(&second.uuid, &second.username, &name.to_lowercase())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::DuplicateKey(_)), "{}", backend);
+        assert!(
+            users
+                .get_user(Some(&second.username), None)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn secret_tokens_are_consumed_once() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("maya"));
+        users.create_user(&created).await.unwrap();
+
+        let (_, reset) = UserSecretToken::create(60, None);
+        users.set_reset_token(&created.uuid, &reset).await.unwrap();
+        assert!(
+            users
+                .get_user_from_reset_token(&reset.hash)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .consume_reset_token(&reset.hash)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .consume_reset_token(&reset.hash)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+
+        let email = format!("{}@nerv.jp", unique("maya"));
+        let (_, verification) = UserSecretToken::create(60, Some(email.clone()));
+        users
+            .set_email(&created.uuid, &email, &verification)
+            .await
+            .unwrap();
+        // Unverified addresses may belong to someone else.
+        assert!(
+            users.get_user_from_email(&email).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+        let verified = users
+            .consume_verification_token(&verification.hash)
+            .await
+            .unwrap();
+        assert!(
+            matches!(verified, Some(user) if user.email_verified),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .consume_verification_token(&verification.hash)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+        let found = users.get_user_from_email(&email).await.unwrap();
+        assert_eq!(
+            found.map(|user| user.uuid),
+            Some(created.uuid.clone()),
+            "{}",
+            backend
+        );
+
+        let other = user(&unique("Ritsuko"));
+        users.create_user(&other).await.unwrap();
+        let (_, verification) = UserSecretToken::create(60, Some(email.clone()));
+        users
+            .set_email(&other.uuid, &email, &verification)
+            .await
+            .unwrap();
+        assert!(
+            matches!(
+                users.consume_verification_token(&verification.hash).await,
+                Err(Error::DuplicateKey(_))
+            ),
+            "{}",
+            backend
+        );
+        // Nor can an account be created with it verified.
+        let mut another = user(&unique("Maya"));
+        another.email = Some(email.clone());
+        another.email_verified = true;
+        assert!(
+            matches!(
+                users.create_user(&another).await,
+                Err(Error::DuplicateKey(_))
+            ),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn totp_steps_are_used_once() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("hyuga"));
+        users.create_user(&created).await.unwrap();
+        let totp = UserTotp {
+            secret: "secret".to_string(),
+            enabled: true,
+            ..Default::default()
+        };
+        users.set_totp(&created.uuid, &totp).await.unwrap();
+
+        assert_eq!(
+            users
+                .use_totp_step(&created.uuid, 10)
+                .await
+                .unwrap()
+                .matched_count,
+            1,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            users
+                .use_totp_step(&created.uuid, 10)
+                .await
+                .unwrap()
+                .matched_count,
+            0,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            users
+                .use_totp_step(&created.uuid, 9)
+                .await
+                .unwrap()
+                .matched_count,
+            0,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            users
+                .use_totp_step(&created.uuid, 11)
+                .await
+                .unwrap()
+                .matched_count,
+            1,
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn recovery_codes_are_used_once() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("hyuga"));
+        users.create_user(&created).await.unwrap();
+        let totp = UserTotp {
+            secret: "secret".to_string(),
+            enabled: true,
+            recovery_codes: vec![hash_token("first"), hash_token("second")],
+            ..Default::default()
+        };
+        users.set_totp(&created.uuid, &totp).await.unwrap();
+
+        for (code, modified) in [("first", 1), ("first", 0), ("unknown", 0)] {
+            let result = users
+                .remove_recovery_code(&created.uuid, &hash_token(code))
+                .await
+                .unwrap();
+            assert_eq!(result.modified_count, modified, "{}", backend);
+        }
+        let found = users
+            .get_user(None, Some(&created.uuid))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            found.totp.unwrap().recovery_codes,
+            vec![hash_token("second")],
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn identities_are_linked_once() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("aoba"));
+        users.create_user(&created).await.unwrap();
+        let identity = UserIdentity {
+            provider: "nerv".to_string(),
+            subject: unique("subject"),
+            email: None,
+            timestamp: get_current_timestamp(),
+        };
+
+        assert_eq!(
+            users
+                .add_identity(&created.uuid, &identity)
+                .await
+                .unwrap()
+                .modified_count,
+            1,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            users
+                .add_identity(&created.uuid, &identity)
+                .await
+                .unwrap()
+                .matched_count,
+            0,
+            "{}",
+            backend
+        );
+        let found = users
+            .get_user_from_identity("nerv", &identity.subject)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.map(|user| user.uuid),
+            Some(created.uuid.clone()),
+            "{}",
+            backend
+        );
+
+        users
+            .remove_identity(&created.uuid, "nerv", &identity.subject)
+            .await
+            .unwrap();
+        let found = users
+            .get_user_from_identity("nerv", &identity.subject)
+            .await
+            .unwrap();
+        assert_eq!(found, None, "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn deleted_users_are_purged() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let name = unique("kaji");
+        let mut created = user(&name);
+        let session = created.new_token(60);
+        users.create_user(&created).await.unwrap();
+
+        let result = users.delete_user(Some(&name), None).await.unwrap();
+        assert_eq!(
+            result.map(|result| result.modified_count),
+            Some(1),
+            "{}",
+            backend
+        );
+        assert!(
+            users
+                .get_user_from_token(&session.token)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+        let result = users.delete_user(Some(&name), None).await.unwrap();
+        assert_eq!(
+            result.map(|result| result.matched_count),
+            Some(0),
+            "{}",
+            backend
+        );
+
+        // The grace period is 0, the account is due right away.
+        assert!(
+            users
+                .purge_deleted_users()
+                .await
+                .unwrap()
+                .iter()
+                .any(|purged| purged.uuid == created.uuid),
+            "{}",
+            backend
+        );
+        let purged = users
+            .get_user(None, Some(&created.uuid))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            matches!(purged.status, UserStatus::Purged { .. }),
+            "{}",
+            backend
+        );
+        assert!(!users.username_exists(&name).await.unwrap(), "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn only_unexpired_sessions_are_counted() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        // Relative, shared databases keep the sessions of earlier runs.
+        let before = users.count_sessions().await.unwrap();
+        let created = user(&unique("Kaworu"));
+        users.create_user(&created).await.unwrap();
+        for seconds in [60, 3600, -60] {
+            users
+                .save_token(&created.uuid, &token(seconds))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            users.count_sessions().await.unwrap(),
+            before + 2,
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn saving_a_session_drops_expired_ones() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let created = user(&unique("Misato"));
+        users.create_user(&created).await.unwrap();
+        let expired = token(-60);
+        let valid = token(60);
+        users.save_token(&created.uuid, &expired).await.unwrap();
+        users.save_token(&created.uuid, &valid).await.unwrap();
+        let found = users
+            .get_user(None, Some(&created.uuid))
+            .await
+            .unwrap()
+            .unwrap();
+        let tokens: Vec<_> = found.tokens.unwrap_or_default();
+        assert_eq!(tokens, vec![valid], "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn searches_sort_and_page() {
+    for (backend, database) in backends().await {
+        let users = &database.usermanager;
+        let prefix = unique("pilot");
+        for (index, name) in ["c", "a", "b"].iter().enumerate() {
+            let mut created = user(&format!("{}-{}", prefix, name));
+            created.timestamp = index as u64;
+            users.create_user(&created).await.unwrap();
+        }
+        let search = UserSearch {
+            username_prefix: Some(prefix.to_uppercase()),
+            ..Default::default()
+        };
+
+        let (page, total) = users
+            .search_users(&search, &UserSort::Username, false, 1, 1)
+            .await
+            .unwrap();
+        assert_eq!(total, 3, "{}", backend);
+        assert_eq!(page[0].username, format!("{}-b", prefix), "{}", backend);
+
+        let (page, _) = users
+            .search_users(&search, &UserSort::Created, true, 0, 0)
+            .await
+            .unwrap();
+        let names: Vec<_> = page.iter().map(|user| user.username.clone()).collect();
+        let expected: Vec<_> = ["b", "a", "c"]
+            .iter()
+            .map(|name| format!("{}-{}", prefix, name))
+            .collect();
+        assert_eq!(names, expected, "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn apiusers_are_replaced_and_deleted() {
+    for (backend, database) in backends().await {
+        let apiusers = &database.apiusermanager;
+        let mut apiuser = ApiUser::create(Uuid::new_v4().to_string());
+
+        assert_eq!(
+            apiusers
+                .create_apiuser(&apiuser)
+                .await
+                .unwrap()
+                .matched_count,
+            0,
+            "{}",
+            backend
+        );
+        let token = apiuser.new_token(60);
+        assert_eq!(
+            apiusers
+                .create_apiuser(&apiuser)
+                .await
+                .unwrap()
+                .modified_count,
+            1,
+            "{}",
+            backend
+        );
+        let found = apiusers.get_apiuser_from_token(&token.token).await.unwrap();
+        assert_eq!(found.as_ref(), Some(&apiuser), "{}", backend);
+        let found = apiusers
+            .get_apiuser(None, Some(&apiuser.uuid))
+            .await
+            .unwrap();
+        assert_eq!(found.as_ref(), Some(&apiuser), "{}", backend);
+
+        apiusers.clear_tokens(&apiuser.uuid).await.unwrap();
+        assert!(
+            apiusers
+                .get_apiuser_from_token(&token.token)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+
+        let result = apiusers
+            .delete_apiuser(None, Some(&apiuser.uuid))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.map(|result| result.deleted_count),
+            Some(1),
+            "{}",
+            backend
+        );
+        assert!(
+            !apiusers.uuid_exists(&apiuser.uuid).await.unwrap(),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn login_failures_lock_the_key() {
+    for (backend, database) in backends().await {
+        let attempts = &database.loginattemptmanager;
+        let key = format!("account:{}", unique("gendo"));
+        let settings = Settings::init();
+
+        for _ in 0..settings.login_max_attempts {
+            attempts.register_failure(&key).await.unwrap();
+        }
+        assert!(
+            attempts.get_lock(&key).await.unwrap().is_some(),
+            "{}",
+            backend
+        );
+        let locked = attempts.get_locked_keys("account:").await.unwrap();
+        assert!(
+            locked.contains(&key["account:".len()..].to_string()),
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            attempts.clear_failures(&key).await.unwrap().deleted_count,
+            1,
+            "{}",
+            backend
+        );
+        assert!(
+            attempts.get_lock(&key).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn invites_are_redeemed_up_to_their_uses() {
+    for (backend, database) in backends().await {
+        let invites = &database.invitemanager;
+        let (_, invite) = Invite::create(2, None, UserAccess::default());
+        invites.create_invite(&invite).await.unwrap();
+
+        let first = invites.redeem(&invite.hash, "first").await.unwrap();
+        assert_eq!(first.map(|invite| invite.uses), Some(1), "{}", backend);
+        assert!(
+            invites
+                .redeem(&invite.hash, "second")
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+        assert!(
+            invites
+                .redeem(&invite.hash, "third")
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            invites
+                .release(&invite.id, "second")
+                .await
+                .unwrap()
+                .modified_count,
+            1,
+            "{}",
+            backend
+        );
+        assert!(
+            invites
+                .redeem(&invite.hash, "third")
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            invites
+                .delete_invite(&invite.id)
+                .await
+                .unwrap()
+                .deleted_count,
+            1,
+            "{}",
+            backend
+        );
+        assert!(
+            invites
+                .redeem(&invite.hash, "fourth")
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn concurrent_redemptions_respect_max_uses() {
+    for (backend, database) in backends().await {
+        let invites = &database.invitemanager;
+        let (_, invite) = Invite::create(2, None, UserAccess::default());
+        invites.create_invite(&invite).await.unwrap();
+
+        let results = tokio::join!(
+            invites.redeem(&invite.hash, "first"),
+            invites.redeem(&invite.hash, "second"),
+            invites.redeem(&invite.hash, "third"),
+            invites.redeem(&invite.hash, "fourth"),
+        );
+        let redeemed = [results.0, results.1, results.2, results.3]
+            .into_iter()
+            .filter(|result| matches!(result, Ok(Some(_))))
+            .count();
+        assert_eq!(redeemed, 2, "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn units_of_work_commit_or_leave_nothing() {
+    for (backend, database) in backends().await {
+        let (_, invite) = Invite::create(1, None, UserAccess::default());
+        database.invitemanager.create_invite(&invite).await.unwrap();
+
+        let aborted = user(&unique("toji"));
+        let mut work = database.begin().await.unwrap();
+        assert!(
+            work.redeem_invite(&invite.hash, &aborted.uuid)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+        work.create_user(&aborted).await.unwrap();
+        work.save_token(&aborted.uuid, &token(60)).await.unwrap();
+        work.create_apiuser(&ApiUser::create(aborted.uuid.clone()))
+            .await
+            .unwrap();
+        work.abort().await;
+
+        let users = &database.usermanager;
+        assert!(
+            !users.uuid_exists(&aborted.uuid).await.unwrap(),
+            "{}",
+            backend
+        );
+        assert!(
+            !users.username_exists(&aborted.username).await.unwrap(),
+            "{}",
+            backend
+        );
+        assert!(
+            !database
+                .apiusermanager
+                .uuid_exists(&aborted.uuid)
+                .await
+                .unwrap(),
+            "{}",
+            backend
+        );
+
+        // The invite use was given back.
+        let committed = user(&unique("yui"));
+        let session = token(60);
+        let mut work = database.begin().await.unwrap();
+        assert!(
+            work.redeem_invite(&invite.hash, &committed.uuid)
+                .await
+                .unwrap()
+                .is_some(),
+            "{}",
+            backend
+        );
+        work.create_user(&committed).await.unwrap();
+        work.save_token(&committed.uuid, &session).await.unwrap();
+        work.commit().await.unwrap();
+
+        // A write matching nothing fails instead of committing half done.
+        let mut work = database.begin().await.unwrap();
+        let error = work.save_token(&unique("nobody"), &token(60)).await;
+        assert!(matches!(error, Err(Error::NotFound(_))), "{}", backend);
+        work.abort().await;
+
+        let found = users.get_user_from_token(&session.token).await.unwrap();
+        assert_eq!(
+            found.map(|user| user.uuid),
+            Some(committed.uuid),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_single_export_is_pending_per_user() {
+    for (backend, database) in backends().await {
+        let exports = &database.exportmanager;
+        let uuid = Uuid::new_v4().to_string();
+        let export = Export::create(&uuid, 60);
+        exports.create_export(&export).await.unwrap();
+        let error = exports
+            .create_export(&Export::create(&uuid, 60))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::DuplicateKey(_)), "{}", backend);
+        exports
+            .create_export(&Export::create(&Uuid::new_v4().to_string(), 60))
+            .await
+            .unwrap();
+
+        exports
+            .set_status(&export.id, &ExportStatus::Ready, Some(42))
+            .await
+            .unwrap();
+        exports
+            .create_export(&Export::create(&uuid, 60))
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn stale_pending_exports_are_failed() {
+    for (backend, database) in backends().await {
+        let exports = &database.exportmanager;
+        let uuid = Uuid::new_v4().to_string();
+        let export = Export::create(&uuid, 60);
+        exports.create_export(&export).await.unwrap();
+
+        let result = exports
+            .fail_stale_export(&uuid, export.timestamp)
+            .await
+            .unwrap();
+        assert_eq!(result.modified_count, 0, "{}", backend);
+        let result = exports
+            .fail_stale_export(&uuid, export.timestamp + 1)
+            .await
+            .unwrap();
+        assert_eq!(result.modified_count, 1, "{}", backend);
+        let failed = exports.get_latest_export(&uuid).await.unwrap().unwrap();
+        assert_eq!(failed.status, ExportStatus::Failed, "{}", backend);
+        exports
+            .create_export(&Export::create(&uuid, 60))
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn exports_are_downloaded_once() {
+    for (backend, database) in backends().await {
+        let exports = &database.exportmanager;
+        let uuid = Uuid::new_v4().to_string();
+        let export = Export::create(&uuid, 60);
+        exports.create_export(&export).await.unwrap();
+
+        assert!(
+            exports.consume_export(&export.id).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+        exports
+            .set_status(&export.id, &ExportStatus::Ready, Some(42))
+            .await
+            .unwrap();
+        let latest = exports.get_latest_export(&uuid).await.unwrap().unwrap();
+        assert_eq!(latest.size, Some(42), "{}", backend);
+        assert!(
+            exports.consume_export(&export.id).await.unwrap().is_some(),
+            "{}",
+            backend
+        );
+        assert!(
+            exports.consume_export(&export.id).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+
+        let expired = Export::create(&uuid, 0);
+        exports.create_export(&expired).await.unwrap();
+        let removed = exports.remove_expired().await.unwrap();
+        assert!(
+            removed.iter().any(|export| export.id == expired.id),
+            "{}",
+            backend
+        );
+        assert!(
+            !removed.iter().any(|removed| removed.id == export.id),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn oauth_grants_cascade() {
+    for (backend, database) in backends().await {
+        let oauth = &database.oauthmanager;
+        let owner = Uuid::new_v4().to_string();
+        let uuid = Uuid::new_v4().to_string();
+        let (_, client) = OAuthClient::create(
+            &owner,
+            "Magi".to_string(),
+            vec!["https://magi.example/callback".to_string()],
+            vec!["profile".to_string(), "email".to_string()],
+            true,
+        );
+        oauth.create_client(&client).await.unwrap();
+        assert_eq!(
+            oauth.get_clients(&owner).await.unwrap(),
+            vec![client.clone()],
+            "{}",
+            backend
+        );
+
+        let scopes = ["profile".to_string()];
+        oauth
+            .add_consent(&uuid, &client.client_id, &scopes)
+            .await
+            .unwrap();
+        let consent = oauth
+            .add_consent(
+                &uuid,
+                &client.client_id,
+                &["email".to_string(), "profile".to_string()],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(consent.scopes, vec!["profile", "email"], "{}", backend);
+
+        let (_, code) = OAuthCode::create(
+            &client.client_id,
+            &uuid,
+            "https://magi.example/callback",
+            scopes.to_vec(),
+            "challenge",
+            60,
+        );
+        oauth.save_code(&code).await.unwrap();
+        assert!(
+            oauth.consume_code(&code.hash).await.unwrap().is_some(),
+            "{}",
+            backend
+        );
+        assert!(
+            oauth.consume_code(&code.hash).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+
+        let (_, token) = OAuthToken::create(
+            &client.client_id,
+            &uuid,
+            scopes.to_vec(),
+            ApiUserAccess::default(),
+            60,
+        );
+        oauth.save_token(&token).await.unwrap();
+        assert_eq!(
+            oauth
+                .revoke_token(&token.hash, "another")
+                .await
+                .unwrap()
+                .deleted_count,
+            0,
+            "{}",
+            backend
+        );
+        assert!(
+            oauth.get_token(&token.hash).await.unwrap().is_some(),
+            "{}",
+            backend
+        );
+
+        assert_eq!(
+            oauth
+                .delete_client(&uuid, &client.client_id)
+                .await
+                .unwrap()
+                .deleted_count,
+            0,
+            "{}",
+            backend
+        );
+        assert_eq!(
+            oauth
+                .delete_client(&owner, &client.client_id)
+                .await
+                .unwrap()
+                .deleted_count,
+            1,
+            "{}",
+            backend
+        );
+        assert!(
+            oauth.get_token(&token.hash).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+        assert!(
+            oauth.get_consents(&uuid).await.unwrap().is_empty(),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[tokio::test]
+async fn revoking_a_consent_revokes_its_tokens() {
+    for (backend, database) in backends().await {
+        let oauth = &database.oauthmanager;
+        let uuid = Uuid::new_v4().to_string();
+        let client_id = Uuid::new_v4().to_string();
+        let scopes = vec!["profile".to_string()];
+        oauth.add_consent(&uuid, &client_id, &scopes).await.unwrap();
+        let (_, token) =
+            OAuthToken::create(&client_id, &uuid, scopes, ApiUserAccess::default(), 60);
+        oauth.save_token(&token).await.unwrap();
+
+        assert_eq!(
+            oauth
+                .revoke_consent(&uuid, &client_id)
+                .await
+                .unwrap()
+                .deleted_count,
+            1,
+            "{}",
+            backend
+        );
+        assert!(
+            oauth
+                .get_consent(&uuid, &client_id)
+                .await
+                .unwrap()
+                .is_none(),
+            "{}",
+            backend
+        );
+        assert!(
+            oauth.get_token(&token.hash).await.unwrap().is_none(),
+            "{}",
+            backend
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use uuid::Uuid;
+
+use misato_database::database::Database;
+use misato_database::models::user_model::User;
+use misato_security::password::Password;
+use misato_utils::settings::Settings;
+
+/// A file, every connection to an in-memory SQLite database would open a new one.
+#[tokio::test]
+async fn sql_units_of_work_are_hidden_until_committed() {
+    let path = std::env::temp_dir().join(format!("misato-work-{}.db", Uuid::new_v4().simple()));
+    let settings = Settings {
+        sql_url: format!("sqlite://{}?mode=rwc", path.display()),
+        ..Settings::init()
+    };
+    let database = Database::sql(&settings).await.unwrap();
+    let users = &database.usermanager;
+
+    let user = User::create("asuka".to_string(), Password::default(), None);
+    let mut work = database.begin().await.unwrap();
+    work.create_user(&user).await.unwrap();
+    assert!(!users.uuid_exists(&user.uuid).await.unwrap());
+    work.commit().await.unwrap();
+    assert!(users.uuid_exists(&user.uuid).await.unwrap());
+
+    let _ = std::fs::remove_file(&path);
+}
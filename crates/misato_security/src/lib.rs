@@ -1,6 +1,12 @@
+use data_encoding::HEXLOWER;
+use hmac::{Hmac, Mac};
 use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
 
 pub mod password;
+pub mod pkce;
+pub mod pow;
+pub mod totp;
 
 pub fn generate_token(size: usize) -> String {
     rand::thread_rng()
@@ -9,3 +15,46 @@ pub fn generate_token(size: usize) -> String {
         .map(char::from)
         .collect()
 }
+
+/// Fingerprint of a random token, for tokens that are stored but must be looked up.
+/// Unlike passwords they carry enough entropy to not need a salt.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::*;
+///
+/// let token = generate_token(64);
+/// assert_eq!(hash_token(&token), hash_token(&token));
+/// assert_eq!(hash_token(&token) != token, true);
+/// ```
+pub fn hash_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+/// HMAC-SHA256 of `message`, to hand out links that cannot be forged.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::*;
+///
+/// let signature = sign("secret", "export:1700000000000");
+/// assert_eq!(verify_signature("secret", "export:1700000000000", &signature), true);
+/// assert_eq!(verify_signature("secret", "export:1700000000001", &signature), false);
+/// assert_eq!(verify_signature("other", "export:1700000000000", &signature), false);
+/// ```
+pub fn sign(secret: &str, message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(message.as_bytes());
+    HEXLOWER.encode(&mac.finalize().into_bytes())
+}
+
+/// Compares in constant time.
+pub fn verify_signature(secret: &str, message: &str, signature: &str) -> bool {
+    let signature = match HEXLOWER.decode(signature.as_bytes()) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(message.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
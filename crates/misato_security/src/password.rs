@@ -1,8 +1,83 @@
+use data_encoding::HEXUPPER;
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Cost of argon2id hashes, stored in every PHC string.
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PasswordParams {
+    /// Memory in KiB.
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl Default for PasswordParams {
+    fn default() -> Self {
+        Self {
+            mem_cost: 19 * 1024,
+            time_cost: 2,
+            lanes: 1,
+        }
+    }
+}
+
+impl PasswordParams {
+    fn config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            hash_length: 32,
+            ..argon2::Config::default()
+        }
+    }
+
+    /// Fails with the reason argon2 would refuse these costs.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// assert_eq!(PasswordParams::default().check(), Ok(()));
+    /// assert_eq!(PasswordParams { mem_cost: 8, time_cost: 1, lanes: 2 }.check().is_err(), true);
+    /// ```
+    pub fn check(&self) -> Result<(), String> {
+        if self.lanes == 0 || self.lanes > 0xFF_FFFF {
+            return Err(format!("parallelism must be between 1 and {}", 0xFF_FFFF));
+        }
+        if self.time_cost == 0 {
+            return Err("time cost must be at least 1".to_string());
+        }
+        if self.mem_cost < 8 * self.lanes {
+            return Err(format!(
+                "memory cost must be at least 8 KiB per lane, {} KiB",
+                8 * self.lanes
+            ));
+        }
+        Ok(())
+    }
+
+    /// True if any cost is below the one of `other`.
+    pub fn is_weaker_than(&self, other: &PasswordParams) -> bool {
+        self.mem_cost < other.mem_cost
+            || self.time_cost < other.time_cost
+            || self.lanes < other.lanes
+    }
+}
 
 #[derive(Eq, Hash, PartialEq, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Password {
+    /// PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub phc: String,
+    /// Raw argon2i hash with default parameters, only found on old accounts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub salt: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub hash: Vec<u8>,
 }
 
@@ -11,24 +86,53 @@ pub fn generate_salt(size: usize) -> Vec<u8> {
     random_bytes
 }
 
+/// Verify a plain text password against a throwaway hash.
+/// Always returns false, but costs the same as a real verification so callers
+/// can answer unknown usernames in the same time as wrong passwords.
+/// The throwaway hash is built once, with the parameters of the first call.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::password::*;
+///
+/// assert_eq!(verify_dummy_password(b"anypassword", &PasswordParams::default()), false);
+/// ```
+pub fn verify_dummy_password(password: &[u8], params: &PasswordParams) -> bool {
+    static DUMMY: OnceLock<Password> = OnceLock::new();
+    let dummy = DUMMY.get_or_init(|| Password::hash_password_with(&generate_salt(32), params));
+    // The dummy is built from random bytes, a match is not a valid login anyway.
+    let _ = dummy.is_correct_password(password);
+    false
+}
+
 impl Password {
-    /// Random salt is generated everytime this function is called.
-    /// Hash is always different in that case.
+    /// Hash with the default parameters, see [`Password::hash_password_with`].
     /// Basic usage:
     ///
     /// ```
     /// use misato_security::password::*;
     ///
     /// let encrypted_password = Password::hash_password(b"anypassword");
-    /// let same_password = Password::hash_password(b"anypassword");
-    /// assert_eq!(same_password.salt != encrypted_password.salt, true);
-    /// assert_eq!(same_password.hash != encrypted_password.hash, true);
+    /// assert_eq!(encrypted_password.phc.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"), true);
     /// ```
     pub fn hash_password(password: &[u8]) -> Self {
-        let salt = generate_salt(256);
-        let hash = argon2::hash_raw(password, &salt, &argon2::Config::default()).unwrap();
+        Self::hash_password_with(password, &PasswordParams::default())
+    }
 
-        Self { salt, hash }
+    /// Random salt is generated everytime this function is called.
+    /// Hash is always different in that case.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// let params = PasswordParams::default();
+    /// let encrypted_password = Password::hash_password_with(b"anypassword", &params);
+    /// let same_password = Password::hash_password_with(b"anypassword", &params);
+    /// assert_eq!(same_password.phc != encrypted_password.phc, true);
+    /// ```
+    pub fn hash_password_with(password: &[u8], params: &PasswordParams) -> Self {
+        Self::hash_password_salt(&generate_salt(16), password, params)
     }
 
     /// You have to provide the salt.
@@ -38,23 +142,22 @@ impl Password {
     /// ```
     /// use misato_security::password::*;
     ///
-    /// let salt = generate_salt(256); // 256 bytes salt
-    /// let encrypted_password = Password::hash_password_salt(&salt, b"anypassword");
-    /// let same_password = Password::hash_password_salt(&salt, b"anypassword");
-    /// let another_password = Password::hash_password_salt(&salt, b"anotherpassword");
-    ///
-    /// assert_eq!(encrypted_password.salt == same_password.salt, true);
-    /// assert_eq!(encrypted_password.salt == another_password.salt, true);
+    /// let params = PasswordParams::default();
+    /// let salt = generate_salt(16); // 16 bytes salt
+    /// let encrypted_password = Password::hash_password_salt(&salt, b"anypassword", &params);
+    /// let same_password = Password::hash_password_salt(&salt, b"anypassword", &params);
+    /// let another_password = Password::hash_password_salt(&salt, b"anotherpassword", &params);
     ///
-    /// assert_eq!(encrypted_password.hash == same_password.hash, true);
-    /// assert_eq!(encrypted_password.hash == another_password.hash, false);
+    /// assert_eq!(encrypted_password.phc == same_password.phc, true);
+    /// assert_eq!(encrypted_password.phc == another_password.phc, false);
     /// ```
-    pub fn hash_password_salt(salt: &[u8], password: &[u8]) -> Password {
-        let hash = argon2::hash_raw(password, &salt, &argon2::Config::default()).unwrap();
+    pub fn hash_password_salt(salt: &[u8], password: &[u8], params: &PasswordParams) -> Password {
+        let phc = argon2::hash_encoded(password, salt, &params.config())
+            .expect("argon2 parameters are checked when the settings are loaded");
 
         Password {
-            salt: salt.iter().cloned().collect(),
-            hash,
+            phc,
+            ..Default::default()
         }
     }
 
@@ -69,9 +172,222 @@ impl Password {
     /// assert_eq!(encrypted_password.is_correct_password(b"anotherpassword"), false);
     /// ```
     pub fn is_correct_password(&self, password: &[u8]) -> bool {
-        match argon2::verify_raw(password, &self.salt, &self.hash, &argon2::Config::default()) {
-            Ok(result) => return result,
-            Err(_) => false,
+        let result = if self.phc.is_empty() {
+            argon2::verify_raw(password, &self.salt, &self.hash, &argon2::Config::default())
+        } else {
+            argon2::verify_encoded(&self.phc, password)
+        };
+        result.unwrap_or(false)
+    }
+
+    /// Variant and parameters read from the PHC string, `None` for raw hashes.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// let params = PasswordParams { mem_cost: 4096, time_cost: 3, lanes: 1 };
+    /// let encrypted_password = Password::hash_password_with(b"anypassword", &params);
+    /// assert_eq!(encrypted_password.params(), Some(("argon2id".to_string(), params)));
+    /// ```
+    pub fn params(&self) -> Option<(String, PasswordParams)> {
+        let mut fields = self.phc.split('$').skip(1);
+        let variant = fields.next()?.to_string();
+        let mut params = fields.nth(1)?.split(',').map(|param| param.split_once('='));
+        let mut next_value = |key: &str| match params.next()? {
+            Some((name, value)) if name == key => value.parse().ok(),
+            _ => None,
+        };
+        Some((
+            variant,
+            PasswordParams {
+                mem_cost: next_value("m")?,
+                time_cost: next_value("t")?,
+                lanes: next_value("p")?,
+            },
+        ))
+    }
+
+    /// True when the hash was made with a weaker algorithm or cheaper parameters
+    /// than `params`, so it should be replaced on the next successful login.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// let weak = PasswordParams { mem_cost: 4096, time_cost: 1, lanes: 1 };
+    /// let encrypted_password = Password::hash_password_with(b"anypassword", &weak);
+    /// assert_eq!(encrypted_password.needs_rehash(&weak), false);
+    /// assert_eq!(encrypted_password.needs_rehash(&PasswordParams::default()), true);
+    /// ```
+    pub fn needs_rehash(&self, params: &PasswordParams) -> bool {
+        match self.params() {
+            Some((variant, current)) => variant != "argon2id" || current.is_weaker_than(params),
+            None => true,
+        }
+    }
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum PasswordRule {
+    TooShort(usize),
+    TooLong(usize),
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    ContainsUsername,
+    Breached,
+}
+
+impl PasswordRule {
+    pub fn message(&self) -> String {
+        match self {
+            PasswordRule::TooShort(length) => format!("at least {} characters", length),
+            PasswordRule::TooLong(length) => format!("at most {} characters", length),
+            PasswordRule::MissingLowercase => "at least one lowercase letter".to_string(),
+            PasswordRule::MissingUppercase => "at least one uppercase letter".to_string(),
+            PasswordRule::MissingDigit => "at least one digit".to_string(),
+            PasswordRule::MissingSymbol => "at least one symbol".to_string(),
+            PasswordRule::ContainsUsername => "must not contain the username".to_string(),
+            PasswordRule::Breached => "must not appear in a known data breach".to_string(),
+        }
+    }
+}
+
+/// Offline lookup in a breached password list split by SHA-1 prefix,
+/// the layout of the Have I Been Pwned range API: one file per 5 hex characters
+/// prefix, named after it, holding `SUFFIX:COUNT` lines.
+#[derive(Debug, Clone)]
+pub struct BreachedPasswords {
+    pub directory: PathBuf,
+}
+
+impl BreachedPasswords {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// A missing prefix file means no breached password starts with that prefix.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// let directory = std::env::temp_dir().join("misato-breached-doctest");
+    /// std::fs::create_dir_all(&directory).unwrap();
+    /// // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    /// std::fs::write(directory.join("5BAA6"), "1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n").unwrap();
+    ///
+    /// let breached = BreachedPasswords::new(&directory);
+    /// assert_eq!(breached.contains("password").unwrap(), true);
+    /// assert_eq!(breached.contains("correct horse battery staple").unwrap(), false);
+    /// ```
+    pub fn contains(&self, password: &str) -> std::io::Result<bool> {
+        let hash = HEXUPPER.encode(&Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        let content = match std::fs::read_to_string(self.directory.join(prefix)) {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        Ok(content.lines().any(|line| {
+            let candidate = line.split(':').next().unwrap_or("");
+            candidate.trim().eq_ignore_ascii_case(suffix)
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Bounds the work given to argon2 by a single request.
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub forbid_username: bool,
+    pub breached: Option<BreachedPasswords>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            forbid_username: true,
+            breached: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// True when verifying `password` against a hash is not worth it, no accepted password
+    /// is that long.
+    pub fn is_too_long(&self, password: &str) -> bool {
+        password.chars().count() > self.max_length
+    }
+
+    /// Returns every rule the password breaks, empty when it is accepted.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::password::*;
+    ///
+    /// let policy = PasswordPolicy {
+    ///     require_digit: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(policy.check("misato", "correct horse 1"), vec![]);
+    /// assert_eq!(
+    ///     policy.check("misato", "misato"),
+    ///     vec![PasswordRule::TooShort(8), PasswordRule::MissingDigit, PasswordRule::ContainsUsername]
+    /// );
+    /// ```
+    pub fn check(&self, username: &str, password: &str) -> Vec<PasswordRule> {
+        let mut rules = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            rules.push(PasswordRule::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            rules.push(PasswordRule::TooLong(self.max_length));
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            rules.push(PasswordRule::MissingLowercase);
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            rules.push(PasswordRule::MissingUppercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            rules.push(PasswordRule::MissingDigit);
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            rules.push(PasswordRule::MissingSymbol);
+        }
+        if self.forbid_username
+            && !username.is_empty()
+            && password.to_lowercase().contains(&username.to_lowercase())
+        {
+            rules.push(PasswordRule::ContainsUsername);
+        }
+        // Not worth hashing a password already refused for its length.
+        if length <= self.max_length {
+            if let Some(breached) = &self.breached {
+                match breached.contains(password) {
+                    Ok(true) => rules.push(PasswordRule::Breached),
+                    Ok(false) => {}
+                    Err(error) => tracing::error!(%error, "Cannot read breached passwords"),
+                }
+            }
         }
+        rules
     }
 }
@@ -0,0 +1,25 @@
+use data_encoding::BASE64URL_NOPAD;
+use sha2::{Digest, Sha256};
+
+use crate::generate_token;
+
+/// Random code verifier, 64 characters out of the 43 to 128 allowed by RFC 7636.
+pub fn generate_verifier() -> String {
+    generate_token(64)
+}
+
+/// S256 code challenge sent with the authorization request.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::pkce::*;
+///
+/// // RFC 7636 appendix B
+/// assert_eq!(
+///     code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+///     "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+/// );
+/// ```
+pub fn code_challenge(verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()))
+}
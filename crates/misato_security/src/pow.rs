@@ -0,0 +1,106 @@
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+
+use misato_utils::bounded::BoundedMap;
+
+use crate::generate_token;
+
+/// Challenges kept at most, the oldest are forgotten first.
+const CHALLENGES_SIZE: usize = 100_000;
+
+/// Number of leading zero bits of a hash.
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// True if SHA-256(`challenge:nonce`) starts with `difficulty` zero bits.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::pow::*;
+///
+/// let nonce = (0u64..).find(|nonce| is_valid_proof("challenge", &nonce.to_string(), 8)).unwrap();
+/// assert_eq!(is_valid_proof("challenge", &nonce.to_string(), 8), true);
+/// assert_eq!(is_valid_proof("challenge", "anything", 0), true);
+/// ```
+pub fn is_valid_proof(challenge: &str, nonce: &str, difficulty: u32) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+    leading_zero_bits(&hash) >= difficulty
+}
+
+#[derive(Debug, Clone)]
+pub struct PowChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expiration_timestamp: u64,
+}
+
+/// Issues single-use proof-of-work challenges, kept in memory until solved or pushed out
+/// by newer ones.
+pub struct ProofOfWork {
+    pub difficulty: u32,
+    /// Lifetime of a challenge in milliseconds.
+    pub duration: u64,
+    challenges: Mutex<BoundedMap<String, u64>>,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32, duration: u64) -> Self {
+        Self {
+            difficulty,
+            duration,
+            challenges: Mutex::new(BoundedMap::new(CHALLENGES_SIZE)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.difficulty > 0
+    }
+
+    pub fn issue(&self, timestamp: u64) -> PowChallenge {
+        let challenge = generate_token(32);
+        let expiration_timestamp = timestamp + self.duration;
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(challenge.clone(), expiration_timestamp);
+        PowChallenge {
+            challenge,
+            difficulty: self.difficulty,
+            expiration_timestamp,
+        }
+    }
+
+    /// Consumes the challenge when the nonce solves it.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_security::pow::*;
+    ///
+    /// let pow = ProofOfWork::new(4, 60_000);
+    /// let issued = pow.issue(0);
+    /// let nonce = (0u64..)
+    ///     .map(|nonce| nonce.to_string())
+    ///     .find(|nonce| is_valid_proof(&issued.challenge, nonce, issued.difficulty))
+    ///     .unwrap();
+    /// assert_eq!(pow.verify(&issued.challenge, &nonce, 1), true);
+    /// assert_eq!(pow.verify(&issued.challenge, &nonce, 1), false);
+    /// ```
+    pub fn verify(&self, challenge: &str, nonce: &str, timestamp: u64) -> bool {
+        if !is_valid_proof(challenge, nonce, self.difficulty) {
+            return false;
+        }
+        match self.challenges.lock().unwrap().remove(challenge) {
+            Some(expiration) => expiration >= timestamp,
+            None => false,
+        }
+    }
+}
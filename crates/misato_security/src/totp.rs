@@ -0,0 +1,116 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::Sha1;
+
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_PERIOD: u64 = 30;
+
+/// 160 bits, the size recommended by RFC 4226.
+pub fn generate_secret() -> String {
+    let random_bytes: Vec<u8> = (0..20).map(|_| rand::random::<u8>()).collect();
+    BASE32_NOPAD.encode(&random_bytes)
+}
+
+/// Single-use codes given to the user to log in without their authenticator.
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect()
+        })
+        .collect()
+}
+
+/// Time step a timestamp (in seconds) belongs to.
+pub fn time_step(timestamp: u64) -> u64 {
+    timestamp / TOTP_PERIOD
+}
+
+/// Compute the code of a base32 secret for a time step.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::totp::*;
+///
+/// // RFC 6238 test vector: "12345678901234567890" at T = 59
+/// let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+/// assert_eq!(generate_code(secret, time_step(59)), Some("287082".to_string()));
+/// assert_eq!(generate_code("not base32!", 0), None);
+/// ```
+pub fn generate_code(secret: &str, step: u64) -> Option<String> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).ok()?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(format!(
+        "{:0width$}",
+        binary % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    ))
+}
+
+/// Check a code against the steps around `timestamp` (in seconds) to tolerate clock drift.
+/// Returns the matching step so callers can refuse to accept it twice.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::totp::*;
+///
+/// let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+/// assert_eq!(verify_code(secret, "287082", 59, 1), Some(time_step(59)));
+/// assert_eq!(verify_code(secret, "287082", 59 + TOTP_PERIOD, 1), Some(time_step(59)));
+/// assert_eq!(verify_code(secret, "287082", 59 + 2 * TOTP_PERIOD, 1), None);
+/// ```
+pub fn verify_code(secret: &str, code: &str, timestamp: u64, window: u64) -> Option<u64> {
+    let code = code.trim();
+    let step = time_step(timestamp);
+    (step.saturating_sub(window)..=step + window)
+        .find(|step| generate_code(secret, *step).as_deref() == Some(code))
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// URI understood by authenticator apps, usually shown as a QR code.
+/// Basic usage:
+///
+/// ```
+/// use misato_security::totp::*;
+///
+/// assert_eq!(
+///     otpauth_uri("Misato", "misato user", "JBSWY3DPEHPK3PXP"),
+///     "otpauth://totp/Misato:misato%20user?secret=JBSWY3DPEHPK3PXP&issuer=Misato&algorithm=SHA1&digits=6&period=30"
+/// );
+/// ```
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_uri_component(issuer),
+        encode_uri_component(account),
+        secret,
+        encode_uri_component(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
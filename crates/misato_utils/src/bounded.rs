@@ -0,0 +1,100 @@
+//! Map for state that unauthenticated requests create, forgetting the least recently used
+//! entries beyond a fixed size so that nobody can make it grow without bound.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+pub struct BoundedMap<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (u64, V)>,
+    /// Keys by the tick they were last used at, the first one is evicted first.
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> BoundedMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Marks `key` as the most recently used, if present.
+    fn touch(&mut self, key: &K) -> bool {
+        let tick = self.tick + 1;
+        match self.entries.get_mut(key) {
+            Some((used, _)) => {
+                self.recency.remove(used);
+                *used = tick;
+                self.recency.insert(tick, key.clone());
+                self.tick = tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if !self.touch(key) {
+            return None;
+        }
+        self.entries.get_mut(key).map(|(_, value)| value)
+    }
+
+    /// Evicts the least recently used entries when full.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (self.tick, value));
+        previous
+    }
+
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V) -> &mut V {
+        if !self.touch(&key) {
+            self.insert(key.clone(), default());
+        }
+        &mut self.entries.get_mut(&key).unwrap().1
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (used, value) = self.entries.remove(key)?;
+        self.recency.remove(&used);
+        Some(value)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        let recency = &mut self.recency;
+        self.entries.retain(|key, (used, value)| {
+            let kept = keep(key, value);
+            if !kept {
+                recency.remove(used);
+            }
+            kept
+        });
+    }
+}
@@ -1,6 +1,8 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod bounded;
 pub mod settings;
+pub mod username;
 
 pub fn get_current_timestamp() -> u64 {
     SystemTime::now()
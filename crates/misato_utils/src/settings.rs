@@ -1,11 +1,117 @@
 use dotenv::dotenv;
 use std::env;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitQuota {
+    pub capacity: u32,
+    pub period: u64,
+}
+
+impl FromStr for RateLimitQuota {
+    type Err = String;
+
+    /// Parses quotas written as `capacity/period`, e.g. `10/60` for 10 requests per minute.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (capacity, period) = s
+            .split_once('/')
+            .ok_or(format!("[{}]: Expected `capacity/period`.", s))?;
+        Ok(Self {
+            capacity: capacity
+                .trim()
+                .parse()
+                .map_err(|_| format!("[{}]: Invalid capacity.", s))?,
+            period: period
+                .trim()
+                .parse()
+                .map_err(|_| format!("[{}]: Invalid period.", s))?,
+        })
+    }
+}
+
+/// External OpenID Connect provider, configured with `MISATO_OIDC_<NAME>_*` variables.
+#[derive(Clone, Debug)]
+pub struct OidcProviderSettings {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: String,
+}
+
+fn parse_env<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(v) => v.parse().unwrap_or(default),
+        Err(_) => default,
+    }
+}
 
 #[derive(Clone)]
 pub struct Settings {
+    pub database_backend: String,
+    pub sql_url: String,
     pub mongodb_uri: String,
     pub mongodb_name: String,
+    pub migrate_on_startup: bool,
+    /// Milliseconds after which a database query is logged as slow.
+    pub slow_query_threshold: u64,
+    /// Milliseconds before the first reconnection when the database is down on startup,
+    /// doubled after each failure up to `database_retry_max_delay`.
+    pub database_retry_delay: u64,
+    pub database_retry_max_delay: u64,
+    /// Milliseconds the readiness probe waits for the database.
+    pub health_timeout: u64,
     pub admin_token: String,
+    pub login_max_attempts: u32,
+    pub login_lockout_duration: u64,
+    pub login_lockout_max_duration: u64,
+    pub ratelimit_login: RateLimitQuota,
+    pub ratelimit_signup: RateLimitQuota,
+    pub ratelimit_write: RateLimitQuota,
+    pub ratelimit_read: RateLimitQuota,
+    /// Comma separated networks of the reverse proxies whose `X-Real-IP` header is believed.
+    pub trusted_proxies: String,
+    pub totp_issuer: String,
+    pub public_url: String,
+    /// Web client the mails link to, its pages post the tokens to the API.
+    pub frontend_url: String,
+    pub mailer: String,
+    pub mail_from: String,
+    pub mail_directory: String,
+    pub mail_templates: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub password_require_lowercase: bool,
+    pub password_require_uppercase: bool,
+    pub password_require_digit: bool,
+    pub password_require_symbol: bool,
+    pub password_forbid_username: bool,
+    pub password_breached_directory: String,
+    pub argon2_memory_cost: u32,
+    pub argon2_time_cost: u32,
+    pub argon2_parallelism: u32,
+    pub registration: String,
+    pub invite_codes: String,
+    pub signup_pow_difficulty: u32,
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub username_reserved: String,
+    pub avatar_directory: String,
+    pub avatar_max_size: u64,
+    pub deletion_grace_period: u64,
+    pub purge_interval: u64,
+    pub export_directory: String,
+    pub export_secret: String,
+    pub oidc_providers: Vec<OidcProviderSettings>,
+    pub log_format: String,
+    pub log_level: String,
+    pub metrics_token: String,
+    /// Comma separated networks allowed to scrape the metrics without the token.
+    pub metrics_networks: String,
 }
 
 impl Settings {
@@ -27,9 +133,111 @@ impl Settings {
             ),
         };
         Self {
+            database_backend: parse_env("MISATO_DATABASE_BACKEND", "mongodb".to_string()),
+            sql_url: parse_env("MISATO_SQL_URL", "sqlite://misato.db?mode=rwc".to_string()),
             mongodb_uri: mongodb_uri,
             mongodb_name: mongodb_name,
+            migrate_on_startup: parse_env("MISATO_MIGRATE_ON_STARTUP", true),
+            slow_query_threshold: parse_env("MISATO_SLOW_QUERY_THRESHOLD", 100),
+            database_retry_delay: parse_env("MISATO_DATABASE_RETRY_DELAY", 1000),
+            database_retry_max_delay: parse_env("MISATO_DATABASE_RETRY_MAX_DELAY", 60 * 1000),
+            health_timeout: parse_env("MISATO_HEALTH_TIMEOUT", 2000),
             admin_token: admin_token,
+            login_max_attempts: parse_env("MISATO_LOGIN_MAX_ATTEMPTS", 5),
+            login_lockout_duration: parse_env("MISATO_LOGIN_LOCKOUT_DURATION", 30),
+            login_lockout_max_duration: parse_env("MISATO_LOGIN_LOCKOUT_MAX_DURATION", 60 * 60),
+            ratelimit_login: parse_env(
+                "MISATO_RATELIMIT_LOGIN",
+                RateLimitQuota {
+                    capacity: 10,
+                    period: 60,
+                },
+            ),
+            ratelimit_signup: parse_env(
+                "MISATO_RATELIMIT_SIGNUP",
+                RateLimitQuota {
+                    capacity: 5,
+                    period: 60 * 60,
+                },
+            ),
+            ratelimit_write: parse_env(
+                "MISATO_RATELIMIT_WRITE",
+                RateLimitQuota {
+                    capacity: 60,
+                    period: 60,
+                },
+            ),
+            ratelimit_read: parse_env(
+                "MISATO_RATELIMIT_READ",
+                RateLimitQuota {
+                    capacity: 300,
+                    period: 60,
+                },
+            ),
+            trusted_proxies: parse_env("MISATO_TRUSTED_PROXIES", String::new()),
+            totp_issuer: parse_env("MISATO_TOTP_ISSUER", "Misato".to_string()),
+            public_url: parse_env("MISATO_PUBLIC_URL", "http://localhost:8080".to_string()),
+            frontend_url: parse_env("MISATO_FRONTEND_URL", "http://localhost:3000".to_string()),
+            mailer: parse_env("MISATO_MAILER", "stdout".to_string()),
+            mail_from: parse_env("MISATO_MAIL_FROM", "Misato <noreply@localhost>".to_string()),
+            mail_directory: parse_env("MISATO_MAIL_DIRECTORY", "mails".to_string()),
+            mail_templates: parse_env("MISATO_MAIL_TEMPLATES", "templates/mail".to_string()),
+            smtp_host: parse_env("MISATO_SMTP_HOST", "localhost".to_string()),
+            smtp_port: parse_env("MISATO_SMTP_PORT", 465),
+            smtp_username: parse_env("MISATO_SMTP_USERNAME", String::new()),
+            smtp_password: parse_env("MISATO_SMTP_PASSWORD", String::new()),
+            password_min_length: parse_env("MISATO_PASSWORD_MIN_LENGTH", 8),
+            password_max_length: parse_env("MISATO_PASSWORD_MAX_LENGTH", 128),
+            password_require_lowercase: parse_env("MISATO_PASSWORD_REQUIRE_LOWERCASE", false),
+            password_require_uppercase: parse_env("MISATO_PASSWORD_REQUIRE_UPPERCASE", false),
+            password_require_digit: parse_env("MISATO_PASSWORD_REQUIRE_DIGIT", false),
+            password_require_symbol: parse_env("MISATO_PASSWORD_REQUIRE_SYMBOL", false),
+            password_forbid_username: parse_env("MISATO_PASSWORD_FORBID_USERNAME", true),
+            password_breached_directory: parse_env(
+                "MISATO_PASSWORD_BREACHED_DIRECTORY",
+                String::new(),
+            ),
+            argon2_memory_cost: parse_env("MISATO_ARGON2_MEMORY_COST", 19 * 1024),
+            argon2_time_cost: parse_env("MISATO_ARGON2_TIME_COST", 2),
+            argon2_parallelism: parse_env("MISATO_ARGON2_PARALLELISM", 1),
+            registration: parse_env("MISATO_REGISTRATION", "open".to_string()),
+            invite_codes: parse_env("MISATO_INVITE_CODES", String::new()),
+            signup_pow_difficulty: parse_env("MISATO_SIGNUP_POW_DIFFICULTY", 0),
+            username_min_length: parse_env("MISATO_USERNAME_MIN_LENGTH", 3),
+            username_max_length: parse_env("MISATO_USERNAME_MAX_LENGTH", 32),
+            username_reserved: parse_env(
+                "MISATO_USERNAME_RESERVED",
+                "admin,administrator,root,system,misato,api,support".to_string(),
+            ),
+            avatar_directory: parse_env("MISATO_AVATAR_DIRECTORY", "avatars".to_string()),
+            avatar_max_size: parse_env("MISATO_AVATAR_MAX_SIZE", 1024 * 1024),
+            deletion_grace_period: parse_env("MISATO_DELETION_GRACE_PERIOD", 30 * 24 * 60 * 60),
+            purge_interval: parse_env("MISATO_PURGE_INTERVAL", 60 * 60),
+            export_directory: parse_env("MISATO_EXPORT_DIRECTORY", "exports".to_string()),
+            export_secret: parse_env("MISATO_EXPORT_SECRET", String::new()),
+            oidc_providers: parse_env("MISATO_OIDC_PROVIDERS", String::new())
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    let key =
+                        |suffix: &str| format!("MISATO_OIDC_{}_{}", name.to_uppercase(), suffix);
+                    OidcProviderSettings {
+                        issuer: parse_env(&key("ISSUER"), String::new()),
+                        client_id: parse_env(&key("CLIENT_ID"), String::new()),
+                        client_secret: parse_env(&key("CLIENT_SECRET"), String::new()),
+                        scopes: parse_env(&key("SCOPES"), "openid email profile".to_string()),
+                        name,
+                    }
+                })
+                .collect(),
+            log_format: parse_env("MISATO_LOG_FORMAT", "json".to_string()),
+            log_level: parse_env("MISATO_LOG_LEVEL", "info,_=warn,rocket=warn".to_string()),
+            metrics_token: parse_env("MISATO_METRICS_TOKEN", String::new()),
+            metrics_networks: parse_env(
+                "MISATO_METRICS_NETWORKS",
+                "127.0.0.1/32,::1/128".to_string(),
+            ),
         }
     }
 }
@@ -0,0 +1,95 @@
+#[derive(Eq, Hash, PartialEq, Debug, Clone)]
+pub enum UsernameRule {
+    TooShort(usize),
+    TooLong(usize),
+    InvalidCharacters,
+    InvalidStart,
+    Reserved,
+}
+
+impl UsernameRule {
+    pub fn message(&self) -> String {
+        match self {
+            UsernameRule::TooShort(length) => format!("at least {} characters", length),
+            UsernameRule::TooLong(length) => format!("at most {} characters", length),
+            UsernameRule::InvalidCharacters => {
+                "only letters, digits, '_', '-' and '.' are allowed".to_string()
+            }
+            UsernameRule::InvalidStart => "must start with a letter or a digit".to_string(),
+            UsernameRule::Reserved => "this username is reserved".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UsernamePolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Compared case-insensitively.
+    pub reserved: Vec<String>,
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 3,
+            max_length: 32,
+            reserved: [
+                "admin",
+                "administrator",
+                "root",
+                "system",
+                "misato",
+                "api",
+                "support",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl UsernamePolicy {
+    /// Returns every rule the username breaks, empty when it is accepted.
+    /// Basic usage:
+    ///
+    /// ```
+    /// use misato_utils::username::*;
+    ///
+    /// let policy = UsernamePolicy::default();
+    /// assert_eq!(policy.check("Misato_Katsuragi"), vec![]);
+    /// assert_eq!(policy.check("Admin"), vec![UsernameRule::Reserved]);
+    /// assert_eq!(
+    ///     policy.check("_a b"),
+    ///     vec![UsernameRule::InvalidCharacters, UsernameRule::InvalidStart]
+    /// );
+    /// ```
+    pub fn check(&self, username: &str) -> Vec<UsernameRule> {
+        let mut rules = Vec::new();
+        let length = username.chars().count();
+        if length < self.min_length {
+            rules.push(UsernameRule::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            rules.push(UsernameRule::TooLong(self.max_length));
+        }
+        if !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        {
+            rules.push(UsernameRule::InvalidCharacters);
+        }
+        if !matches!(username.chars().next(), Some(c) if c.is_ascii_alphanumeric()) {
+            rules.push(UsernameRule::InvalidStart);
+        }
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(username))
+        {
+            rules.push(UsernameRule::Reserved);
+        }
+        rules
+    }
+}
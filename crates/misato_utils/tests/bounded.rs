@@ -0,0 +1,33 @@
+use misato_utils::bounded::BoundedMap;
+
+#[test]
+fn least_recently_used_is_evicted() {
+    let mut map = BoundedMap::new(2);
+    map.insert("a", 1);
+    map.insert("b", 2);
+    *map.get_mut(&"a").unwrap() += 10;
+    map.insert("c", 3);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get_mut(&"a"), Some(&mut 11));
+    assert_eq!(map.get_mut(&"b"), None);
+    assert_eq!(map.get_mut(&"c"), Some(&mut 3));
+}
+
+#[test]
+fn size_stays_bounded() {
+    let mut map = BoundedMap::new(100);
+    for key in 0..10_000 {
+        *map.get_or_insert_with(key, || 0) += 1;
+    }
+    assert_eq!(map.len(), 100);
+    assert_eq!(map.get_mut(&9_999), Some(&mut 1));
+    assert_eq!(map.get_mut(&0), None);
+
+    map.retain(|key, _| key % 2 == 0);
+    assert_eq!(map.len(), 50);
+    for key in 10_000..10_050 {
+        map.insert(key, 0);
+    }
+    assert_eq!(map.len(), 100);
+    assert_eq!(map.remove(&9_998), Some(1));
+}
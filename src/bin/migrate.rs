@@ -0,0 +1,66 @@
+//! Manages the document migrations of the MongoDB database set in the environment.
+//!
+//! ```text
+//! cargo run --bin migrate -- list
+//! cargo run --bin migrate -- apply
+//! cargo run --bin migrate -- rollback users
+//! ```
+
+use std::env;
+use std::process::exit;
+
+use misato_database::database::Database;
+use misato_database::migration;
+use misato_utils::settings::Settings;
+
+const USAGE: &str = "usage: migrate list | apply | rollback <collection>";
+
+#[rocket::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let settings = Settings::init();
+    misato_api::logging::init(&Settings {
+        log_format: "text".to_string(),
+        ..settings.clone()
+    });
+    let db = match Database::open_mongodb(&settings).await {
+        Ok(db) => db,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["list"] => migration::status(&db).await.map(|statuses| {
+            for status in statuses {
+                let applied = match status.applied {
+                    Some(timestamp) => format!("applied at {}", timestamp),
+                    None => "not applied".to_string(),
+                };
+                println!(
+                    "{}:{} {} ({}, {} documents pending)",
+                    status.collection, status.version, status.name, applied, status.pending
+                );
+            }
+        }),
+        ["apply"] => migration::apply(&db).await.map(|applied| {
+            if applied.is_empty() {
+                println!("Nothing to apply");
+            }
+        }),
+        ["rollback", collection] => migration::rollback(&db, collection).await.map(|migration| {
+            if migration.is_none() {
+                println!("Nothing to roll back in {}", collection);
+            }
+        }),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        exit(1);
+    }
+}
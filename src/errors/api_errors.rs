@@ -0,0 +1,293 @@
+//! The error every route and catcher answers with, as JSON:
+//!
+//! ```json
+//! {"code": 422, "error": "validation_failed", "message": "Invalid username.",
+//!  "request_id": "3Kq9...", "details": [{"field": "username", "message": "too short"}]}
+//! ```
+//!
+//! `code` is the HTTP status, as in the bodies of earlier versions, `error` is the stable
+//! reason clients should match on. The OAuth endpoints keep the format of RFC 6749.
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::{catch, catchers, Catcher};
+use serde::Serialize;
+
+use misato_security::password::PasswordRule;
+use misato_utils::username::UsernameRule;
+
+use crate::errors::database_errors::describe;
+use crate::fairings::request_id::RequestId;
+
+/// Machine-readable reason, never renamed once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    ValidationFailed,
+    TooManyRequests,
+    Internal,
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorCode::BadRequest => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::Conflict => 409,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::UnsupportedMediaType => 415,
+            ErrorCode::ValidationFailed => 422,
+            ErrorCode::TooManyRequests => 429,
+            ErrorCode::Internal => 500,
+            ErrorCode::Unavailable => 503,
+        }
+    }
+
+    /// Closest reason of a status, for failures that only come with one.
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 => ErrorCode::Unauthorized,
+            403 => ErrorCode::Forbidden,
+            404 | 405 => ErrorCode::NotFound,
+            409 => ErrorCode::Conflict,
+            413 => ErrorCode::PayloadTooLarge,
+            415 => ErrorCode::UnsupportedMediaType,
+            422 => ErrorCode::ValidationFailed,
+            429 => ErrorCode::TooManyRequests,
+            503 => ErrorCode::Unavailable,
+            status if status >= 500 => ErrorCode::Internal,
+            _ => ErrorCode::BadRequest,
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad request.",
+            ErrorCode::Unauthorized => "Authentication required.",
+            ErrorCode::Forbidden => "No permission.",
+            ErrorCode::NotFound => "Not found.",
+            ErrorCode::Conflict => "Conflict.",
+            ErrorCode::PayloadTooLarge => "Payload too large.",
+            ErrorCode::UnsupportedMediaType => "Unsupported media type.",
+            ErrorCode::ValidationFailed => "Invalid request body.",
+            ErrorCode::TooManyRequests => "Too many requests.",
+            ErrorCode::Internal => "Internal error.",
+            ErrorCode::Unavailable => "Service unavailable, retry later.",
+        }
+    }
+}
+
+/// What is wrong with one field of the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Vec<FieldError>,
+    /// `WWW-Authenticate` header, for the bearer tokens of RFC 6750.
+    pub challenge: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    code: u16,
+    error: ErrorCode,
+    message: &'a str,
+    request_id: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    details: &'a [FieldError],
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+            challenge: None,
+        }
+    }
+
+    pub fn with_challenge(mut self, challenge: impl Into<String>) -> Self {
+        self.challenge = Some(challenge.into());
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unauthorized, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Forbidden, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Conflict, message)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::TooManyRequests, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    /// The API token is valid but its role does not allow the route.
+    pub fn no_permission() -> Self {
+        Self::forbidden("No permission.")
+    }
+
+    /// A field of the body is well-formed JSON but not an acceptable value.
+    pub fn invalid_field(field: &str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            code: ErrorCode::ValidationFailed,
+            details: vec![FieldError {
+                field: field.to_string(),
+                message: message.clone(),
+            }],
+            message,
+            challenge: None,
+        }
+    }
+
+    pub fn validation(field: &str, messages: Vec<String>) -> Self {
+        Self {
+            code: ErrorCode::ValidationFailed,
+            message: format!("Invalid {}: {}.", field, messages.join(", ")),
+            details: messages
+                .into_iter()
+                .map(|message| FieldError {
+                    field: field.to_string(),
+                    message,
+                })
+                .collect(),
+            challenge: None,
+        }
+    }
+
+    pub fn from_password_rules(rules: &[PasswordRule]) -> Self {
+        let mut error = Self::validation(
+            "password",
+            rules.iter().map(|rule| rule.message()).collect(),
+        );
+        error.message = format!(
+            "Password does not match the policy: {}.",
+            rules
+                .iter()
+                .map(|rule| rule.message())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        error
+    }
+
+    pub fn from_username_rules(rules: &[UsernameRule]) -> Self {
+        Self::validation(
+            "username",
+            rules.iter().map(|rule| rule.message()).collect(),
+        )
+    }
+
+    /// Another account uses the username, or took it between the check and the write.
+    pub fn username_taken(username: &str) -> Self {
+        Self::conflict(format!(
+            "[{}]: Username already used by an account.",
+            username
+        ))
+    }
+
+    /// Another account verified the address, a verified address belongs to a single account.
+    pub fn email_taken() -> Self {
+        Self::conflict("Email address already verified by another account.")
+    }
+
+    pub fn status(&self) -> u16 {
+        self.code.status()
+    }
+
+    /// JSON body of the error in answer to `request`.
+    pub fn body(&self, request: &Request<'_>) -> String {
+        serde_json::to_string(&Body {
+            code: self.status(),
+            error: self.code,
+            message: &self.message,
+            request_id: RequestId::of(request),
+            details: &self.details,
+        })
+        .unwrap()
+    }
+}
+
+impl From<misato_database::Error> for Error {
+    fn from(error: misato_database::Error) -> Self {
+        let (status, message) = describe(&error);
+        Self::new(ErrorCode::from_status(status), message)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = self.body(request);
+        let mut response = Response::build();
+        response
+            .sized_body(body.len(), std::io::Cursor::new(body))
+            .header(ContentType::JSON)
+            .status(Status::new(self.status()));
+        if let Some(challenge) = self.challenge {
+            response.raw_header("WWW-Authenticate", challenge);
+        }
+        response.ok()
+    }
+}
+
+/// Left by a request guard that failed, the catcher answers with it instead of the bare status.
+pub struct GuardFailure(pub Option<Error>);
+
+impl GuardFailure {
+    pub fn set(request: &Request<'_>, error: Error) {
+        request.local_cache(|| GuardFailure(Some(error)));
+    }
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, request: &Request<'_>) -> Error {
+    match &request.local_cache(|| GuardFailure(None)).0 {
+        Some(error) => error.clone(),
+        None => {
+            let code = ErrorCode::from_status(status.code);
+            Error::new(code, code.message())
+        }
+    }
+}
+
+/// Every failure without a handler, such as unknown routes, bodies that do not parse
+/// and failed guards, answers with the same JSON.
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
@@ -0,0 +1,18 @@
+//! What clients are told when the database layer fails, the details only go to the logs.
+
+use misato_database::Error;
+
+/// Logs the failure and returns the status and message of the response.
+pub fn describe(error: &Error) -> (u16, &'static str) {
+    tracing::error!(
+        kind = error.kind(),
+        message = error.message(),
+        "Database error"
+    );
+    match error {
+        Error::NotFound(_) => (404, "Not found."),
+        Error::DuplicateKey(_) => (409, "Already exists."),
+        Error::Unavailable(_) => (503, "Database unavailable, retry later."),
+        Error::Serialization(_) | Error::Invalid(_) => (500, "Database error."),
+    }
+}
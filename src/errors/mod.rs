@@ -1,2 +1,3 @@
-pub mod account_errors;
-pub mod apiaccount_errors;
+pub mod api_errors;
+pub mod database_errors;
+pub mod oauth_errors;
@@ -0,0 +1,110 @@
+use rocket::serde::Serialize;
+
+use crate::errors::database_errors::describe;
+
+/// Error body defined by RFC 6749, section 5.2.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthError {
+    #[serde(skip)]
+    pub code: u16,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+impl OAuthError {
+    pub fn build(code: u16, error: &str, description: Option<String>) -> Self {
+        Self {
+            code,
+            error: error.to_string(),
+            error_description: description,
+        }
+    }
+}
+
+pub struct Error {
+    pub content: OAuthError,
+}
+
+impl Error {
+    pub fn invalid_request(description: &str) -> Self {
+        Self {
+            content: OAuthError::build(400, "invalid_request", Some(description.to_string())),
+        }
+    }
+
+    pub fn invalid_client() -> Self {
+        Self {
+            content: OAuthError::build(
+                401,
+                "invalid_client",
+                Some("Unknown client or wrong secret.".to_string()),
+            ),
+        }
+    }
+
+    pub fn invalid_grant(description: &str) -> Self {
+        Self {
+            content: OAuthError::build(400, "invalid_grant", Some(description.to_string())),
+        }
+    }
+
+    pub fn invalid_scope() -> Self {
+        Self {
+            content: OAuthError::build(
+                400,
+                "invalid_scope",
+                Some("Unknown scope or not allowed for this client.".to_string()),
+            ),
+        }
+    }
+
+    pub fn unsupported_response_type() -> Self {
+        Self {
+            content: OAuthError::build(400, "unsupported_response_type", None),
+        }
+    }
+
+    pub fn unsupported_grant_type() -> Self {
+        Self {
+            content: OAuthError::build(400, "unsupported_grant_type", None),
+        }
+    }
+
+    /// Resource error of RFC 6750, section 3.1.
+    pub fn insufficient_scope(scope: &str) -> Self {
+        Self {
+            content: OAuthError::build(
+                403,
+                "insufficient_scope",
+                Some(format!("[{}]: Scope required.", scope)),
+            ),
+        }
+    }
+}
+
+/// `temporarily_unavailable` when retrying may help, `server_error` otherwise.
+impl From<misato_database::Error> for Error {
+    fn from(error: misato_database::Error) -> Self {
+        let (code, message) = describe(&error);
+        let (code, kind) = match code {
+            503 => (503, "temporarily_unavailable"),
+            _ => (500, "server_error"),
+        };
+        Self {
+            content: OAuthError::build(code, kind, Some(message.to_string())),
+        }
+    }
+}
+
+impl<'r> rocket::response::Responder<'r, 'static> for Error {
+    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let body = serde_json::to_string(&self.content).unwrap();
+        rocket::Response::build()
+            .sized_body(body.len(), std::io::Cursor::new(body))
+            .header(rocket::http::ContentType::JSON)
+            .status(rocket::http::Status::new(self.content.code))
+            .ok()
+    }
+}
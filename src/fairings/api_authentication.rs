@@ -1,7 +1,14 @@
+use std::sync::Arc;
+
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Outcome, Request};
 
 use misato_database::{database::*, models::*};
+use misato_utils::get_current_timestamp;
+
+use crate::errors::api_errors::{self, GuardFailure};
+use crate::fairings::trace::Caller;
+use crate::metrics::Metrics;
 
 pub struct ApiUserToken {
     pub apiuser: apiuser_model::ApiUser,
@@ -12,6 +19,33 @@ pub enum ApiUserTokenError {
     BadCount,
     Missing,
     Invalid,
+    Inactive,
+}
+
+impl ApiUserTokenError {
+    /// Leaves the JSON error for the catcher and fails the guard with its status.
+    fn fail<T>(self, request: &Request<'_>) -> request::Outcome<T, Self> {
+        let error = match self {
+            ApiUserTokenError::BadCount => {
+                api_errors::Error::bad_request("Exactly one X-Misato-API-Token header is expected.")
+            }
+            ApiUserTokenError::Missing => {
+                api_errors::Error::unauthorized("Missing X-Misato-API-Token header.")
+            }
+            ApiUserTokenError::Invalid => {
+                api_errors::Error::unauthorized("Invalid or expired token.")
+            }
+            ApiUserTokenError::Inactive => {
+                api_errors::Error::forbidden("Account suspended or deleted.")
+            }
+        };
+        if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+            metrics.auth_failure("api", &format!("{:?}", self));
+        }
+        let status = Status::new(error.status());
+        GuardFailure::set(request, error);
+        Outcome::Failure((status, self))
+    }
 }
 
 #[rocket::async_trait]
@@ -21,7 +55,7 @@ impl<'r> FromRequest<'r> for ApiUserToken {
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<ApiUserToken, Self::Error> {
         let keys: Vec<_> = request.headers().get("X-Misato-API-Token").collect();
         match keys.len() {
-            0 => return Outcome::Failure((Status::BadRequest, ApiUserTokenError::Missing)),
+            0 => return ApiUserTokenError::Missing.fail(request),
             1 => {
                 let token = keys.get(0).unwrap();
 
@@ -29,15 +63,22 @@ impl<'r> FromRequest<'r> for ApiUserToken {
 
                 let apiuser = db.apiusermanager.get_apiuser_from_token(&token).await;
 
-                if apiuser.is_ok() && apiuser.as_ref().unwrap().is_some() {
-                    return Outcome::Success(ApiUserToken {
-                        apiuser: apiuser.unwrap().unwrap(),
-                    });
+                if let Ok(Some(apiuser)) = apiuser {
+                    // The token stops working with its user, like their sessions.
+                    if apiuser.uuid != apiuser_model::DEFAULT_APIUSER_UUID {
+                        match db.usermanager.get_user(None, Some(&apiuser.uuid)).await {
+                            Ok(Some(user)) if user.is_active(get_current_timestamp()) => {}
+                            Ok(Some(_)) => return ApiUserTokenError::Inactive.fail(request),
+                            _ => return ApiUserTokenError::Invalid.fail(request),
+                        }
+                    }
+                    Caller::set(request, &apiuser.uuid, "api");
+                    return Outcome::Success(ApiUserToken { apiuser });
                 }
-                return Outcome::Failure((Status::BadRequest, ApiUserTokenError::Invalid));
+                return ApiUserTokenError::Invalid.fail(request);
             }
             _ => {
-                return Outcome::Failure((Status::BadRequest, ApiUserTokenError::BadCount));
+                return ApiUserTokenError::BadCount.fail(request);
             }
         }
     }
@@ -1,10 +1,18 @@
+use std::sync::Arc;
+
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Outcome, Request};
 
 use misato_database::{database::*, models::*};
+use misato_utils::get_current_timestamp;
+
+use crate::errors::api_errors::{self, GuardFailure};
+use crate::fairings::trace::Caller;
+use crate::metrics::Metrics;
 
 pub struct UserToken {
     pub user: user_model::User,
+    pub token: String,
 }
 
 #[derive(Debug)]
@@ -12,6 +20,31 @@ pub enum UserTokenError {
     BadCount,
     Missing,
     Invalid,
+    Inactive,
+}
+
+impl UserTokenError {
+    /// Leaves the JSON error for the catcher and fails the guard with its status.
+    fn fail<T>(self, request: &Request<'_>) -> request::Outcome<T, Self> {
+        let error = match self {
+            UserTokenError::BadCount => api_errors::Error::bad_request(
+                "Exactly one X-Misato-User-Token header is expected.",
+            ),
+            UserTokenError::Missing => {
+                api_errors::Error::unauthorized("Missing X-Misato-User-Token header.")
+            }
+            UserTokenError::Invalid => api_errors::Error::unauthorized("Invalid or expired token."),
+            UserTokenError::Inactive => {
+                api_errors::Error::forbidden("Account suspended or deleted.")
+            }
+        };
+        if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+            metrics.auth_failure("user", &format!("{:?}", self));
+        }
+        let status = Status::new(error.status());
+        GuardFailure::set(request, error);
+        Outcome::Failure((status, self))
+    }
 }
 
 #[rocket::async_trait]
@@ -21,7 +54,7 @@ impl<'r> FromRequest<'r> for UserToken {
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<UserToken, Self::Error> {
         let keys: Vec<_> = request.headers().get("X-Misato-User-Token").collect();
         match keys.len() {
-            0 => return Outcome::Failure((Status::BadRequest, UserTokenError::Missing)),
+            0 => return UserTokenError::Missing.fail(request),
             1 => {
                 let token = keys.get(0).unwrap();
 
@@ -29,15 +62,21 @@ impl<'r> FromRequest<'r> for UserToken {
 
                 let user = db.usermanager.get_user_from_token(&token).await;
 
-                if user.is_ok() && user.as_ref().unwrap().is_some() {
+                // Suspended and deleted accounts keep no session, but a suspension may have expired.
+                if matches!(&user, Ok(Some(user)) if !user.is_active(get_current_timestamp())) {
+                    return UserTokenError::Inactive.fail(request);
+                }
+                if let Ok(Some(user)) = user {
+                    Caller::set(request, &user.uuid, "user");
                     return Outcome::Success(UserToken {
-                        user: user.unwrap().unwrap(),
+                        user,
+                        token: token.to_string(),
                     });
                 }
-                return Outcome::Failure((Status::BadRequest, UserTokenError::Invalid));
+                return UserTokenError::Invalid.fail(request);
             }
             _ => {
-                return Outcome::Failure((Status::BadRequest, UserTokenError::BadCount));
+                return UserTokenError::BadCount.fail(request);
             }
         }
     }
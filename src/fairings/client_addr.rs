@@ -0,0 +1,43 @@
+//! Address of the client. Rocket's `client_ip` believes the `X-Real-IP` header of anyone,
+//! it is only read here when the connection comes from one of `MISATO_TRUSTED_PROXIES`.
+
+use std::convert::Infallible;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use rocket::request::{self, FromRequest, Outcome, Request};
+
+use misato_utils::settings::Settings;
+
+/// Networks of a comma separated list, invalid entries are skipped.
+pub fn networks(list: &str) -> impl Iterator<Item = IpNet> + '_ {
+    list.split(',')
+        .filter_map(|network| network.trim().parse::<IpNet>().ok())
+}
+
+pub struct ClientAddr(pub Option<IpAddr>);
+
+impl ClientAddr {
+    pub fn of(request: &Request<'_>) -> Option<IpAddr> {
+        let remote = request.remote()?.ip();
+        let trusted = request
+            .rocket()
+            .state::<Settings>()
+            .is_some_and(|settings| {
+                networks(&settings.trusted_proxies).any(|network| network.contains(&remote))
+            });
+        match trusted {
+            true => request.real_ip().or(Some(remote)),
+            false => Some(remote),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientAddr {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<ClientAddr, Infallible> {
+        Outcome::Success(ClientAddr(Self::of(request)))
+    }
+}
@@ -0,0 +1,115 @@
+//! Opens the database on ignite. When it cannot be reached the server still starts, its
+//! stores answer 503 and `/health/ready` fails while the connection is retried with backoff.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+
+use misato_database::database::{Database, DatabaseSlot};
+use misato_database::models::apiuser_model::ApiUser;
+use misato_database::Error;
+use misato_utils::settings::Settings;
+
+use crate::metrics::Metrics;
+
+/// How the connection went, for the readiness probe.
+#[derive(Default)]
+pub struct ConnectionStatus {
+    attempts: AtomicU32,
+    last_error: Mutex<Option<String>>,
+}
+
+impl ConnectionStatus {
+    fn failed(&self, error: &Error) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(error.to_string());
+    }
+
+    fn connected(&self) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = None;
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// Why the last attempt failed, none once connected.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+async fn create_default_user(database: &Database, settings: &Settings) {
+    let user = ApiUser::create_default(settings.admin_token.clone());
+    match database.apiusermanager.create_apiuser(&user).await {
+        Ok(_) => tracing::info!("Successfully created default user."),
+        Err(error) => tracing::error!(%error, "Error whilst creating default user"),
+    }
+}
+
+async fn retry(settings: Settings, slot: DatabaseSlot, status: Arc<ConnectionStatus>) {
+    let mut delay = Duration::from_millis(settings.database_retry_delay.max(1));
+    let max_delay = Duration::from_millis(settings.database_retry_max_delay).max(delay);
+    loop {
+        tokio::time::sleep(delay).await;
+        match Database::init(&settings).await {
+            Ok(database) => {
+                create_default_user(&database, &settings).await;
+                slot.fill(database);
+                status.connected();
+                tracing::info!(attempts = status.attempts(), "Connected to the database");
+                return;
+            }
+            Err(error @ Error::Unavailable(_)) => {
+                status.failed(&error);
+                delay = (delay * 2).min(max_delay);
+                tracing::warn!(
+                    %error,
+                    retry_in_ms = delay.as_millis() as u64,
+                    "Database still unreachable"
+                );
+            }
+            Err(error) => {
+                status.failed(&error);
+                tracing::error!(%error, "Cannot connect to the database, giving up");
+                return;
+            }
+        }
+    }
+}
+
+/// Manages the `Database`, connected or deferred, and the settings the routes read.
+///
+/// Only an unreachable database starts the server degraded, a configuration it refuses
+/// (such as migrations unknown to this build) still stops it.
+pub fn connect(settings: Settings, metrics: Arc<Metrics>) -> AdHoc {
+    AdHoc::on_ignite("Connecting to the database", |rocket| async move {
+        let status = Arc::new(ConnectionStatus::default());
+        let database = match Database::init(&settings).await {
+            Ok(database) => {
+                status.connected();
+                create_default_user(&database, &settings).await;
+                database
+            }
+            Err(error @ Error::Unavailable(_)) => {
+                status.failed(&error);
+                tracing::error!(%error, "Database unreachable, starting degraded");
+                let (database, slot) = Database::deferred();
+                tokio::spawn(retry(settings.clone(), slot, status.clone()));
+                database
+            }
+            Err(error) => {
+                tracing::error!(%error, "Cannot connect to the database");
+                panic!("Cannot connect to the database: {:?}", error)
+            }
+        };
+        rocket
+            .manage(database.observed(metrics))
+            .manage(status)
+            .manage(settings)
+    })
+}
@@ -0,0 +1,64 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+
+use misato_security::hash_token;
+use misato_utils::settings::Settings;
+
+use crate::errors::api_errors::{self, GuardFailure};
+use crate::fairings::client_addr::{networks, ClientAddr};
+
+/// Scraper of `/metrics`: sends `Authorization: Bearer <MISATO_METRICS_TOKEN>` or connects
+/// from one of `MISATO_METRICS_NETWORKS`.
+pub struct MetricsAccess;
+
+#[derive(Debug)]
+pub enum MetricsAccessError {
+    Invalid,
+    Refused,
+}
+
+impl MetricsAccessError {
+    fn fail<T>(self, request: &Request<'_>) -> request::Outcome<T, Self> {
+        let error = match self {
+            MetricsAccessError::Invalid => {
+                api_errors::Error::unauthorized("Invalid metrics token.")
+            }
+            MetricsAccessError::Refused => api_errors::Error::no_permission(),
+        };
+        let status = Status::new(error.status());
+        GuardFailure::set(request, error);
+        Outcome::Failure((status, self))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MetricsAccess {
+    type Error = MetricsAccessError;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> request::Outcome<MetricsAccess, Self::Error> {
+        let settings = request.rocket().state::<Settings>().unwrap();
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        match token {
+            // Hashed so the comparison takes the same time whatever the token.
+            Some(token) if !settings.metrics_token.is_empty() => {
+                if hash_token(token.trim()) == hash_token(&settings.metrics_token) {
+                    return Outcome::Success(MetricsAccess);
+                }
+                return MetricsAccessError::Invalid.fail(request);
+            }
+            _ => {}
+        }
+        let allowed = ClientAddr::of(request).is_some_and(|ip| {
+            networks(&settings.metrics_networks).any(|network| network.contains(&ip))
+        });
+        match allowed {
+            true => Outcome::Success(MetricsAccess),
+            false => MetricsAccessError::Refused.fail(request),
+        }
+    }
+}
@@ -1,2 +1,10 @@
 pub mod api_authentication;
 pub mod authentication;
+pub mod client_addr;
+pub mod connection;
+pub mod metrics_authentication;
+pub mod oauth_authentication;
+pub mod purge;
+pub mod rate_limit;
+pub mod request_id;
+pub mod trace;
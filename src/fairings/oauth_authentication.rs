@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+
+use misato_database::{database::*, models::*};
+use misato_security::hash_token;
+use misato_utils::get_current_timestamp;
+
+use crate::errors::api_errors::{self, GuardFailure};
+use crate::fairings::trace::Caller;
+use crate::metrics::Metrics;
+
+/// Access token of a third-party application, sent as `Authorization: Bearer <token>`.
+pub struct OAuthAccess {
+    pub token: oauth_model::OAuthToken,
+    pub user: user_model::User,
+}
+
+#[derive(Debug)]
+pub enum OAuthAccessError {
+    Missing,
+    Invalid,
+    Inactive,
+}
+
+impl OAuthAccessError {
+    /// Leaves the JSON error for the catcher, with the challenge of RFC 6750, and fails the
+    /// guard with its status.
+    fn fail<T>(self, request: &Request<'_>) -> request::Outcome<T, Self> {
+        let error = match self {
+            OAuthAccessError::Missing => {
+                api_errors::Error::unauthorized("Missing bearer token.").with_challenge("Bearer")
+            }
+            OAuthAccessError::Invalid => {
+                api_errors::Error::unauthorized("Invalid or expired token.")
+                    .with_challenge("Bearer error=\"invalid_token\"")
+            }
+            OAuthAccessError::Inactive => {
+                api_errors::Error::forbidden("Account suspended or deleted.")
+                    .with_challenge("Bearer error=\"invalid_token\"")
+            }
+        };
+        if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+            metrics.auth_failure("oauth", &format!("{:?}", self));
+        }
+        let status = Status::new(error.status());
+        GuardFailure::set(request, error);
+        Outcome::Failure((status, self))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OAuthAccess {
+    type Error = OAuthAccessError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<OAuthAccess, Self::Error> {
+        let token = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim(),
+            None => return OAuthAccessError::Missing.fail(request),
+        };
+        let db = request.rocket().state::<Database>().unwrap();
+        let token = match db.oauthmanager.get_token(&hash_token(token)).await {
+            Ok(Some(token)) => token,
+            _ => return OAuthAccessError::Invalid.fail(request),
+        };
+        match db.usermanager.get_user(None, Some(&token.uuid)).await {
+            Ok(Some(user)) if user.is_active(get_current_timestamp()) => {
+                Caller::set(request, &user.uuid, "oauth");
+                Outcome::Success(OAuthAccess { token, user })
+            }
+            Ok(Some(_)) => OAuthAccessError::Inactive.fail(request),
+            _ => OAuthAccessError::Invalid.fail(request),
+        }
+    }
+}
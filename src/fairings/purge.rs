@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio::{self, fs};
+
+use misato_database::database::Database;
+use misato_utils::settings::Settings;
+
+/// Periodically purges the accounts whose deletion grace period is over, along with
+/// their avatar, and the personal data exports nobody downloaded in time.
+pub fn purge_job(settings: &Settings) -> AdHoc {
+    let interval = settings.purge_interval;
+    let export_directory = PathBuf::from(&settings.export_directory);
+    let avatar_directory = PathBuf::from(&settings.avatar_directory);
+    AdHoc::on_liftoff("Purge job", move |rocket| {
+        let export_directory = export_directory.clone();
+        let avatar_directory = avatar_directory.clone();
+        Box::pin(async move {
+            let (users, exports) = match rocket.state::<Database>() {
+                Some(db) => (db.usermanager.clone(), db.exportmanager.clone()),
+                None => return,
+            };
+            tokio::spawn(async move {
+                let mut timer = tokio::time::interval(Duration::from_secs(interval.max(1)));
+                loop {
+                    timer.tick().await;
+                    match users.purge_deleted_users().await {
+                        Ok(purged) if purged.is_empty() => {}
+                        Ok(purged) => {
+                            for user in &purged {
+                                if let Some(avatar) = &user.profile.avatar {
+                                    let _ = fs::remove_file(avatar_directory.join(avatar)).await;
+                                }
+                            }
+                            tracing::info!(count = purged.len(), "Purged deleted accounts");
+                        }
+                        Err(error) => tracing::error!(%error, "Error whilst purging accounts"),
+                    }
+                    match exports.remove_expired().await {
+                        Ok(expired) => {
+                            for export in expired {
+                                let _ = fs::remove_file(export_directory.join(export.file_name()))
+                                    .await;
+                            }
+                        }
+                        Err(error) => tracing::error!(%error, "Error whilst purging exports"),
+                    }
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,250 @@
+//! Token buckets per route group and client. The group is set on the routes when they are
+//! mounted, the check runs before their handler and a refused request fails with 429.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Response, Route};
+
+use misato_database::database::Database;
+use misato_security::hash_token;
+use misato_utils::{
+    bounded::BoundedMap,
+    get_current_timestamp,
+    settings::{RateLimitQuota, Settings},
+};
+
+use crate::errors::api_errors::{self, GuardFailure};
+use crate::fairings::client_addr::ClientAddr;
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub enum RouteGroup {
+    Login,
+    Signup,
+    Write,
+    Read,
+}
+
+impl RouteGroup {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RouteGroup::Login => "login",
+            RouteGroup::Signup => "signup",
+            RouteGroup::Write => "write",
+            RouteGroup::Read => "read",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next request is allowed, 0 if allowed.
+    pub retry_after: u64,
+}
+
+/// Backend keeping the buckets, swap the in-memory one for a shared store
+/// when running several instances.
+#[rocket::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn take(&self, key: &str, quota: &RateLimitQuota) -> RateLimitDecision;
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: u64,
+}
+
+/// Buckets of the clients seen last, a forgotten client starts again with a full bucket.
+pub struct MemoryStore {
+    buckets: Mutex<BoundedMap<String, Bucket>>,
+}
+
+const MEMORY_STORE_SIZE: usize = 100_000;
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            buckets: Mutex::new(BoundedMap::new(MEMORY_STORE_SIZE)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, quota: &RateLimitQuota) -> RateLimitDecision {
+        let timestamp = get_current_timestamp();
+        let capacity = quota.capacity as f64;
+        // Tokens regained per millisecond
+        let rate = capacity / (quota.period.max(1) * 1000) as f64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_with(key.to_string(), || Bucket {
+            tokens: capacity,
+            last_refill: timestamp,
+        });
+        let elapsed = timestamp.saturating_sub(bucket.last_refill) as f64;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = timestamp;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let to_seconds = |tokens: f64| (tokens / rate / 1000.0).ceil() as u64;
+        RateLimitDecision {
+            allowed,
+            limit: quota.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: to_seconds(capacity - bucket.tokens),
+            retry_after: if allowed {
+                0
+            } else {
+                to_seconds(1.0 - bucket.tokens)
+            },
+        }
+    }
+}
+
+struct RateLimitState(Option<RateLimitDecision>);
+
+/// Managed by the application, the routes wrapped by `rate_limited` take from its buckets.
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    quotas: HashMap<RouteGroup, RateLimitQuota>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &Settings, store: impl RateLimitStore + 'static) -> Self {
+        Self {
+            store: Box::new(store),
+            quotas: HashMap::from([
+                (RouteGroup::Login, settings.ratelimit_login),
+                (RouteGroup::Signup, settings.ratelimit_signup),
+                (RouteGroup::Write, settings.ratelimit_write),
+                (RouteGroup::Read, settings.ratelimit_read),
+            ]),
+        }
+    }
+
+    pub fn in_memory(settings: &Settings) -> Self {
+        Self::new(settings, MemoryStore::default())
+    }
+
+    /// Requests are identified by the account of their API, user or OAuth token, once the
+    /// token is known to be valid, and otherwise by the client address. Unverified tokens
+    /// are ignored, a client sending random ones would get a new bucket each time.
+    async fn client_key(request: &Request<'_>) -> String {
+        let headers = request.headers();
+        if let Some(db) = request.rocket().state::<Database>() {
+            if let Some(token) = headers.get_one("X-Misato-API-Token") {
+                if let Ok(Some(apiuser)) = db.apiusermanager.get_apiuser_from_token(token).await {
+                    return format!("api:{}", apiuser.uuid);
+                }
+            }
+            if let Some(token) = headers.get_one("X-Misato-User-Token") {
+                if let Ok(Some(user)) = db.usermanager.get_user_from_token(token).await {
+                    if user.is_active(get_current_timestamp()) {
+                        return format!("user:{}", user.uuid);
+                    }
+                }
+            }
+            if let Some(token) = headers
+                .get_one("Authorization")
+                .and_then(|header| header.strip_prefix("Bearer "))
+            {
+                if let Ok(Some(token)) = db.oauthmanager.get_token(&hash_token(token.trim())).await
+                {
+                    return format!("oauth:{}", token.uuid);
+                }
+            }
+        }
+        match ClientAddr::of(request) {
+            Some(ip) => format!("ip:{}", ip),
+            None => "unknown".to_string(),
+        }
+    }
+
+    async fn take(&self, request: &Request<'_>, group: RouteGroup) -> Option<RateLimitDecision> {
+        let quota = &self.quotas[&group];
+        if quota.capacity == 0 {
+            return None;
+        }
+        let key = format!("{}:{}", group.name(), Self::client_key(request).await);
+        Some(self.store.take(&key, quota).await)
+    }
+}
+
+#[derive(Clone)]
+struct Limited(RouteGroup, Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for Limited {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let decision = match request.rocket().state::<RateLimiter>() {
+            Some(limiter) => limiter.take(request, self.0).await,
+            None => None,
+        };
+        request.local_cache(|| RateLimitState(decision));
+        match decision {
+            Some(decision) if !decision.allowed => {
+                GuardFailure::set(
+                    request,
+                    api_errors::Error::too_many_requests(format!(
+                        "Too many requests, try again in {} seconds.",
+                        decision.retry_after
+                    )),
+                );
+                Outcome::Failure(Status::TooManyRequests)
+            }
+            _ => self.1.handle(request, data).await,
+        }
+    }
+}
+
+/// Counts the requests to `routes` against the buckets of `group`.
+pub fn rate_limited(group: RouteGroup, routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(Limited(group, route.handler));
+            route
+        })
+        .collect()
+}
+
+/// Tells clients where they stand with the `X-RateLimit-*` headers, and when to retry.
+pub struct RateLimitHeaders;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limit headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let decision = match request.local_cache(|| RateLimitState(None)).0 {
+            Some(decision) => decision,
+            None => return,
+        };
+        response.set_header(Header::new("X-RateLimit-Limit", decision.limit.to_string()));
+        response.set_header(Header::new(
+            "X-RateLimit-Remaining",
+            decision.remaining.to_string(),
+        ));
+        response.set_header(Header::new("X-RateLimit-Reset", decision.reset.to_string()));
+        if !decision.allowed {
+            response.set_header(Header::new("Retry-After", decision.retry_after.to_string()));
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use rocket::Request;
+
+use misato_security::generate_token;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const REQUEST_ID_LENGTH: usize = 24;
+const REQUEST_ID_MAX_LENGTH: usize = 64;
+
+/// Identifier of a request, in its logs, in the `X-Request-Id` response header and in error bodies.
+/// The one of a proxy in front is kept when it looks sane, otherwise a new one is generated.
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn of<'a>(request: &'a Request<'_>) -> &'a str {
+        &request
+            .local_cache(|| {
+                let id = request
+                    .headers()
+                    .get_one(REQUEST_ID_HEADER)
+                    .filter(|id| Self::is_valid(id))
+                    .map(str::to_string)
+                    .unwrap_or_else(|| generate_token(REQUEST_ID_LENGTH));
+                RequestId(id)
+            })
+            .0
+    }
+
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= REQUEST_ID_MAX_LENGTH
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    }
+}
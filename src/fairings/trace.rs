@@ -0,0 +1,116 @@
+//! One log line and one metrics sample per request, with the span of the request around its
+//! handler so the events of the guards and of the database calls it makes carry the request id.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Response, Route};
+use tracing::{Instrument, Span};
+
+use crate::fairings::request_id::{RequestId, REQUEST_ID_HEADER};
+use crate::metrics::Metrics;
+
+/// Who made the request, left by the authentication guard that accepted it.
+pub struct Caller {
+    pub uuid: String,
+    /// `user`, `api` or `oauth`.
+    pub token_type: &'static str,
+}
+
+impl Caller {
+    pub fn set(request: &Request<'_>, uuid: &str, token_type: &'static str) {
+        request.local_cache(|| {
+            Some(Caller {
+                uuid: uuid.to_string(),
+                token_type,
+            })
+        });
+    }
+
+    pub fn of<'a>(request: &'a Request<'_>) -> Option<&'a Caller> {
+        request.local_cache(|| None::<Caller>).as_ref()
+    }
+}
+
+struct RequestSpan(Span, Instant);
+
+impl RequestSpan {
+    fn of<'a>(request: &'a Request<'_>) -> &'a RequestSpan {
+        request.local_cache(|| RequestSpan(Span::none(), Instant::now()))
+    }
+}
+
+pub struct RequestTrace;
+
+#[rocket::async_trait]
+impl Fairing for RequestTrace {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let span = tracing::info_span!("request", request_id = RequestId::of(request));
+        request.local_cache(|| RequestSpan(span, Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let RequestSpan(span, started) = RequestSpan::of(request);
+        let latency = started.elapsed();
+        let route = request.route().map(|route| route.uri.to_string());
+        let caller = Caller::of(request);
+        if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+            // Unmatched paths are arbitrary, they share one series.
+            let route = route.as_deref().unwrap_or("unmatched");
+            metrics.observe_request(
+                request.method().as_str(),
+                route,
+                response.status().code,
+                latency,
+            );
+        }
+        span.in_scope(|| {
+            tracing::info!(
+                method = %request.method(),
+                route,
+                path = %request.uri().path(),
+                status = response.status().code,
+                latency_ms = latency.as_secs_f64() * 1000.0,
+                uuid = caller.map(|caller| caller.uuid.as_str()),
+                token_type = caller.map(|caller| caller.token_type),
+                "Request"
+            )
+        });
+        response.set_header(Header::new(
+            REQUEST_ID_HEADER,
+            RequestId::of(request).to_string(),
+        ));
+    }
+}
+
+#[derive(Clone)]
+struct Traced(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for Traced {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let span = RequestSpan::of(request).0.clone();
+        self.0.handle(request, data).instrument(span).await
+    }
+}
+
+/// Runs the handlers, and the guards they start with, in the span of their request.
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(Traced(route.handler));
+            route
+        })
+        .collect()
+}
@@ -0,0 +1,234 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rocket::{fairing::AdHoc, *};
+
+use misato_security::{
+    generate_token,
+    password::{BreachedPasswords, PasswordParams, PasswordPolicy},
+    pow::ProofOfWork,
+};
+use misato_utils::{settings::Settings, username::UsernamePolicy};
+
+pub mod errors;
+pub mod fairings;
+pub mod logging;
+pub mod mailer;
+pub mod metrics;
+pub mod models;
+pub mod oidc;
+pub mod routes;
+
+use errors::api_errors;
+use fairings::{
+    connection::connect,
+    purge::purge_job,
+    rate_limit::{rate_limited, RateLimitHeaders, RateLimiter, RouteGroup},
+    trace::{traced, RequestTrace},
+};
+use mailer::MailService;
+use metrics::Metrics;
+use oidc::OidcService;
+use routes::{admin, api, root, user};
+
+fn password_policy(settings: &Settings) -> PasswordPolicy {
+    PasswordPolicy {
+        min_length: settings.password_min_length,
+        max_length: settings.password_max_length,
+        require_lowercase: settings.password_require_lowercase,
+        require_uppercase: settings.password_require_uppercase,
+        require_digit: settings.password_require_digit,
+        require_symbol: settings.password_require_symbol,
+        forbid_username: settings.password_forbid_username,
+        breached: match settings.password_breached_directory.as_str() {
+            "" => None,
+            directory => Some(BreachedPasswords::new(directory)),
+        },
+    }
+}
+
+/// Refuses to start with costs argon2 rejects, every hash would fail.
+fn password_params(settings: &Settings) -> PasswordParams {
+    let params = PasswordParams {
+        mem_cost: settings.argon2_memory_cost,
+        time_cost: settings.argon2_time_cost,
+        lanes: settings.argon2_parallelism,
+    };
+    if let Err(error) = params.check() {
+        tracing::error!(%error, "Invalid argon2 settings");
+        panic!("Invalid argon2 settings: {}", error)
+    }
+    params
+}
+
+fn username_policy(settings: &Settings) -> UsernamePolicy {
+    UsernamePolicy {
+        min_length: settings.username_min_length,
+        max_length: settings.username_max_length,
+        reserved: settings
+            .username_reserved
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect(),
+    }
+}
+
+/// Signup challenges stay valid for five minutes.
+fn proof_of_work(settings: &Settings) -> ProofOfWork {
+    ProofOfWork::new(settings.signup_pow_difficulty, 5 * 60 * 1000)
+}
+
+fn mailer(settings: Settings) -> AdHoc {
+    AdHoc::on_ignite("Loading mailer", |rocket| async move {
+        match MailService::init(&settings) {
+            Ok(mailer) => rocket.manage(mailer),
+            Err(error) => {
+                panic!("Cannot load mailer:: {:?}", error)
+            }
+        }
+    })
+}
+
+/// Secret of the export links when none is configured, generated once and kept next to the
+/// exports so that the links handed out survive a restart.
+fn stored_export_secret(settings: &Settings) -> String {
+    let path = Path::new(&settings.export_directory).join(".secret");
+    let stored = std::fs::read_to_string(&path).or_else(|_| {
+        let secret = generate_token(64);
+        std::fs::create_dir_all(&settings.export_directory)
+            .and_then(|_| std::fs::write(&path, &secret))
+            .map(|_| secret)
+    });
+    match stored {
+        Ok(secret) if !secret.trim().is_empty() => secret.trim().to_string(),
+        Ok(_) => panic!("Empty export secret in {}", path.display()),
+        Err(error) => {
+            tracing::error!(%error, path = %path.display(), "Cannot store the export secret");
+            panic!("Cannot store the export secret: {:?}", error)
+        }
+    }
+}
+
+/// The whole application, `main` launches it with the settings of the environment.
+pub fn rocket(mut settings: Settings) -> Rocket<Build> {
+    if settings.export_secret.is_empty() {
+        settings.export_secret = stored_export_secret(&settings);
+    }
+    let metrics = Arc::new(Metrics::default());
+    // Probes and scrapes come often from a single address, a 429 would make the pod look down.
+    let mut routes: Vec<Route> = routes![
+        root::health::live,
+        root::health::ready,
+        root::metrics::metrics,
+    ];
+
+    // Credentials and secrets sent by mail, guessable by brute force
+    routes.append(&mut rate_limited(
+        RouteGroup::Login,
+        routes![
+            root::account::login,
+            root::account::login_totp,
+            root::oidc::login,
+            root::oidc::callback,
+            root::oidc::exchange,
+            root::oauth::token,
+            root::oauth::introspect,
+            root::recovery::forgot_password,
+            root::recovery::reset_password,
+            root::recovery::verify_email,
+        ],
+    ));
+
+    routes.append(&mut rate_limited(
+        RouteGroup::Signup,
+        routes![
+            api::admin::account::signup,
+            api::root::account::signup,
+            root::signup::signup,
+            admin::account::signup,
+        ],
+    ));
+
+    routes.append(&mut rate_limited(
+        RouteGroup::Read,
+        routes![
+            api::admin::account::check_token,
+            api::root::account::check_token,
+            root::signup::challenge,
+            root::profile::public_profile,
+            root::profile::avatar,
+            root::export::download,
+            root::oidc::providers,
+            root::oauth::userinfo,
+            user::account::check_token,
+            user::profile::get_profile,
+            user::export::get_export,
+            user::identities::list,
+            user::oauth::list_clients,
+            user::oauth::authorize,
+            user::oauth::list_consents,
+            admin::account::profile,
+            admin::account::profile_from_token,
+            admin::account::check_token,
+            admin::invite::list,
+            admin::users::list,
+        ],
+    ));
+
+    routes.append(&mut rate_limited(
+        RouteGroup::Write,
+        routes![
+            api::admin::account::refresh_token,
+            api::admin::account::clear_tokens,
+            api::admin::account::delete,
+            api::root::account::refresh_token,
+            api::root::account::clear_tokens,
+            api::root::account::delete,
+            root::oauth::revoke,
+            root::oauth::update_profile,
+            user::account::delete,
+            user::account::clear_tokens,
+            user::credentials::change_password,
+            user::credentials::change_username,
+            user::email::set_email,
+            user::profile::update_profile,
+            user::profile::upload_avatar,
+            user::profile::delete_avatar,
+            user::export::request_export,
+            user::identities::link,
+            user::identities::unlink,
+            user::oauth::register_client,
+            user::oauth::delete_client,
+            user::oauth::decide,
+            user::oauth::revoke_consent,
+            user::totp::enroll,
+            user::totp::activate,
+            user::totp::disable,
+            admin::account::refresh_token,
+            admin::account::clear_tokens,
+            admin::account::delete,
+            admin::invite::create,
+            admin::invite::delete,
+            admin::users::bulk,
+        ],
+    ));
+
+    // Colored output would end up escaped in the JSON logs.
+    let figment = Config::figment().merge(("cli_colors", settings.log_format == "text"));
+    rocket::custom(figment)
+        .manage(password_policy(&settings))
+        .manage(password_params(&settings))
+        .manage(username_policy(&settings))
+        .manage(proof_of_work(&settings))
+        .manage(OidcService::init(&settings))
+        .manage(RateLimiter::in_memory(&settings))
+        .manage(metrics.clone())
+        .attach(RequestTrace)
+        .attach(RateLimitHeaders)
+        .attach(mailer(settings.clone()))
+        .attach(purge_job(&settings))
+        .attach(connect(settings, metrics))
+        .mount("/", traced(routes))
+        .register("/", api_errors::catchers())
+}
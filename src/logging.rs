@@ -0,0 +1,24 @@
+//! Installs the subscriber printing the events of the whole process, Rocket and the SQL
+//! driver included, as one JSON object per line unless `MISATO_LOG_FORMAT=text`.
+
+use tracing_subscriber::EnvFilter;
+
+use misato_utils::settings::Settings;
+
+pub fn init(settings: &Settings) {
+    let filter = EnvFilter::try_new(&settings.log_level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match settings.log_format.as_str() {
+        "text" => builder.try_init(),
+        _ => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .try_init(),
+    };
+    // Only the first call installs it, tests build many instances.
+    if let Err(error) = result {
+        tracing::debug!(%error, "Logging already initialized");
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+use misato_security::hash_token;
+use misato_utils::get_current_timestamp;
+
+use super::{Mail, Mailer, MailerError};
+
+/// Writes mails to a directory, or to stdout without one. Meant for local testing.
+pub struct FileMailer {
+    directory: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn init(directory: Option<String>) -> Self {
+        Self {
+            directory: directory.map(PathBuf::from),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError> {
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            mail.to, mail.subject, mail.body
+        );
+        match &self.directory {
+            Some(directory) => {
+                rocket::tokio::fs::create_dir_all(directory)
+                    .await
+                    .map_err(MailerError::build)?;
+                // Addresses may hold `/` and other characters unsafe in a file name.
+                let recipient = &hash_token(&mail.to)[..16];
+                let path = directory.join(format!("{}-{}.eml", get_current_timestamp(), recipient));
+                rocket::tokio::fs::write(path, content)
+                    .await
+                    .map_err(MailerError::build)
+            }
+            None => {
+                println!("{}", content);
+                Ok(())
+            }
+        }
+    }
+}
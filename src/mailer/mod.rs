@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use rocket_dyn_templates::tera::{Context, Tera};
+
+use misato_utils::settings::Settings;
+
+pub mod file;
+pub mod smtp;
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailerError {
+    pub message: String,
+}
+
+impl std::fmt::Display for MailerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl MailerError {
+    pub fn build(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError>;
+}
+
+/// Renders the mail templates and hands them to the configured mailer.
+pub struct MailService {
+    mailer: Arc<dyn Mailer>,
+    templates: Tera,
+    pub public_url: String,
+    pub frontend_url: String,
+}
+
+impl MailService {
+    pub fn init(settings: &Settings) -> Result<Self, MailerError> {
+        let mailer: Arc<dyn Mailer> = match settings.mailer.as_str() {
+            "smtp" => Arc::new(smtp::SmtpMailer::init(settings)?),
+            "file" => Arc::new(file::FileMailer::init(Some(
+                settings.mail_directory.clone(),
+            ))),
+            "stdout" => Arc::new(file::FileMailer::init(None)),
+            other => return Err(MailerError::build(format!("[{}]: Unknown mailer.", other))),
+        };
+        let templates = Tera::new(&format!("{}/**/*.tera", settings.mail_templates))
+            .map_err(MailerError::build)?;
+        Ok(Self {
+            mailer,
+            templates,
+            public_url: settings.public_url.trim_end_matches('/').to_string(),
+            frontend_url: settings.frontend_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn render(
+        &self,
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &Context,
+    ) -> Result<Mail, MailerError> {
+        let mut context = context.clone();
+        context.insert("public_url", &self.public_url);
+        context.insert("frontend_url", &self.frontend_url);
+        let body = self
+            .templates
+            .render(template, &context)
+            .map_err(MailerError::build)?;
+        Ok(Mail {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body,
+        })
+    }
+
+    pub async fn send_template(
+        &self,
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &Context,
+    ) -> Result<(), MailerError> {
+        let mail = self.render(to, subject, template, context)?;
+        self.mailer.send(&mail).await
+    }
+
+    /// Sends in the background, the caller answers without waiting for the mail server.
+    /// Failures to send are only logged.
+    pub fn spawn_template(
+        &self,
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &Context,
+    ) -> Result<(), MailerError> {
+        let mail = self.render(to, subject, template, context)?;
+        let mailer = self.mailer.clone();
+        rocket::tokio::spawn(async move {
+            if let Err(error) = mailer.send(&mail).await {
+                tracing::error!(%error, subject = %mail.subject, "Cannot send an email");
+            }
+        });
+        Ok(())
+    }
+}
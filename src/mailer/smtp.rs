@@ -0,0 +1,50 @@
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use misato_utils::settings::Settings;
+
+use super::{Mail, Mailer, MailerError};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn init(settings: &Settings) -> Result<Self, MailerError> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.smtp_host)
+            .map_err(MailerError::build)?
+            .port(settings.smtp_port);
+        if !settings.smtp_username.is_empty() {
+            transport = transport.credentials(Credentials::new(
+                settings.smtp_username.clone(),
+                settings.smtp_password.clone(),
+            ));
+        }
+        Ok(Self {
+            transport: transport.build(),
+            from: settings.mail_from.parse().map_err(MailerError::build)?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), MailerError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().map_err(MailerError::build)?)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(MailerError::build)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(MailerError::build)
+    }
+}
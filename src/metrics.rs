@@ -0,0 +1,224 @@
+//! Counters and histograms of the process, served by `/metrics` in the Prometheus text format.
+//!
+//! Kept in memory since the start of the process, Prometheus computes the rates from them.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use misato_database::measured::OperationObserver;
+
+/// Upper bounds in seconds, the last bucket is `+Inf`.
+const REQUEST_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const OPERATION_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Quoted label value, escaped as the text format requires.
+fn label(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    /// By method, route and status.
+    requests: Mutex<BTreeMap<(String, String, u16), Histogram>>,
+    /// By guard and reason.
+    auth_failures: Mutex<BTreeMap<(&'static str, String), u64>>,
+    /// By manager and method, then the failed calls.
+    operations: Mutex<BTreeMap<(&'static str, &'static str), Histogram>>,
+    operation_errors: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+impl Metrics {
+    /// `route` is the template of the matched route, so that paths with parameters add up.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        self.requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert_with(|| Histogram::new(REQUEST_BUCKETS))
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn auth_failure(&self, guard: &'static str, reason: &str) {
+        *self
+            .auth_failures
+            .lock()
+            .unwrap()
+            .entry((guard, reason.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Everything in the text format, `sessions` is read from the database at each scrape.
+    pub fn render(&self, sessions: Option<u64>) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "misato_http_requests_total",
+            "counter",
+            "Requests answered, by route and status.",
+        );
+        let requests = self.requests.lock().unwrap();
+        for ((method, route, status), histogram) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "misato_http_requests_total{{method={},route={},status=\"{}\"}} {}",
+                label(method),
+                label(route),
+                status,
+                histogram.count
+            );
+        }
+        header(
+            &mut out,
+            "misato_http_request_duration_seconds",
+            "histogram",
+            "Time to answer requests, by route and status.",
+        );
+        for ((method, route, status), histogram) in requests.iter() {
+            let labels = format!(
+                "method={},route={},status=\"{}\"",
+                label(method),
+                label(route),
+                status
+            );
+            histogram.render(&mut out, "misato_http_request_duration_seconds", &labels);
+        }
+        drop(requests);
+
+        header(
+            &mut out,
+            "misato_auth_failures_total",
+            "counter",
+            "Requests refused by an authentication guard, by reason.",
+        );
+        for ((guard, reason), count) in self.auth_failures.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "misato_auth_failures_total{{guard={},reason={}}} {}",
+                label(guard),
+                label(reason),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "misato_database_operation_duration_seconds",
+            "histogram",
+            "Time spent in the stores, by manager and method.",
+        );
+        for ((manager, method), histogram) in self.operations.lock().unwrap().iter() {
+            let labels = format!("manager={},method={}", label(manager), label(method));
+            histogram.render(
+                &mut out,
+                "misato_database_operation_duration_seconds",
+                &labels,
+            );
+        }
+        header(
+            &mut out,
+            "misato_database_operation_errors_total",
+            "counter",
+            "Calls to the stores that failed, by manager and method.",
+        );
+        for ((manager, method), count) in self.operation_errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "misato_database_operation_errors_total{{manager={},method={}}} {}",
+                label(manager),
+                label(method),
+                count
+            );
+        }
+
+        if let Some(sessions) = sessions {
+            header(
+                &mut out,
+                "misato_active_sessions",
+                "gauge",
+                "User tokens that have not expired.",
+            );
+            let _ = writeln!(out, "misato_active_sessions {}", sessions);
+        }
+        out
+    }
+}
+
+impl OperationObserver for Metrics {
+    fn observe(&self, manager: &'static str, method: &'static str, duration: Duration, ok: bool) {
+        self.operations
+            .lock()
+            .unwrap()
+            .entry((manager, method))
+            .or_insert_with(|| Histogram::new(OPERATION_BUCKETS))
+            .observe(duration.as_secs_f64());
+        if !ok {
+            *self
+                .operation_errors
+                .lock()
+                .unwrap()
+                .entry((manager, method))
+                .or_default() += 1;
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use misato_database::models::export_model::ExportStatus;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ExportInfos {
+    pub id: String,
+    pub status: ExportStatus,
+    pub timestamp: u64,
+    pub expiration_timestamp: u64,
+    pub size: Option<u64>,
+    /// Signed single-use download link, once the export is ready.
+    pub url: Option<String>,
+    pub url_expiration_timestamp: Option<u64>,
+}
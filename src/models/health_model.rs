@@ -0,0 +1,30 @@
+use rocket::serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Liveness {
+    pub status: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DatabaseHealth {
+    pub connected: bool,
+    /// Round trip of the ping, when it answered in time.
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+    /// Connection attempts since the start of the process.
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Readiness {
+    /// `ready`, or `unavailable` along with a 503.
+    pub status: &'static str,
+    pub version: &'static str,
+    pub database: DatabaseHealth,
+    /// Migrations of this build the database is missing, unknown while it is unreachable.
+    pub pending_migrations: Option<Vec<String>>,
+}
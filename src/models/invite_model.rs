@@ -0,0 +1,49 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use misato_database::models::user_model::UserRoleType;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateInvite {
+    pub max_uses: u32,
+    /// Lifetime in seconds, never expires when missing.
+    pub expires_in: Option<u64>,
+    pub role: Option<UserRoleType>,
+    pub group: Option<String>,
+}
+
+/// The code is only shown once, at creation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InviteCode {
+    pub id: String,
+    pub code: String,
+    pub max_uses: u32,
+    pub expiration_timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InviteRedemption {
+    pub uuid: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InviteInfos {
+    pub id: String,
+    pub timestamp: u64,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expiration_timestamp: u64,
+    pub role: UserRoleType,
+    pub groups: Vec<String>,
+    pub redemptions: Vec<InviteRedemption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InviteId {
+    pub id: String,
+}
@@ -0,0 +1,11 @@
+pub mod credentials_model;
+pub mod export_model;
+pub mod health_model;
+pub mod invite_model;
+pub mod oauth_model;
+pub mod oidc_model;
+pub mod profile_model;
+pub mod recovery_model;
+pub mod signup_model;
+pub mod totp_model;
+pub mod users_model;
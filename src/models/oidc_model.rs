@@ -0,0 +1,40 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::models::totp_model::LoginResult;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OidcAuthorization {
+    /// Where to send the user, the provider then redirects to the callback.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UnlinkIdentity {
+    pub provider: String,
+    pub subject: String,
+}
+
+/// One-time code the callback redirects the frontend with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OidcExchange {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum OidcCallbackResult {
+    Login(LoginResult),
+    Linked(Vec<Identity>),
+}
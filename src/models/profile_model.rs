@@ -0,0 +1,39 @@
+use rocket::serde::{Deserialize, Serialize};
+
+/// Profile as seen by its owner.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Profile {
+    pub uuid: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub timestamp: u64,
+}
+
+/// Profile as seen by anyone, only fields safe to publish.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PublicProfile {
+    pub uuid: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Replaces the editable fields, missing or empty ones are cleared.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UpdateProfile {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
@@ -0,0 +1,20 @@
+use rocket::serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Signup {
+    pub username: String,
+    pub password: String,
+    pub invite_code: Option<String>,
+    pub challenge: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Find a `nonce` such that SHA-256(`challenge:nonce`) starts with `difficulty` zero bits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignupChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expiration_timestamp: u64,
+}
@@ -0,0 +1,54 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use misato::models::account_model::AccountTokenInfos;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Either proves the user is still the one who enabled it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpDisable {
+    pub password: Option<String>,
+    /// Either a code from the authenticator or one of the recovery codes.
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpRecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpChallenge {
+    pub challenge: String,
+    pub expiration_timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpChallengeResponse {
+    pub challenge: String,
+    /// Either a code from the authenticator or one of the recovery codes.
+    pub code: String,
+}
+
+/// Login answers with a session, or with a challenge when two-factor is enabled.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum LoginResult {
+    Session(AccountTokenInfos),
+    Challenge(TotpChallenge),
+}
@@ -0,0 +1,90 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use misato_database::{
+    models::user_model::{UserRoleType, UserStatus},
+    user_manager::UserSort,
+};
+
+/// Every filter is optional, an empty body lists the first page of all users.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UserListQuery {
+    pub role: Option<UserRoleType>,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub last_login_after: Option<u64>,
+    pub last_login_before: Option<u64>,
+    pub locked: Option<bool>,
+    pub username_prefix: Option<String>,
+    pub sort: UserSort,
+    pub descending: bool,
+    /// Starts at 1.
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserSummary {
+    pub uuid: String,
+    pub username: String,
+    pub role: UserRoleType,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub status: UserStatus,
+    pub locked: bool,
+    pub timestamp: u64,
+    pub last_login: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserList {
+    pub users: Vec<UserSummary>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum BulkAction {
+    Suspend,
+    Reactivate,
+    Delete,
+    ClearTokens,
+    ChangeRole,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkOperation {
+    pub uuids: Vec<String>,
+    pub action: BulkAction,
+    /// Required by `change_role`.
+    pub role: Option<UserRoleType>,
+    /// Required by `suspend`.
+    pub reason: Option<String>,
+    /// Suspension length in seconds, indefinite when missing.
+    pub duration: Option<u64>,
+    /// Reports what would happen without changing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkItemResult {
+    pub uuid: String,
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkReport {
+    pub dry_run: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<BulkItemResult>,
+}
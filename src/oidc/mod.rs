@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::serde::Deserialize;
+use rocket::tokio::sync::RwLock;
+use url::Url;
+
+use misato_security::{generate_token, pkce};
+use misato_utils::{
+    bounded::BoundedMap,
+    get_current_timestamp,
+    settings::{OidcProviderSettings, Settings},
+};
+
+use crate::errors::api_errors;
+use crate::models::oidc_model::OidcCallbackResult;
+
+/// Time given to the user to go through the provider, in seconds.
+const AUTHORIZATION_DURATION: u64 = 10 * 60;
+/// Discovery documents and keys are fetched again after this, in seconds.
+const METADATA_DURATION: u64 = 60 * 60;
+/// Authorizations kept at most, the least recently started are forgotten first.
+const PENDING_SIZE: usize = 10_000;
+/// Time the frontend has to exchange the code of a callback, in seconds.
+const RESULT_DURATION: u64 = 60;
+/// Cookie holding the secret that binds authorizations to the browser starting them.
+pub const BINDING_COOKIE: &str = "misato_oidc_binding";
+
+#[derive(Debug)]
+pub struct OidcError {
+    pub message: String,
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl OidcError {
+    pub fn build(message: impl ToString) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// What the authorization was started for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OidcIntent {
+    Login,
+    /// Link the identity to the user with this uuid.
+    Link(String),
+}
+
+struct PendingAuthorization {
+    provider: String,
+    verifier: String,
+    nonce: String,
+    intent: OidcIntent,
+    /// Secret of the client that started it, the callback must come from the same one.
+    binding: String,
+    expiration_timestamp: u64,
+}
+
+/// What a callback ended with, kept until the frontend exchanges its code.
+pub type CallbackOutcome = Result<OidcCallbackResult, api_errors::Error>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Clone)]
+struct ProviderMetadata {
+    discovery: Discovery,
+    keys: JwkSet,
+    timestamp: u64,
+}
+
+struct OidcProvider {
+    settings: OidcProviderSettings,
+    metadata: RwLock<Option<ProviderMetadata>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Authorization code flow with PKCE against the configured providers.
+pub struct OidcService {
+    providers: HashMap<String, OidcProvider>,
+    client: reqwest::Client,
+    public_url: String,
+    pending: Mutex<BoundedMap<String, PendingAuthorization>>,
+    /// Outcomes of callbacks by one-time code, with their expiration.
+    outcomes: Mutex<BoundedMap<String, (u64, CallbackOutcome)>>,
+}
+
+impl OidcService {
+    pub fn init(settings: &Settings) -> Self {
+        Self {
+            providers: settings
+                .oidc_providers
+                .iter()
+                .map(|provider| {
+                    (
+                        provider.name.clone(),
+                        OidcProvider {
+                            settings: provider.clone(),
+                            metadata: RwLock::new(None),
+                        },
+                    )
+                })
+                .collect(),
+            client: reqwest::Client::new(),
+            public_url: settings.public_url.trim_end_matches('/').to_string(),
+            pending: Mutex::new(BoundedMap::new(PENDING_SIZE)),
+            outcomes: Mutex::new(BoundedMap::new(PENDING_SIZE)),
+        }
+    }
+
+    pub fn provider_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn provider(&self, name: &str) -> Result<&OidcProvider, OidcError> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| OidcError::build(format!("[{}]: Unknown provider.", name)))
+    }
+
+    /// Secret of this client, kept in a cookie sent back to the callback.
+    /// Reused while present so that several authorizations can run side by side, it is
+    /// scoped to every path as `/user/identities` starts some of them.
+    pub fn client_binding(&self, cookies: &CookieJar<'_>) -> String {
+        if let Some(cookie) = cookies.get(BINDING_COOKIE) {
+            return cookie.value().to_string();
+        }
+        let binding = generate_token(32);
+        cookies.add(
+            Cookie::build(BINDING_COOKIE, binding.clone())
+                .path("/")
+                .http_only(true)
+                .secure(self.public_url.starts_with("https://"))
+                .same_site(SameSite::Lax)
+                .max_age(rocket::time::Duration::seconds(
+                    AUTHORIZATION_DURATION as i64,
+                ))
+                .finish(),
+        );
+        binding
+    }
+
+    /// Keeps the outcome of a callback and returns the one-time code to exchange it with.
+    pub fn hand_over(&self, outcome: CallbackOutcome) -> String {
+        let code = generate_token(32);
+        let expiration_timestamp = get_current_timestamp() + RESULT_DURATION * 1000;
+        self.outcomes
+            .lock()
+            .unwrap()
+            .insert(code.clone(), (expiration_timestamp, outcome));
+        code
+    }
+
+    /// The outcome handed over with `code`, at most once.
+    pub fn take_outcome(&self, code: &str) -> Option<CallbackOutcome> {
+        match self.outcomes.lock().unwrap().remove(code) {
+            Some((expiration_timestamp, outcome))
+                if expiration_timestamp >= get_current_timestamp() =>
+            {
+                Some(outcome)
+            }
+            _ => None,
+        }
+    }
+
+    fn redirect_uri(&self, name: &str) -> String {
+        format!("{}/oidc/{}/callback", self.public_url, name)
+    }
+
+    async fn fetch_metadata(&self, provider: &OidcProvider) -> Result<ProviderMetadata, OidcError> {
+        let issuer = provider.settings.issuer.trim_end_matches('/');
+        let discovery: Discovery = self
+            .client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(OidcError::build)?
+            .json()
+            .await
+            .map_err(OidcError::build)?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            return Err(OidcError::build(format!(
+                "[{}]: Discovery document of another issuer.",
+                discovery.issuer
+            )));
+        }
+        let keys: JwkSet = self
+            .client
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(OidcError::build)?
+            .json()
+            .await
+            .map_err(OidcError::build)?;
+        Ok(ProviderMetadata {
+            discovery,
+            keys,
+            timestamp: get_current_timestamp(),
+        })
+    }
+
+    /// Cached metadata, fetched again when stale or when `refresh` is set (e.g. unknown key id).
+    async fn metadata(
+        &self,
+        provider: &OidcProvider,
+        refresh: bool,
+    ) -> Result<ProviderMetadata, OidcError> {
+        if !refresh {
+            if let Some(metadata) = provider.metadata.read().await.as_ref() {
+                if metadata.timestamp + METADATA_DURATION * 1000 > get_current_timestamp() {
+                    return Ok(metadata.clone());
+                }
+            }
+        }
+        let metadata = self.fetch_metadata(provider).await?;
+        *provider.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// URL of the provider to send the user to, `binding` is the secret the client presents
+    /// again on the callback.
+    pub async fn authorization_url(
+        &self,
+        name: &str,
+        intent: OidcIntent,
+        binding: &str,
+    ) -> Result<String, OidcError> {
+        let provider = self.provider(name)?;
+        let metadata = self.metadata(provider, false).await?;
+
+        let state = generate_token(32);
+        let verifier = pkce::generate_verifier();
+        let nonce = generate_token(32);
+        let mut url =
+            Url::parse(&metadata.discovery.authorization_endpoint).map_err(OidcError::build)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.settings.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri(name))
+            .append_pair("scope", &provider.settings.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &pkce::code_challenge(&verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        let timestamp = get_current_timestamp();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, authorization| authorization.expiration_timestamp >= timestamp);
+        pending.insert(
+            state,
+            PendingAuthorization {
+                provider: name.to_string(),
+                verifier,
+                nonce,
+                intent,
+                binding: binding.to_string(),
+                expiration_timestamp: timestamp + AUTHORIZATION_DURATION * 1000,
+            },
+        );
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the code and returns the validated claims of the ID token.
+    /// A state can only be used once, by the client that started the authorization.
+    pub async fn callback(
+        &self,
+        name: &str,
+        code: &str,
+        state: &str,
+        binding: Option<&str>,
+    ) -> Result<(OidcIntent, IdTokenClaims), OidcError> {
+        let provider = self.provider(name)?;
+        let authorization = match self.pending.lock().unwrap().remove(state) {
+            Some(authorization)
+                if authorization.provider == name
+                    && Some(authorization.binding.as_str()) == binding
+                    && authorization.expiration_timestamp >= get_current_timestamp() =>
+            {
+                authorization
+            }
+            _ => return Err(OidcError::build("Invalid or expired state.")),
+        };
+        let metadata = self.metadata(provider, false).await?;
+
+        let redirect_uri = self.redirect_uri(name);
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &redirect_uri),
+            ("client_id", &provider.settings.client_id),
+            ("code_verifier", &authorization.verifier),
+        ];
+        if !provider.settings.client_secret.is_empty() {
+            form.push(("client_secret", &provider.settings.client_secret));
+        }
+        let response = self
+            .client
+            .post(&metadata.discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(OidcError::build)?;
+        if !response.status().is_success() {
+            return Err(OidcError::build(format!(
+                "[{}]: Token request refused.",
+                response.status()
+            )));
+        }
+        let token: TokenResponse = response.json().await.map_err(OidcError::build)?;
+
+        let claims = match self.validate_id_token(provider, &metadata, &token.id_token) {
+            Err(None) => {
+                // Unknown key, the provider may have rotated them.
+                let metadata = self.metadata(provider, true).await?;
+                self.validate_id_token(provider, &metadata, &token.id_token)
+            }
+            result => result,
+        }
+        .map_err(|error| {
+            error.unwrap_or_else(|| OidcError::build("ID token signed with an unknown key."))
+        })?;
+        if claims.nonce.as_deref() != Some(authorization.nonce.as_str()) {
+            return Err(OidcError::build("Invalid ID token nonce."));
+        }
+        Ok((authorization.intent, claims))
+    }
+
+    /// Fails with `None` when no key matches the token.
+    fn validate_id_token(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, Option<OidcError>> {
+        let header = decode_header(id_token).map_err(|error| Some(OidcError::build(error)))?;
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+        ) {
+            return Err(Some(OidcError::build(format!(
+                "[{:?}]: Unsupported ID token algorithm.",
+                header.alg
+            ))));
+        }
+        let jwk = metadata
+            .keys
+            .keys
+            .iter()
+            .find(|jwk| header.kid.is_none() || jwk.common.key_id == header.kid)
+            .ok_or(None)?;
+        let key = match &jwk.algorithm {
+            AlgorithmParameters::RSA(rsa) => DecodingKey::from_rsa_components(&rsa.n, &rsa.e)
+                .map_err(|error| Some(OidcError::build(error)))?,
+            _ => return Err(Some(OidcError::build("Only RSA keys are supported."))),
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.discovery.issuer]);
+        validation.set_audience(&[&provider.settings.client_id]);
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|error| Some(OidcError::build(error)))
+    }
+}
@@ -6,7 +6,7 @@ use misato_security::password::*;
 
 use misato::models::account_model;
 
-use crate::errors::account_errors;
+use crate::errors::api_errors;
 
 const TOKEN_DURATION: u64 = 24 * 60 * 60;
 
@@ -16,48 +16,57 @@ use crate::fairings::api_authentication::ApiUserToken;
 pub async fn signup(
     api: ApiUserToken,
     db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    params: &State<PasswordParams>,
     input: Json<account_model::AccountCredentials>,
-) -> Result<Json<account_model::AccountTokenInfos>, account_errors::Error> {
+) -> Result<Json<account_model::AccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
+    }
+    let rules = policy.check(&input.username, &input.password);
+    if !rules.is_empty() {
+        return Err(api_errors::Error::from_password_rules(&rules));
     }
     let mut user = user_model::User::create(
         input.username.to_string(),
-        Password::hash_password(input.password.as_bytes()),
+        Password::hash_password_with(input.password.as_bytes(), params),
         None,
     );
 
     match db.usermanager.username_exists(&user.username).await {
         Ok(exists) => {
             if exists {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!(
-                            "[{}]: Username already used by an account.",
-                            input.username
-                        )),
-                    ),
-                });
+                return Err(api_errors::Error::username_taken(&input.username));
             }
         }
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 
-    match db.usermanager.create_user(&user).await {
-        Ok(_) => {
-            let token = user.new_token(TOKEN_DURATION);
-            let _ = db.usermanager.save_token(&user.uuid, &token).await;
+    let mut work = match db.begin().await {
+        Ok(work) => work,
+        Err(error) => {
+            return Err(error.into());
+        }
+    };
+    let result = async {
+        work.create_user(&user).await?;
+        let token = user.new_token(TOKEN_DURATION);
+        work.save_token(&user.uuid, &token).await?;
+        Ok(token)
+    }
+    .await;
+    let result = match result {
+        Ok(token) => work.commit().await.map(|_| token),
+        Err(error) => {
+            work.abort().await;
+            Err(error)
+        }
+    };
+
+    match result {
+        Ok(token) => {
             return Ok(Json(account_model::AccountTokenInfos {
                 token: token.token.clone(),
                 timestamp: token.timestamp,
@@ -65,14 +74,11 @@ pub async fn signup(
                 uuid: user.uuid,
             }));
         }
-        Err(_error) => {
-            println!("{:?}", _error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+        Err(misato_database::Error::DuplicateKey(_)) => {
+            return Err(api_errors::Error::username_taken(&input.username));
+        }
+        Err(error) => {
+            return Err(error.into());
         }
     }
 }
@@ -82,11 +88,9 @@ pub async fn profile(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountUuid>,
-) -> Result<Json<account_model::Account>, account_errors::Error> {
+) -> Result<Json<account_model::Account>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.get_user(None, Some(&input.uuid)).await {
         Ok(user) => match user {
@@ -97,22 +101,14 @@ pub async fn profile(
                 }));
             }
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -122,11 +118,9 @@ pub async fn profile_from_token(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountToken>,
-) -> Result<Json<account_model::Account>, account_errors::Error> {
+) -> Result<Json<account_model::Account>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.get_user_from_token(&input.token).await {
         Ok(user) => match user {
@@ -137,25 +131,14 @@ pub async fn profile_from_token(
                 }));
             }
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!(
-                            "[{}]: Token not related to any account.",
-                            input.token
-                        )),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Token not related to any account.",
+                    input.token
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -165,11 +148,9 @@ pub async fn refresh_token(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountUuid>,
-) -> Result<Json<account_model::AccountTokenInfos>, account_errors::Error> {
+) -> Result<Json<account_model::AccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.get_user(None, Some(&input.uuid)).await {
         Ok(mut user) => match &mut user {
@@ -184,22 +165,14 @@ pub async fn refresh_token(
                 }));
             }
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -209,11 +182,9 @@ pub async fn check_token(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountToken>,
-) -> Result<Json<account_model::AccountTokenInfos>, account_errors::Error> {
+) -> Result<Json<account_model::AccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.get_user_from_token(&input.token).await {
         Ok(user) => match user {
@@ -229,25 +200,14 @@ pub async fn check_token(
                 }));
             }
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!(
-                            "[{}]: Token not related to any account.",
-                            input.token
-                        )),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Token not related to any account.",
+                    input.token
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -257,34 +217,24 @@ pub async fn delete(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountUuid>,
-) -> Result<Json<String>, account_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.delete_user(None, Some(&input.uuid)).await {
         Ok(user) => match user {
-            Some(count) if count.deleted_count >= 1 => {
+            Some(count) if count.modified_count >= 1 => {
                 return Ok(Json("Account deleted.".to_string()));
             }
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -294,32 +244,22 @@ pub async fn clear_tokens(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<account_model::AccountUuid>,
-) -> Result<Json<String>, account_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(account_errors::Error {
-            content: account_model::AccountError::build(400, Some("No permission.".to_string())),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.usermanager.clear_tokens(&input.uuid).await {
         Ok(user) => match user.modified_count {
             1 => return Ok(Json("Tokens cleared.".to_string())),
             _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -0,0 +1,97 @@
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{
+    database::*,
+    models::{apiuser_model, invite_model::Invite, user_model},
+};
+
+use crate::errors::api_errors;
+use crate::fairings::api_authentication::ApiUserToken;
+use crate::models::invite_model;
+
+fn check_admin(api: &ApiUserToken) -> Result<(), api_errors::Error> {
+    if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
+        return Err(api_errors::Error::no_permission());
+    }
+    Ok(())
+}
+
+#[post("/admin/invite/create", data = "<input>")]
+pub async fn create(
+    api: ApiUserToken,
+    db: &State<Database>,
+    input: Json<invite_model::CreateInvite>,
+) -> Result<Json<invite_model::InviteCode>, api_errors::Error> {
+    check_admin(&api)?;
+    if input.max_uses == 0 {
+        return Err(api_errors::Error::bad_request(
+            "An invite must allow at least one use.",
+        ));
+    }
+    let access = user_model::UserAccess {
+        role: input.role.clone().unwrap_or_default(),
+        groups: input.group.iter().cloned().collect(),
+        ..Default::default()
+    };
+    let (code, invite) = Invite::create(input.max_uses, input.expires_in, access);
+    match db.invitemanager.create_invite(&invite).await {
+        Ok(_) => Ok(Json(invite_model::InviteCode {
+            id: invite.id,
+            code,
+            max_uses: invite.max_uses,
+            expiration_timestamp: invite.expiration_timestamp,
+        })),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[post("/admin/invite/list")]
+pub async fn list(
+    api: ApiUserToken,
+    db: &State<Database>,
+) -> Result<Json<Vec<invite_model::InviteInfos>>, api_errors::Error> {
+    check_admin(&api)?;
+    let invites = db.invitemanager.get_invites().await?;
+    Ok(Json(
+        invites
+            .into_iter()
+            .map(|invite| invite_model::InviteInfos {
+                id: invite.id,
+                timestamp: invite.timestamp,
+                max_uses: invite.max_uses,
+                uses: invite.uses,
+                expiration_timestamp: invite.expiration_timestamp,
+                role: invite.access.role,
+                groups: invite.access.groups,
+                redemptions: invite
+                    .redemptions
+                    .into_iter()
+                    .map(|redemption| invite_model::InviteRedemption {
+                        uuid: redemption.uuid,
+                        timestamp: redemption.timestamp,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    ))
+}
+
+#[post("/admin/invite/delete", data = "<input>")]
+pub async fn delete(
+    api: ApiUserToken,
+    db: &State<Database>,
+    input: Json<invite_model::InviteId>,
+) -> Result<Json<String>, api_errors::Error> {
+    check_admin(&api)?;
+    match db.invitemanager.delete_invite(&input.id).await {
+        Ok(result) if result.deleted_count == 1 => {
+            Ok(Json(format!("[{}]: Invite deleted.", input.id)))
+        }
+        Ok(_) => Err(api_errors::Error::not_found(format!(
+            "[{}]: Invite doesn't exist.",
+            input.id
+        ))),
+        Err(error) => Err(error.into()),
+    }
+}
@@ -1 +1,3 @@
 pub mod account;
+pub mod invite;
+pub mod users;
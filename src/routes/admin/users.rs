@@ -0,0 +1,222 @@
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{
+    database::*,
+    models::{apiuser_model, loginattempt_model::LoginAttempt, user_model},
+    user_manager::UserSearch,
+};
+use misato_utils::get_current_timestamp;
+
+use crate::errors::{api_errors, database_errors::describe};
+use crate::fairings::api_authentication::ApiUserToken;
+use crate::models::users_model;
+
+const DEFAULT_PER_PAGE: u64 = 50;
+const MAX_PER_PAGE: u64 = 500;
+const MAX_BULK_SIZE: usize = 1000;
+
+fn check_admin(api: &ApiUserToken) -> Result<(), api_errors::Error> {
+    if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
+        return Err(api_errors::Error::no_permission());
+    }
+    Ok(())
+}
+
+/// Usernames whose login is currently locked after failed attempts, in lowercase.
+async fn locked_usernames(db: &Database) -> Result<Vec<String>, api_errors::Error> {
+    db.loginattemptmanager
+        .get_locked_keys(&LoginAttempt::account_key(""))
+        .await
+        .map_err(api_errors::Error::from)
+}
+
+#[post("/admin/users", data = "<input>")]
+pub async fn list(
+    api: ApiUserToken,
+    db: &State<Database>,
+    input: Json<users_model::UserListQuery>,
+) -> Result<Json<users_model::UserList>, api_errors::Error> {
+    check_admin(&api)?;
+    let page = input.page.unwrap_or(1).max(1);
+    let per_page = input
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let locked = locked_usernames(db).await?;
+    let mut search = UserSearch {
+        role: input.role.clone(),
+        created_after: input.created_after,
+        created_before: input.created_before,
+        last_login_after: input.last_login_after,
+        last_login_before: input.last_login_before,
+        username_prefix: input.username_prefix.clone(),
+        ..Default::default()
+    };
+    match input.locked {
+        Some(true) => search.include_usernames = Some(locked.clone()),
+        Some(false) => search.exclude_usernames = locked.clone(),
+        None => {}
+    }
+
+    let (users, total) = db
+        .usermanager
+        .search_users(
+            &search,
+            &input.sort,
+            input.descending,
+            (page - 1) * per_page,
+            per_page as i64,
+        )
+        .await?;
+    Ok(Json(users_model::UserList {
+        users: users
+            .into_iter()
+            .map(|user| users_model::UserSummary {
+                locked: locked.contains(&user.username.to_lowercase()),
+                uuid: user.uuid,
+                username: user.username,
+                role: user.access.role,
+                email: user.email,
+                email_verified: user.email_verified,
+                status: user.status,
+                timestamp: user.timestamp,
+                last_login: user.last_login,
+            })
+            .collect(),
+        total,
+        page,
+        per_page,
+    }))
+}
+
+/// Applies the action to one user, the message describes what was (or would be) done.
+async fn apply(
+    db: &Database,
+    input: &users_model::BulkOperation,
+    user: &user_model::User,
+) -> Result<String, String> {
+    use users_model::BulkAction;
+
+    let active = user.is_active(get_current_timestamp());
+    let result = match (&input.action, input.dry_run) {
+        (_, _) if matches!(user.status, user_model::UserStatus::Purged { .. }) => {
+            return Err("Account purged.".to_string())
+        }
+        (BulkAction::Delete, _)
+            if matches!(user.status, user_model::UserStatus::Deleted { .. }) =>
+        {
+            return Err("Already deleted.".to_string())
+        }
+        (BulkAction::Reactivate, _) if user.status == user_model::UserStatus::Active => {
+            return Err("Already active.".to_string())
+        }
+        (BulkAction::ChangeRole, _) if input.role.as_ref() == Some(&user.access.role) => {
+            return Err("Already has this role.".to_string())
+        }
+        (BulkAction::Suspend, true) if active => return Ok("Would be suspended.".to_string()),
+        (BulkAction::Suspend, true) => return Ok("Suspension would be replaced.".to_string()),
+        (BulkAction::Reactivate, true) => return Ok("Would be reactivated.".to_string()),
+        (BulkAction::Delete, true) => return Ok("Would be deleted.".to_string()),
+        (BulkAction::ClearTokens, true) => return Ok("Sessions would be revoked.".to_string()),
+        (BulkAction::ChangeRole, true) => return Ok("Role would be changed.".to_string()),
+        (BulkAction::Suspend, false) => {
+            let timestamp = get_current_timestamp();
+            let status = user_model::UserStatus::Suspended {
+                reason: input
+                    .reason
+                    .as_deref()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                timestamp,
+                expiration_timestamp: input.duration.map(|duration| timestamp + duration * 1000),
+            };
+            db.usermanager
+                .set_status(&user.uuid, &status)
+                .await
+                .map(|_| "Suspended.")
+        }
+        (BulkAction::Reactivate, false) => db
+            .usermanager
+            .set_status(&user.uuid, &user_model::UserStatus::Active)
+            .await
+            .map(|_| "Reactivated."),
+        (BulkAction::Delete, false) => db
+            .usermanager
+            .delete_user(None, Some(&user.uuid))
+            .await
+            .map(|_| "Deleted."),
+        (BulkAction::ClearTokens, false) => db
+            .usermanager
+            .clear_tokens(&user.uuid)
+            .await
+            .map(|_| "Sessions revoked."),
+        (BulkAction::ChangeRole, false) => match &input.role {
+            Some(role) => db
+                .usermanager
+                .set_role(&user.uuid, role)
+                .await
+                .map(|_| "Role changed."),
+            None => return Err("Missing role.".to_string()),
+        },
+    };
+    result
+        .map(str::to_string)
+        .map_err(|error| describe(&error).1.to_string())
+}
+
+#[post("/admin/users/bulk", data = "<input>")]
+pub async fn bulk(
+    api: ApiUserToken,
+    db: &State<Database>,
+    input: Json<users_model::BulkOperation>,
+) -> Result<Json<users_model::BulkReport>, api_errors::Error> {
+    check_admin(&api)?;
+    if input.uuids.len() > MAX_BULK_SIZE {
+        return Err(api_errors::Error::bad_request(format!(
+            "At most {} users per operation.",
+            MAX_BULK_SIZE
+        )));
+    }
+    let reason = input.reason.as_deref().map(str::trim).unwrap_or_default();
+    if input.action == users_model::BulkAction::Suspend && reason.is_empty() {
+        return Err(api_errors::Error::bad_request(
+            "The suspend action needs a reason.",
+        ));
+    }
+    if input.action == users_model::BulkAction::ChangeRole && input.role.is_none() {
+        return Err(api_errors::Error::bad_request(
+            "The change_role action needs a role.",
+        ));
+    }
+
+    let mut results = Vec::new();
+    for uuid in &input.uuids {
+        let outcome = match db.usermanager.get_user(None, Some(uuid)).await {
+            Ok(Some(user)) => apply(db, &input, &user).await,
+            Ok(None) => Err("Account doesn't exist.".to_string()),
+            Err(error) => Err(describe(&error).1.to_string()),
+        };
+        results.push(match outcome {
+            Ok(message) => users_model::BulkItemResult {
+                uuid: uuid.clone(),
+                success: true,
+                message,
+            },
+            Err(message) => users_model::BulkItemResult {
+                uuid: uuid.clone(),
+                success: false,
+                message,
+            },
+        });
+    }
+    let succeeded = results.iter().filter(|result| result.success).count();
+    Ok(Json(users_model::BulkReport {
+        dry_run: input.dry_run,
+        succeeded,
+        failed: results.len() - succeeded,
+        results,
+    }))
+}
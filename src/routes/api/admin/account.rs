@@ -5,7 +5,7 @@ use misato_database::{database::*, models::*};
 
 use misato::models::apiaccount_model;
 
-use crate::errors::apiaccount_errors;
+use crate::errors::api_errors;
 use crate::fairings::api_authentication::ApiUserToken;
 
 const TOKEN_DURATION: u64 = 24 * 60 * 60;
@@ -15,14 +15,9 @@ pub async fn signup(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<apiaccount_model::ApiAccountUuid>,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some("No permission.".to_string()),
-            ),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     let mut user = apiuser_model::ApiUser::create(input.uuid.clone());
 
@@ -31,76 +26,61 @@ pub async fn signup(
         .get_apiuser(None, Some(&user.uuid.to_string()))
         .await;
     if result.is_ok() && result.as_ref().unwrap().is_some() {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some(format!("[{}]: API Account already exists.", input.uuid)),
-            ),
-        });
+        return Err(api_errors::Error::conflict(format!(
+            "[{}]: API Account already exists.",
+            input.uuid
+        )));
     }
     if result.is_err() {
-        println!("{:?}", result.unwrap_err());
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                500,
-                Some("Database error.".to_string()),
-            ),
-        });
+        return Err(result.unwrap_err().into());
     }
 
     match db.apiusermanager.uuid_exists(&user.uuid).await {
         Ok(exists) => {
             if !exists {
-                return Err(apiaccount_errors::Error {
-                    content: apiaccount_model::ApiAccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                });
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )));
             }
         }
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 
-    match db.apiusermanager.create_apiuser(&user).await {
-        Ok(_) => {
-            let token = user.new_token(TOKEN_DURATION);
-            match db.apiusermanager.set_token(&user.uuid, &token).await {
-                Ok(_) => {
-                    return Ok(Json(apiaccount_model::ApiAccountTokenInfos {
-                        token: token.token,
-                        timestamp: token.timestamp,
-                        expiration_timestamp: token.expiration_timestamp,
-                        uuid: user.uuid,
-                    }));
-                }
-                Err(_error) => {
-                    println!("{:?}", _error);
-                    return Err(apiaccount_errors::Error {
-                        content: apiaccount_model::ApiAccountError::build(
-                            500,
-                            Some("Database error.".to_string()),
-                        ),
-                    });
-                }
-            }
+    let mut work = match db.begin().await {
+        Ok(work) => work,
+        Err(error) => {
+            return Err(error.into());
+        }
+    };
+    let result = async {
+        work.create_apiuser(&user).await?;
+        let token = user.new_token(TOKEN_DURATION);
+        work.set_apiuser_token(&user.uuid, &token).await?;
+        Ok(token)
+    }
+    .await;
+    let result = match result {
+        Ok(token) => work.commit().await.map(|_| token),
+        Err(error) => {
+            work.abort().await;
+            Err(error)
         }
-        Err(_error) => {
-            println!("{:?}", _error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+    };
+
+    match result {
+        Ok(token) => {
+            return Ok(Json(apiaccount_model::ApiAccountTokenInfos {
+                token: token.token,
+                timestamp: token.timestamp,
+                expiration_timestamp: token.expiration_timestamp,
+                uuid: user.uuid,
+            }));
+        }
+        Err(error) => {
+            return Err(error.into());
         }
     }
 }
@@ -110,14 +90,9 @@ pub async fn refresh_token(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<apiaccount_model::ApiAccountUuid>,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some("No permission.".to_string()),
-            ),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db
         .apiusermanager
@@ -136,34 +111,20 @@ pub async fn refresh_token(
                             uuid: user.uuid.clone(),
                         }));
                     }
-                    Err(_error) => {
-                        println!("{:?}", _error);
-                        return Err(apiaccount_errors::Error {
-                            content: apiaccount_model::ApiAccountError::build(
-                                500,
-                                Some("Database error.".to_string()),
-                            ),
-                        });
+                    Err(error) => {
+                        return Err(error.into());
                     }
                 }
             }
             _ => {
-                return Err(apiaccount_errors::Error {
-                    content: apiaccount_model::ApiAccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -173,14 +134,9 @@ pub async fn check_token(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<apiaccount_model::ApiAccountToken>,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some("No permission".to_string()),
-            ),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.apiusermanager.get_apiuser_from_token(&input.token).await {
         Ok(user) => match user {
@@ -194,25 +150,14 @@ pub async fn check_token(
                 }));
             }
             _ => {
-                return Err(apiaccount_errors::Error {
-                    content: apiaccount_model::ApiAccountError::build(
-                        400,
-                        Some(format!(
-                            "[{}]: Token not related to any account.",
-                            &input.token
-                        )),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Token not related to any account.",
+                    &input.token
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -222,14 +167,9 @@ pub async fn delete(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<apiaccount_model::ApiAccountUuid>,
-) -> Result<Json<String>, apiaccount_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some("No permission.".to_string()),
-            ),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db
         .apiusermanager
@@ -241,22 +181,14 @@ pub async fn delete(
                 return Ok(Json("account deleted.".to_string()));
             }
             _ => {
-                return Err(apiaccount_errors::Error {
-                    content: apiaccount_model::ApiAccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -266,35 +198,22 @@ pub async fn clear_tokens(
     api: ApiUserToken,
     db: &State<Database>,
     input: Json<apiaccount_model::ApiAccountUuid>,
-) -> Result<Json<String>, apiaccount_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     if api.apiuser.access.role != apiuser_model::ApiUserRoleType::Admin {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some("No permission.".to_string()),
-            ),
-        });
+        return Err(api_errors::Error::no_permission());
     }
     match db.apiusermanager.clear_tokens(&input.uuid).await {
         Ok(user) => match user.modified_count {
             1 => return Ok(Json("Token removed.".to_string())),
             _ => {
-                return Err(apiaccount_errors::Error {
-                    content: apiaccount_model::ApiAccountError::build(
-                        400,
-                        Some(format!("[{}]: Account doesn't exist.", input.uuid)),
-                    ),
-                })
+                return Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    input.uuid
+                )))
             }
         },
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
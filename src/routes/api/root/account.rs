@@ -5,7 +5,7 @@ use misato_database::{database::*, models::*};
 
 use misato::models::apiaccount_model;
 
-use crate::errors::apiaccount_errors;
+use crate::errors::api_errors;
 use crate::fairings::api_authentication::ApiUserToken;
 use crate::fairings::authentication::UserToken;
 
@@ -15,7 +15,7 @@ const TOKEN_DURATION: u64 = 24 * 60 * 60;
 pub async fn signup(
     user: UserToken,
     db: &State<Database>,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     let user = user.user;
 
     let result = db
@@ -23,56 +23,49 @@ pub async fn signup(
         .get_apiuser(None, Some(&user.uuid.to_string()))
         .await;
     if result.is_ok() && result.as_ref().unwrap().is_some() {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some(format!("[{}]: API Account already exists.", user.uuid)),
-            ),
-        });
+        return Err(api_errors::Error::conflict(format!(
+            "[{}]: API Account already exists.",
+            user.uuid
+        )));
     }
     if result.is_err() {
-        println!("{:?}", result.unwrap_err());
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                500,
-                Some("Database error.".to_string()),
-            ),
-        });
+        return Err(result.unwrap_err().into());
     }
 
     let mut apiuser = apiuser_model::ApiUser::create(user.uuid.clone());
 
-    match db.apiusermanager.create_apiuser(&apiuser).await {
-        Ok(_) => {
-            let token = apiuser.new_token(TOKEN_DURATION);
-            match db.apiusermanager.set_token(&user.uuid, &token).await {
-                Ok(_) => {
-                    return Ok(Json(apiaccount_model::ApiAccountTokenInfos {
-                        token: token.token,
-                        timestamp: token.timestamp,
-                        expiration_timestamp: token.expiration_timestamp,
-                        uuid: user.uuid,
-                    }));
-                }
-                Err(_error) => {
-                    println!("{:?}", _error);
-                    return Err(apiaccount_errors::Error {
-                        content: apiaccount_model::ApiAccountError::build(
-                            500,
-                            Some("Database error.".to_string()),
-                        ),
-                    });
-                }
-            }
+    let mut work = match db.begin().await {
+        Ok(work) => work,
+        Err(error) => {
+            return Err(error.into());
         }
-        Err(_error) => {
-            println!("{:?}", _error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+    };
+    let result = async {
+        work.create_apiuser(&apiuser).await?;
+        let token = apiuser.new_token(TOKEN_DURATION);
+        work.set_apiuser_token(&user.uuid, &token).await?;
+        Ok(token)
+    }
+    .await;
+    let result = match result {
+        Ok(token) => work.commit().await.map(|_| token),
+        Err(error) => {
+            work.abort().await;
+            Err(error)
+        }
+    };
+
+    match result {
+        Ok(token) => {
+            return Ok(Json(apiaccount_model::ApiAccountTokenInfos {
+                token: token.token,
+                timestamp: token.timestamp,
+                expiration_timestamp: token.expiration_timestamp,
+                uuid: user.uuid,
+            }));
+        }
+        Err(error) => {
+            return Err(error.into());
         }
     }
 }
@@ -81,7 +74,7 @@ pub async fn signup(
 pub async fn refresh_token(
     user: UserToken,
     db: &State<Database>,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     let user = user.user;
 
     let result = db
@@ -89,21 +82,13 @@ pub async fn refresh_token(
         .get_apiuser(None, Some(&user.uuid.to_string()))
         .await;
     if result.is_ok() && result.as_ref().unwrap().is_none() {
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                400,
-                Some(format!("[{}]: API Account doesn't exist.", user.uuid)),
-            ),
-        });
+        return Err(api_errors::Error::not_found(format!(
+            "[{}]: API Account doesn't exist.",
+            user.uuid
+        )));
     }
     if result.is_err() {
-        println!("{:?}", result.unwrap_err());
-        return Err(apiaccount_errors::Error {
-            content: apiaccount_model::ApiAccountError::build(
-                500,
-                Some("Database error.".to_string()),
-            ),
-        });
+        return Err(result.unwrap_err().into());
     }
     let token = result.unwrap().unwrap().new_token(TOKEN_DURATION);
     match db.apiusermanager.set_token(&user.uuid, &token).await {
@@ -115,14 +100,8 @@ pub async fn refresh_token(
                 uuid: user.uuid,
             }));
         }
-        Err(_error) => {
-            println!("{:?}", _error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+        Err(error) => {
+            return Err(error.into());
         }
     }
 }
@@ -130,7 +109,7 @@ pub async fn refresh_token(
 #[post("/api/check-token")]
 pub async fn check_token(
     api: ApiUserToken,
-) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, apiaccount_errors::Error> {
+) -> Result<Json<apiaccount_model::ApiAccountTokenInfos>, api_errors::Error> {
     let api = api.apiuser;
     let token = api.token.unwrap();
     return Ok(Json(apiaccount_model::ApiAccountTokenInfos {
@@ -145,20 +124,14 @@ pub async fn check_token(
 pub async fn delete(
     api: ApiUserToken,
     db: &State<Database>,
-) -> Result<Json<String>, apiaccount_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     let token = api.apiuser.token.unwrap().token;
     match db.apiusermanager.delete_apiuser_from_token(&token).await {
         Ok(_) => {
             return Ok(Json("Account deleted.".to_string()));
         }
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
@@ -167,20 +140,14 @@ pub async fn delete(
 pub async fn clear_tokens(
     api: ApiUserToken,
     db: &State<Database>,
-) -> Result<Json<String>, apiaccount_errors::Error> {
+) -> Result<Json<String>, api_errors::Error> {
     let token = api.apiuser.token.unwrap().token;
     match db.apiusermanager.clear_tokens_from_token(&token).await {
         Ok(_) => {
             return Ok(Json("Token removed.".to_string()));
         }
         Err(error) => {
-            println!("{:?}", error);
-            return Err(apiaccount_errors::Error {
-                content: apiaccount_model::ApiAccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
         }
     }
 }
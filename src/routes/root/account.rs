@@ -1,65 +1,230 @@
+use std::net::IpAddr;
+
 use rocket::serde::json::Json;
 use rocket::*;
 
 use misato::models::*;
 
-use misato_database::database::*;
+use misato_database::{database::*, models::loginattempt_model::LoginAttempt, models::user_model};
+use misato_security::password::{verify_dummy_password, Password, PasswordParams, PasswordPolicy};
+use misato_utils::get_current_timestamp;
 
-use crate::errors::account_errors;
+use crate::errors::api_errors;
+use crate::fairings::client_addr::ClientAddr;
+use crate::models::totp_model;
+use crate::routes::user::totp::check_second_factor;
 
 const TOKEN_DURATION: u64 = 24 * 60 * 60;
+const CHALLENGE_DURATION: u64 = 5 * 60;
+
+fn invalid_credentials() -> api_errors::Error {
+    api_errors::Error::unauthorized("Invalid username or password.")
+}
+
+/// Only told once the credentials are known to be right.
+fn account_inactive(user: &user_model::User) -> api_errors::Error {
+    let message = match &user.status {
+        user_model::UserStatus::Suspended {
+            reason,
+            expiration_timestamp: Some(expiration_timestamp),
+            ..
+        } => format!(
+            "[{}]: Account suspended until {}: {}",
+            user.uuid, expiration_timestamp, reason
+        ),
+        user_model::UserStatus::Suspended { reason, .. } => {
+            format!("[{}]: Account suspended: {}", user.uuid, reason)
+        }
+        _ => format!("[{}]: Account deleted.", user.uuid),
+    };
+    api_errors::Error::forbidden(message)
+}
+
+pub async fn check_lock(db: &Database, keys: &[String]) -> Result<(), api_errors::Error> {
+    for key in keys {
+        match db.loginattemptmanager.get_lock(key).await {
+            Ok(Some(locked_until)) => {
+                let remaining = locked_until.saturating_sub(get_current_timestamp()) / 1000 + 1;
+                return Err(api_errors::Error::too_many_requests(format!(
+                    "Too many failed login attempts, try again in {} seconds.",
+                    remaining
+                )));
+            }
+            Ok(None) => {}
+            Err(error) => {
+                return Err(error.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn register_failure(db: &Database, keys: &[String]) -> Result<(), api_errors::Error> {
+    for key in keys {
+        if let Err(error) = db.loginattemptmanager.register_failure(key).await {
+            return Err(error.into());
+        }
+    }
+    Ok(())
+}
+
+async fn open_session(
+    db: &Database,
+    user: &mut user_model::User,
+    ip: Option<IpAddr>,
+) -> account_model::AccountTokenInfos {
+    // The address keeps its failures, logging into an own account between guesses must
+    // not reset the limit of a password spraying client.
+    let _ = db
+        .loginattemptmanager
+        .clear_failures(&LoginAttempt::account_key(&user.username))
+        .await;
+    if let Some(ip) = ip {
+        let log = user_model::UserLog {
+            ip: ip.to_string(),
+            timestamp: get_current_timestamp(),
+        };
+        let _ = db.usermanager.add_log(&user.uuid, &log).await;
+    }
+    let _ = db
+        .usermanager
+        .set_last_login(&user.uuid, get_current_timestamp())
+        .await;
+    let token = user.new_token(TOKEN_DURATION);
+    let _ = db.usermanager.save_token(&user.uuid, &token).await;
+    account_model::AccountTokenInfos {
+        token: token.token,
+        timestamp: token.timestamp,
+        expiration_timestamp: token.expiration_timestamp,
+        uuid: user.uuid.clone(),
+    }
+}
+
+/// Opens a session for an authenticated user, or asks for the second factor first.
+pub async fn start_session(
+    db: &Database,
+    mut user: user_model::User,
+    ip: Option<IpAddr>,
+) -> Result<totp_model::LoginResult, api_errors::Error> {
+    if !user.is_active(get_current_timestamp()) {
+        return Err(account_inactive(&user));
+    }
+    if !user.has_totp() {
+        return Ok(totp_model::LoginResult::Session(
+            open_session(db, &mut user, ip).await,
+        ));
+    }
+    let challenge = user.new_challenge(CHALLENGE_DURATION);
+    match db.usermanager.save_challenge(&user.uuid, &challenge).await {
+        Ok(_) => Ok(totp_model::LoginResult::Challenge(
+            totp_model::TotpChallenge {
+                challenge: challenge.token,
+                expiration_timestamp: challenge.expiration_timestamp,
+            },
+        )),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn login_keys(username: &str, ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![LoginAttempt::account_key(username)];
+    if let Some(ip) = ip {
+        keys.push(LoginAttempt::ip_key(&ip.to_string()));
+    }
+    keys
+}
 
 #[post("/login", data = "<input>")]
 pub async fn login(
     db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    params: &State<PasswordParams>,
+    client: ClientAddr,
     input: Json<account_model::AccountCredentials>,
-) -> Result<Json<account_model::AccountTokenInfos>, account_errors::Error> {
-    match db.usermanager.get_user(Some(&input.username), None).await {
-        Ok(mut user) => match &mut user {
-            Some(user) => {
-                let password = user.password.as_ref();
-                if password.is_some()
-                    && password
-                        .unwrap()
-                        .is_correct_password(input.password.as_bytes())
-                {
-                    let token = user.new_token(TOKEN_DURATION);
-                    let _ = db.usermanager.save_token(&user.uuid, &token).await;
-                    return Ok(Json(account_model::AccountTokenInfos {
-                        token: token.token,
-                        timestamp: token.timestamp,
-                        expiration_timestamp: token.expiration_timestamp,
-                        uuid: user.uuid.clone(),
-                    }));
-                } else {
-                    return Err(account_errors::Error {
-                        content: account_model::AccountError::build(
-                            400,
-                            Some(format!("[{}]: Account has disabled login.", input.username)),
-                        ),
-                    });
-                }
-            }
-            _ => {
-                return Err(account_errors::Error {
-                    content: account_model::AccountError::build(
-                        400,
-                        Some(format!(
-                            "[{}]: Username not related to any account.",
-                            input.username
-                        )),
-                    ),
-                })
+) -> Result<Json<totp_model::LoginResult>, api_errors::Error> {
+    let keys = login_keys(&input.username, client.0);
+    check_lock(db, &keys).await?;
+    // Refused before any hashing, argon2 would work on the whole input.
+    if policy.is_too_long(&input.password) {
+        register_failure(db, &keys).await?;
+        return Err(invalid_credentials());
+    }
+
+    let user = match db.usermanager.get_user(Some(&input.username), None).await {
+        Ok(user) => user,
+        Err(error) => {
+            return Err(error.into());
+        }
+    };
+
+    // Unknown users and accounts without password still pay for a hash verification.
+    let valid = match user.as_ref().and_then(|user| user.password.as_ref()) {
+        Some(password) => password.is_correct_password(input.password.as_bytes()),
+        None => verify_dummy_password(input.password.as_bytes(), params),
+    };
+
+    // The plain password is only known here, upgrade outdated hashes while we have it.
+    if let Some(user) = user.as_ref().filter(|_| valid) {
+        if matches!(&user.password, Some(password) if password.needs_rehash(params)) {
+            let password = Password::hash_password_with(input.password.as_bytes(), params);
+            if let Err(error) = db.usermanager.rehash_password(&user.uuid, &password).await {
+                tracing::error!(%error, "Cannot upgrade the password hash");
             }
-        },
+        }
+    }
+
+    match user {
+        Some(user) if valid => start_session(db, user, client.0).await.map(Json),
+        _ => {
+            register_failure(db, &keys).await?;
+            Err(invalid_credentials())
+        }
+    }
+}
+
+#[post("/login/totp", data = "<input>")]
+pub async fn login_totp(
+    db: &State<Database>,
+    client: ClientAddr,
+    input: Json<totp_model::TotpChallengeResponse>,
+) -> Result<Json<account_model::AccountTokenInfos>, api_errors::Error> {
+    let mut user = match db
+        .usermanager
+        .get_user_from_challenge(&input.challenge)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(api_errors::Error::unauthorized(
+                "Invalid or expired challenge.",
+            ))
+        }
         Err(error) => {
-            println!("{:?}", error);
-            return Err(account_errors::Error {
-                content: account_model::AccountError::build(
-                    500,
-                    Some("Database error.".to_string()),
-                ),
-            });
+            return Err(error.into());
+        }
+    };
+    if !user.is_active(get_current_timestamp()) {
+        return Err(account_inactive(&user));
+    }
+    let keys = login_keys(&user.username, client.0);
+    check_lock(db, &keys).await?;
+
+    if !check_second_factor(db, &user, &input.code).await? {
+        register_failure(db, &keys).await?;
+        return Err(api_errors::Error::unauthorized("Invalid two-factor code."));
+    }
+    // A challenge opens a single session.
+    match db
+        .usermanager
+        .remove_challenge(&user.uuid, &input.challenge)
+        .await
+    {
+        Ok(result) if result.modified_count == 1 => {
+            Ok(Json(open_session(db, &mut user, client.0).await))
         }
+        Ok(_) => Err(api_errors::Error::unauthorized(
+            "Invalid or expired challenge.",
+        )),
+        Err(error) => Err(error.into()),
     }
 }
@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use rocket::http::ContentType;
+use rocket::response::stream::ByteStream;
+use rocket::tokio::{fs, io::AsyncReadExt};
+use rocket::*;
+
+use misato_database::database::*;
+use misato_security::verify_signature;
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::errors::api_errors;
+use crate::routes::user::export::link_message;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Signed link handed out by `/user/export`, works once.
+///
+/// The export is only used up once the archive is sent in full, an interrupted download
+/// can be started again with the same link.
+#[get("/export/<id>?<expires>&<signature>")]
+pub async fn download(
+    db: &State<Database>,
+    settings: &State<Settings>,
+    id: &str,
+    expires: u64,
+    signature: &str,
+) -> Result<(ContentType, ByteStream![Vec<u8>]), api_errors::Error> {
+    let invalid_link = || api_errors::Error::forbidden("Invalid or expired link.");
+    if expires < get_current_timestamp()
+        || !verify_signature(
+            &settings.export_secret,
+            &link_message(id, expires),
+            signature,
+        )
+    {
+        return Err(invalid_link());
+    }
+    // The archive is removed once downloaded or expired.
+    let path = Path::new(&settings.export_directory).join(format!("{}.zip", id));
+    let mut file = match fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Err(invalid_link()),
+        Err(error) => {
+            tracing::error!(%error, "Cannot read the export");
+            return Err(api_errors::Error::internal("Could not read the export."));
+        }
+    };
+    let (exports, id) = (db.exportmanager.clone(), id.to_string());
+    let stream = ByteStream! {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => yield buffer[..read].to_vec(),
+                Err(error) => {
+                    tracing::error!(%error, "Cannot read the export");
+                    return;
+                }
+            }
+        }
+        // Reached once the last chunk is handed to the connection, a client leaving earlier
+        // drops the stream.
+        match exports.consume_export(&id).await {
+            Ok(Some(_)) => {
+                let _ = fs::remove_file(&path).await;
+            }
+            Ok(None) => {}
+            Err(error) => tracing::error!(%error, "Cannot mark the export as downloaded"),
+        }
+    };
+    Ok((ContentType::ZIP, stream))
+}
@@ -0,0 +1,68 @@
+//! Probes for orchestrators, open to anyone since they tell nothing about the accounts.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::tokio::time::timeout;
+use rocket::*;
+
+use misato_database::database::*;
+use misato_utils::settings::Settings;
+
+use crate::fairings::connection::ConnectionStatus;
+use crate::models::health_model;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The process answers, whatever the state of the database.
+#[get("/health/live")]
+pub async fn live() -> Json<health_model::Liveness> {
+    Json(health_model::Liveness {
+        status: "alive",
+        version: VERSION,
+    })
+}
+
+/// Ready to serve once the database answers a ping within `MISATO_HEALTH_TIMEOUT`.
+#[get("/health/ready")]
+pub async fn ready(
+    db: &State<Database>,
+    connection: &State<Arc<ConnectionStatus>>,
+    settings: &State<Settings>,
+) -> (Status, Json<health_model::Readiness>) {
+    let limit = Duration::from_millis(settings.health_timeout);
+    let started = Instant::now();
+    let ping = match timeout(limit, db.ping()).await {
+        Ok(result) => result.map_err(|error| error.to_string()),
+        Err(_) => Err(format!("no answer within {} ms", limit.as_millis())),
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let pending_migrations = match ping {
+        Ok(_) => timeout(limit, db.pending_migrations())
+            .await
+            .ok()
+            .and_then(Result::ok),
+        Err(_) => None,
+    };
+
+    let status = match ping {
+        Ok(_) => Status::Ok,
+        Err(_) => Status::ServiceUnavailable,
+    };
+    (
+        status,
+        Json(health_model::Readiness {
+            status: if ping.is_ok() { "ready" } else { "unavailable" },
+            version: VERSION,
+            database: health_model::DatabaseHealth {
+                connected: db.is_connected(),
+                latency_ms: ping.is_ok().then_some(latency_ms),
+                error: ping.err().or_else(|| connection.last_error()),
+                attempts: connection.attempts(),
+            },
+            pending_migrations,
+        }),
+    )
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use rocket::http::ContentType;
+use rocket::*;
+
+use misato_database::database::*;
+
+use crate::fairings::metrics_authentication::MetricsAccess;
+use crate::metrics::Metrics;
+
+#[get("/metrics")]
+pub async fn metrics(
+    _access: MetricsAccess,
+    metrics: &State<Arc<Metrics>>,
+    db: &State<Database>,
+) -> (ContentType, String) {
+    // A failing database still leaves the other metrics worth scraping.
+    let sessions = db.usermanager.count_sessions().await.ok();
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        metrics.render(sessions),
+    )
+}
@@ -1 +1,9 @@
 pub mod account;
+pub mod export;
+pub mod health;
+pub mod metrics;
+pub mod oauth;
+pub mod oidc;
+pub mod profile;
+pub mod recovery;
+pub mod signup;
@@ -0,0 +1,196 @@
+use rocket::form::Form;
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{
+    database::*,
+    models::{apiuser_model::*, oauth_model::*, user_model},
+};
+use misato_security::{hash_token, pkce::code_challenge};
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::errors::oauth_errors;
+use crate::fairings::oauth_authentication::OAuthAccess;
+use crate::models::{oauth_model, profile_model};
+use crate::routes::root::profile::avatar_url;
+use crate::routes::user::{identities::identities, profile::check_profile};
+
+const TOKEN_DURATION: u64 = 60 * 60;
+
+async fn authenticate_client(
+    db: &Database,
+    client_id: &str,
+    secret: Option<&str>,
+) -> Result<OAuthClient, oauth_errors::Error> {
+    match db.oauthmanager.get_client(client_id).await {
+        Ok(Some(client)) if client.is_correct_secret(secret) => Ok(client),
+        Ok(_) => Err(oauth_errors::Error::invalid_client()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Tokens delegate the API access of the user, never the one of the main website.
+async fn delegated_access(db: &Database, uuid: &str) -> Result<ApiUserAccess, oauth_errors::Error> {
+    let apiuser = db.apiusermanager.get_apiuser(None, Some(uuid)).await?;
+    Ok(match apiuser {
+        Some(apiuser) if apiuser.access.role != ApiUserRoleType::Admin => apiuser.access,
+        _ => ApiUserAccess::default(),
+    })
+}
+
+fn role_name(role: &ApiUserRoleType) -> String {
+    match role {
+        ApiUserRoleType::Admin => "admin",
+        ApiUserRoleType::Dev => "dev",
+        ApiUserRoleType::User => "user",
+    }
+    .to_string()
+}
+
+fn user_info(settings: &Settings, access: &OAuthAccess) -> oauth_model::UserInfo {
+    let (token, user) = (&access.token, &access.user);
+    let mut info = oauth_model::UserInfo {
+        sub: user.uuid.clone(),
+        ..Default::default()
+    };
+    if token.has_scope("profile") {
+        info.username = Some(user.username.clone());
+        info.display_name = user.profile.display_name.clone();
+        info.bio = user.profile.bio.clone();
+        info.avatar = avatar_url(settings, &user.profile.avatar);
+    }
+    if token.has_scope("email") {
+        info.email = user.email.clone();
+        info.email_verified = user.email.as_ref().map(|_| user.email_verified);
+    }
+    if token.has_scope("identities") {
+        info.identities = Some(identities(user));
+    }
+    info
+}
+
+/// Authorization code grant, the code verifier is mandatory.
+#[post("/oauth/token", data = "<input>")]
+pub async fn token(
+    db: &State<Database>,
+    input: Form<oauth_model::TokenRequest>,
+) -> Result<Json<oauth_model::TokenResponse>, oauth_errors::Error> {
+    if input.grant_type != "authorization_code" {
+        return Err(oauth_errors::Error::unsupported_grant_type());
+    }
+    let client = authenticate_client(db, &input.client_id, input.client_secret.as_deref()).await?;
+    let code = match db.oauthmanager.consume_code(&hash_token(&input.code)).await {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            return Err(oauth_errors::Error::invalid_grant(
+                "Invalid or expired code.",
+            ))
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if code.client_id != client.client_id || code.redirect_uri != input.redirect_uri {
+        return Err(oauth_errors::Error::invalid_grant(
+            "Code issued to another client or redirect_uri.",
+        ));
+    }
+    if code_challenge(&input.code_verifier) != code.code_challenge {
+        return Err(oauth_errors::Error::invalid_grant("Invalid code_verifier."));
+    }
+    match db.usermanager.get_user(None, Some(&code.uuid)).await {
+        Ok(Some(user)) if user.is_active(get_current_timestamp()) => {}
+        Ok(_) => return Err(oauth_errors::Error::invalid_grant("Inactive account.")),
+        Err(error) => return Err(error.into()),
+    }
+
+    let access = delegated_access(db, &code.uuid).await?;
+    let scope = code.scopes.join(" ");
+    let (token, record) = OAuthToken::create(
+        &client.client_id,
+        &code.uuid,
+        code.scopes,
+        access,
+        TOKEN_DURATION,
+    );
+    db.oauthmanager.save_token(&record).await?;
+    Ok(Json(oauth_model::TokenResponse {
+        access_token: token,
+        token_type: "Bearer".to_string(),
+        expires_in: TOKEN_DURATION,
+        scope,
+    }))
+}
+
+/// Answers the same for unknown tokens, as required by RFC 7009.
+#[post("/oauth/revoke", data = "<input>")]
+pub async fn revoke(
+    db: &State<Database>,
+    input: Form<oauth_model::TokenQuery>,
+) -> Result<(), oauth_errors::Error> {
+    let client = authenticate_client(db, &input.client_id, input.client_secret.as_deref()).await?;
+    db.oauthmanager
+        .revoke_token(&hash_token(&input.token), &client.client_id)
+        .await?;
+    Ok(())
+}
+
+/// A client only sees its own tokens as active.
+#[post("/oauth/introspect", data = "<input>")]
+pub async fn introspect(
+    db: &State<Database>,
+    input: Form<oauth_model::TokenQuery>,
+) -> Result<Json<oauth_model::Introspection>, oauth_errors::Error> {
+    let client = authenticate_client(db, &input.client_id, input.client_secret.as_deref()).await?;
+    let token = match db.oauthmanager.get_token(&hash_token(&input.token)).await {
+        Ok(Some(token)) if token.client_id == client.client_id => token,
+        Ok(_) => return Ok(Json(oauth_model::Introspection::default())),
+        Err(error) => return Err(error.into()),
+    };
+    let user = match db.usermanager.get_user(None, Some(&token.uuid)).await {
+        Ok(Some(user)) if user.is_active(get_current_timestamp()) => user,
+        Ok(_) => return Ok(Json(oauth_model::Introspection::default())),
+        Err(error) => return Err(error.into()),
+    };
+    Ok(Json(oauth_model::Introspection {
+        active: true,
+        scope: Some(token.scopes.join(" ")),
+        client_id: Some(token.client_id),
+        sub: Some(token.uuid),
+        username: Some(user.username),
+        token_type: Some("Bearer".to_string()),
+        exp: Some(token.expiration_timestamp / 1000),
+        iat: Some(token.timestamp / 1000),
+        role: Some(role_name(&token.access.role)),
+    }))
+}
+
+#[get("/oauth/userinfo")]
+pub async fn userinfo(
+    access: OAuthAccess,
+    settings: &State<Settings>,
+) -> Json<oauth_model::UserInfo> {
+    Json(user_info(settings, &access))
+}
+
+#[post("/oauth/profile", data = "<input>")]
+pub async fn update_profile(
+    mut access: OAuthAccess,
+    db: &State<Database>,
+    settings: &State<Settings>,
+    input: Json<profile_model::UpdateProfile>,
+) -> Result<Json<oauth_model::UserInfo>, oauth_errors::Error> {
+    if !access.token.has_scope("profile:write") {
+        return Err(oauth_errors::Error::insufficient_scope("profile:write"));
+    }
+    let updated = match check_profile(&input) {
+        Ok(profile) => user_model::UserProfile {
+            avatar: access.user.profile.avatar.clone(),
+            ..profile
+        },
+        Err(error) => return Err(oauth_errors::Error::invalid_request(&error.message)),
+    };
+    db.usermanager
+        .set_profile(&access.user.uuid, &updated)
+        .await?;
+    access.user.profile = updated;
+    Ok(Json(user_info(settings, &access)))
+}
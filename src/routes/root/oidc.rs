@@ -0,0 +1,226 @@
+use rocket::http::CookieJar;
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::{generate_token, password::Password};
+use misato_utils::{get_current_timestamp, settings::Settings, username::UsernamePolicy};
+
+use crate::errors::api_errors;
+use crate::fairings::client_addr::ClientAddr;
+use crate::models::oidc_model;
+use crate::oidc::{CallbackOutcome, IdTokenClaims, OidcIntent, OidcService, BINDING_COOKIE};
+use crate::routes::root::account::start_session;
+use crate::routes::user::identities::identities;
+
+fn oidc_error(error: impl std::fmt::Display) -> api_errors::Error {
+    api_errors::Error::bad_request(error.to_string())
+}
+
+/// Username derived from the claims, made unique with a random suffix when taken.
+async fn provision_username(
+    db: &Database,
+    usernames: &UsernamePolicy,
+    claims: &IdTokenClaims,
+) -> Result<String, api_errors::Error> {
+    let wanted = claims
+        .preferred_username
+        .clone()
+        .or_else(|| {
+            claims
+                .email
+                .as_ref()
+                .and_then(|email| email.split('@').next().map(str::to_string))
+        })
+        .unwrap_or_default();
+    let mut base: String = wanted
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-' || *c == '.')
+        .take(usernames.max_length.saturating_sub(7))
+        .collect();
+    if !matches!(base.chars().next(), Some(c) if c.is_ascii_alphanumeric()) {
+        base = "user".to_string();
+    }
+    let candidates = std::iter::once(base.clone())
+        .chain((0..5).map(|_| format!("{}-{}", base, generate_token(6).to_lowercase())));
+    for candidate in candidates {
+        if usernames.check(&candidate).is_empty()
+            && !db.usermanager.username_exists(&candidate).await?
+        {
+            return Ok(candidate);
+        }
+    }
+    Err(api_errors::Error::conflict(
+        "Could not find a free username.",
+    ))
+}
+
+fn identity(provider: &str, claims: &IdTokenClaims) -> user_model::UserIdentity {
+    user_model::UserIdentity {
+        provider: provider.to_string(),
+        subject: claims.sub.clone(),
+        email: claims.email.clone(),
+        timestamp: get_current_timestamp(),
+    }
+}
+
+#[get("/oidc/providers")]
+pub async fn providers(oidc: &State<OidcService>) -> Json<Vec<String>> {
+    Json(oidc.provider_names())
+}
+
+/// Also sets the cookie binding the authorization to this browser, see `callback`.
+#[get("/oidc/<provider>/login")]
+pub async fn login(
+    oidc: &State<OidcService>,
+    cookies: &CookieJar<'_>,
+    provider: &str,
+) -> Result<Json<oidc_model::OidcAuthorization>, api_errors::Error> {
+    let binding = oidc.client_binding(cookies);
+    match oidc
+        .authorization_url(provider, OidcIntent::Login, &binding)
+        .await
+    {
+        Ok(url) => Ok(Json(oidc_model::OidcAuthorization { url })),
+        Err(error) => Err(oidc_error(error)),
+    }
+}
+
+/// The store refused the new account: a verified address is unique, and so is the username
+/// unless another signup took it since `provision_username`.
+async fn creation_conflict(db: &Database, user: &user_model::User) -> api_errors::Error {
+    let email = match (&user.email, user.email_verified) {
+        (Some(email), true) => email,
+        _ => return api_errors::Error::username_taken(&user.username),
+    };
+    match db.usermanager.get_user_from_email(email).await {
+        Ok(Some(_)) => api_errors::Error::email_taken(),
+        Ok(None) => api_errors::Error::username_taken(&user.username),
+        Err(error) => error.into(),
+    }
+}
+
+/// Logs in with the linked account, creating one when registration is open,
+/// or links the identity when the authorization was started by `/user/identities`.
+/// Only the browser holding the binding cookie of the authorization can complete it.
+///
+/// The browser comes here from the provider, it is sent on to the frontend with a
+/// one-time code. The frontend exchanges it with `/oidc/exchange` for the session, or the
+/// error, so that no token ends up in the address bar or the history.
+#[get("/oidc/<provider>/callback?<code>&<state>&<error>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    db: &State<Database>,
+    oidc: &State<OidcService>,
+    settings: &State<Settings>,
+    usernames: &State<UsernamePolicy>,
+    client: ClientAddr,
+    cookies: &CookieJar<'_>,
+    provider: &str,
+    code: Option<&str>,
+    state: Option<&str>,
+    error: Option<&str>,
+) -> Redirect {
+    let binding = cookies.get(BINDING_COOKIE).map(|cookie| cookie.value());
+    let outcome = complete(
+        db, oidc, settings, usernames, client, binding, provider, code, state, error,
+    )
+    .await;
+    Redirect::to(format!(
+        "{}/oidc/callback?code={}",
+        settings.frontend_url.trim_end_matches('/'),
+        oidc.hand_over(outcome)
+    ))
+}
+
+#[post("/oidc/exchange", data = "<input>")]
+pub async fn exchange(
+    oidc: &State<OidcService>,
+    input: Json<oidc_model::OidcExchange>,
+) -> Result<Json<oidc_model::OidcCallbackResult>, api_errors::Error> {
+    match oidc.take_outcome(&input.code) {
+        Some(outcome) => outcome.map(Json),
+        None => Err(api_errors::Error::bad_request("Invalid or expired code.")),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn complete(
+    db: &Database,
+    oidc: &OidcService,
+    settings: &Settings,
+    usernames: &UsernamePolicy,
+    client: ClientAddr,
+    binding: Option<&str>,
+    provider: &str,
+    code: Option<&str>,
+    state: Option<&str>,
+    error: Option<&str>,
+) -> CallbackOutcome {
+    let (code, state) = match (code, state, error) {
+        (Some(code), Some(state), None) => (code, state),
+        (_, _, Some(error)) => {
+            return Err(oidc_error(format!("[{}]: Refused by the provider.", error)))
+        }
+        _ => return Err(oidc_error("Missing code or state.")),
+    };
+    let (intent, claims) = oidc
+        .callback(provider, code, state, binding)
+        .await
+        .map_err(oidc_error)?;
+
+    let linked = db
+        .usermanager
+        .get_user_from_identity(provider, &claims.sub)
+        .await?;
+    match (intent, linked) {
+        (OidcIntent::Login, Some(user)) => Ok(oidc_model::OidcCallbackResult::Login(
+            start_session(db, user, client.0).await?,
+        )),
+        (OidcIntent::Login, None) if settings.registration == "open" => {
+            let mut user = user_model::User::create(
+                provision_username(db, usernames, &claims).await?,
+                Password::default(),
+                None,
+            );
+            user.password = None;
+            user.email = claims.email.as_ref().map(|email| email.to_lowercase());
+            user.email_verified = user.email.is_some() && claims.email_verified;
+            user.profile.display_name = claims.name.clone();
+            user.identities = vec![identity(provider, &claims)];
+            match db.usermanager.create_user(&user).await {
+                Ok(()) => {}
+                Err(misato_database::Error::DuplicateKey(_)) => {
+                    return Err(creation_conflict(db, &user).await)
+                }
+                Err(error) => return Err(error.into()),
+            }
+            Ok(oidc_model::OidcCallbackResult::Login(
+                start_session(db, user, client.0).await?,
+            ))
+        }
+        (OidcIntent::Login, None) => Err(api_errors::Error::not_found(
+            "No account is linked to this identity.",
+        )),
+        (OidcIntent::Link(uuid), Some(user)) if user.uuid == uuid => {
+            Ok(oidc_model::OidcCallbackResult::Linked(identities(&user)))
+        }
+        (OidcIntent::Link(_), Some(_)) => Err(api_errors::Error::conflict(
+            "Identity already linked to another account.",
+        )),
+        (OidcIntent::Link(uuid), None) => {
+            db.usermanager
+                .add_identity(&uuid, &identity(provider, &claims))
+                .await?;
+            match db.usermanager.get_user(None, Some(&uuid)).await {
+                Ok(Some(user)) => Ok(oidc_model::OidcCallbackResult::Linked(identities(&user))),
+                Ok(None) => Err(api_errors::Error::not_found(format!(
+                    "[{}]: Account doesn't exist.",
+                    uuid
+                ))),
+                Err(error) => Err(error.into()),
+            }
+        }
+    }
+}
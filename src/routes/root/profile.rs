@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use rocket::fs::NamedFile;
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::database::*;
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::errors::api_errors;
+use crate::models::profile_model;
+
+/// Avatars are named by the upload, anything else is not one of them.
+pub fn is_avatar_name(name: &str) -> bool {
+    !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.')
+}
+
+pub fn avatar_url(settings: &Settings, avatar: &Option<String>) -> Option<String> {
+    avatar
+        .as_ref()
+        .map(|avatar| format!("{}/avatar/{}", settings.public_url, avatar))
+}
+
+#[get("/profile/<username>")]
+pub async fn public_profile(
+    db: &State<Database>,
+    settings: &State<Settings>,
+    username: &str,
+) -> Result<Json<profile_model::PublicProfile>, api_errors::Error> {
+    // Suspended and deleted accounts, even within their grace period, are not shown.
+    match db.usermanager.get_user(Some(username), None).await {
+        Ok(Some(user)) if user.is_active(get_current_timestamp()) => {
+            Ok(Json(profile_model::PublicProfile {
+                avatar: avatar_url(settings, &user.profile.avatar),
+                uuid: user.uuid,
+                username: user.username,
+                display_name: user.profile.display_name,
+                bio: user.profile.bio,
+                timestamp: user.timestamp,
+            }))
+        }
+        Ok(_) => Err(api_errors::Error::not_found(format!(
+            "[{}]: Account doesn't exist.",
+            username
+        ))),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[get("/avatar/<name>")]
+pub async fn avatar(settings: &State<Settings>, name: &str) -> Option<NamedFile> {
+    if !is_avatar_name(name) {
+        return None;
+    }
+    NamedFile::open(Path::new(&settings.avatar_directory).join(name))
+        .await
+        .ok()
+}
@@ -0,0 +1,108 @@
+use rocket::serde::json::Json;
+use rocket::*;
+use rocket_dyn_templates::tera::Context;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::{hash_token, password::*};
+
+use crate::errors::api_errors;
+use crate::mailer::MailService;
+use crate::models::recovery_model;
+
+const RESET_TOKEN_DURATION: u64 = 30 * 60;
+
+#[post("/password/forgot", data = "<input>")]
+pub async fn forgot_password(
+    db: &State<Database>,
+    mailer: &State<MailService>,
+    input: Json<recovery_model::ForgotPassword>,
+) -> Result<Json<String>, api_errors::Error> {
+    let user = if input.login.contains('@') {
+        db.usermanager
+            .get_user_from_email(&input.login.trim().to_lowercase())
+            .await
+    } else {
+        db.usermanager.get_user(Some(&input.login), None).await
+    };
+    let user = user?;
+
+    // Same answer whether or not the account exists, and whatever happens to the mail.
+    let answer = Json(
+        "If the account has a verified email address, a reset link has been sent.".to_string(),
+    );
+    // An unverified address may be mistyped, or someone else's.
+    let (user, email) = match user {
+        Some(user) if user.email_verified => match user.email.clone() {
+            Some(email) => (user, email),
+            None => return Ok(answer),
+        },
+        _ => return Ok(answer),
+    };
+
+    let (token, secret) = user_model::UserSecretToken::create(RESET_TOKEN_DURATION, None);
+    db.usermanager.set_reset_token(&user.uuid, &secret).await?;
+
+    let mut context = Context::new();
+    context.insert("username", &user.username);
+    context.insert("token", &token);
+    context.insert("minutes", &(RESET_TOKEN_DURATION / 60));
+    // Not awaited, waiting for the mail server would tell which accounts have an address.
+    if let Err(error) = mailer.spawn_template(
+        &email,
+        "Reset your Misato password",
+        "password_reset.txt.tera",
+        &context,
+    ) {
+        tracing::error!(%error, "Cannot send the password reset email");
+    }
+    Ok(answer)
+}
+
+#[post("/password/reset", data = "<input>")]
+pub async fn reset_password(
+    db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    params: &State<PasswordParams>,
+    input: Json<recovery_model::ResetPassword>,
+) -> Result<Json<String>, api_errors::Error> {
+    // Checked before consuming the token so a refused password can be retried.
+    let user = db
+        .usermanager
+        .get_user_from_reset_token(&hash_token(&input.token))
+        .await?;
+    if let Some(user) = &user {
+        let rules = policy.check(&user.username, &input.password);
+        if !rules.is_empty() {
+            return Err(api_errors::Error::from_password_rules(&rules));
+        }
+    }
+    let user = db
+        .usermanager
+        .consume_reset_token(&hash_token(&input.token))
+        .await?;
+    match user {
+        Some(user) => {
+            let password = Password::hash_password_with(input.password.as_bytes(), params);
+            db.usermanager.set_password(&user.uuid, &password).await?;
+            Ok(Json(format!("[{}]: Password changed.", user.uuid)))
+        }
+        None => Err(api_errors::Error::bad_request("Invalid or expired token.")),
+    }
+}
+
+#[post("/email/verify", data = "<input>")]
+pub async fn verify_email(
+    db: &State<Database>,
+    input: Json<recovery_model::EmailToken>,
+) -> Result<Json<String>, api_errors::Error> {
+    match db
+        .usermanager
+        .consume_verification_token(&hash_token(&input.token))
+        .await
+    {
+        Ok(Some(user)) => Ok(Json(format!("[{}]: Email address verified.", user.uuid))),
+        Ok(None) => Err(api_errors::Error::bad_request("Invalid or expired token.")),
+        Err(misato_database::Error::DuplicateKey(_)) => Err(api_errors::Error::email_taken()),
+        Err(error) => Err(error.into()),
+    }
+}
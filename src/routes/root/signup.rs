@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use rocket::serde::json::Json;
+use rocket::tokio;
+use rocket::*;
+
+use misato::models::account_model;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::{hash_token, password::*, pow::ProofOfWork};
+use misato_utils::{get_current_timestamp, settings::Settings, username::UsernamePolicy};
+
+use crate::errors::api_errors;
+use crate::models::signup_model;
+
+const TOKEN_DURATION: u64 = 24 * 60 * 60;
+/// Runs of the unit of work creating the account, when the database asks to retry it.
+const SIGNUP_ATTEMPTS: u32 = 3;
+/// Wait before the second run, doubled before each following one.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Codes from the settings never run out and give the default access.
+fn is_static_invite_code(settings: &Settings, code: &str) -> bool {
+    settings
+        .invite_codes
+        .split(',')
+        .any(|invite_code| invite_code.trim() == code)
+}
+
+fn invalid_invite_code() -> api_errors::Error {
+    api_errors::Error::forbidden("A valid invite code is required.")
+}
+
+#[get("/signup/challenge")]
+pub async fn challenge(
+    pow: &State<ProofOfWork>,
+) -> Result<Json<signup_model::SignupChallenge>, api_errors::Error> {
+    if !pow.is_enabled() {
+        return Err(api_errors::Error::not_found("No challenge required."));
+    }
+    let challenge = pow.issue(get_current_timestamp());
+    Ok(Json(signup_model::SignupChallenge {
+        challenge: challenge.challenge,
+        difficulty: challenge.difficulty,
+        expiration_timestamp: challenge.expiration_timestamp,
+    }))
+}
+
+#[post("/signup", data = "<input>")]
+pub async fn signup(
+    db: &State<Database>,
+    settings: &State<Settings>,
+    pow: &State<ProofOfWork>,
+    usernames: &State<UsernamePolicy>,
+    policy: &State<PasswordPolicy>,
+    params: &State<PasswordParams>,
+    input: Json<signup_model::Signup>,
+) -> Result<Json<account_model::AccountTokenInfos>, api_errors::Error> {
+    let invite_code = input
+        .invite_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty());
+    match settings.registration.as_str() {
+        "open" => {}
+        "invite" if invite_code.is_some() => {}
+        "invite" => return Err(invalid_invite_code()),
+        _ => return Err(api_errors::Error::forbidden("Registration is closed.")),
+    }
+
+    if pow.is_enabled() {
+        let solved = match (&input.challenge, &input.nonce) {
+            (Some(challenge), Some(nonce)) => pow.verify(challenge, nonce, get_current_timestamp()),
+            _ => false,
+        };
+        if !solved {
+            return Err(api_errors::Error::bad_request(
+                "Invalid or expired challenge.",
+            ));
+        }
+    }
+
+    let username = input.username.trim();
+    let rules = usernames.check(username);
+    if !rules.is_empty() {
+        return Err(api_errors::Error::from_username_rules(&rules));
+    }
+    let rules = policy.check(username, &input.password);
+    if !rules.is_empty() {
+        return Err(api_errors::Error::from_password_rules(&rules));
+    }
+    if db.usermanager.username_exists(username).await? {
+        return Err(api_errors::Error::username_taken(username));
+    }
+
+    let created = user_model::User::create(
+        username.to_string(),
+        Password::hash_password_with(input.password.as_bytes(), params),
+        None,
+    );
+    // The invite is redeemed in the unit of work creating the account, a refused signup
+    // gives the use back. Concurrent redemptions of one invite conflict in a MongoDB
+    // transaction, the signup that lost is run again.
+    let mut attempts = 0;
+    let (user, token) = loop {
+        attempts += 1;
+        let mut user = created.clone();
+        let mut work = db.begin().await?;
+        let result = async {
+            if let Some(code) = invite_code.filter(|code| !is_static_invite_code(settings, code)) {
+                match work.redeem_invite(&hash_token(code), &user.uuid).await? {
+                    Some(invite) => user.access = invite.access,
+                    None => return Ok(Err(invalid_invite_code())),
+                }
+            }
+            match work.create_user(&user).await {
+                Ok(()) => {}
+                Err(misato_database::Error::DuplicateKey(_)) => {
+                    return Ok(Err(api_errors::Error::username_taken(username)))
+                }
+                Err(error) => return Err(error),
+            }
+            let token = user.new_token(TOKEN_DURATION);
+            work.save_token(&user.uuid, &token).await?;
+            Ok(Ok(token))
+        }
+        .await;
+        let result = match result {
+            Ok(Ok(token)) => work.commit().await.map(|_| Ok(token)),
+            refused_or_failed => {
+                work.abort().await;
+                refused_or_failed
+            }
+        };
+        match result {
+            Ok(Ok(token)) => break (user, token),
+            Ok(Err(refused)) => {
+                // An earlier run may have committed without hearing back, the account it
+                // created is then the one in the way.
+                if attempts > 1 && db.usermanager.uuid_exists(&user.uuid).await? {
+                    let token = user.new_token(TOKEN_DURATION);
+                    db.usermanager.save_token(&user.uuid, &token).await?;
+                    break (user, token);
+                }
+                return Err(refused);
+            }
+            Err(misato_database::Error::Unavailable(_)) if attempts < SIGNUP_ATTEMPTS => {
+                tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempts - 1)).await;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    };
+    Ok(Json(account_model::AccountTokenInfos {
+        token: token.token.clone(),
+        timestamp: token.timestamp,
+        expiration_timestamp: token.expiration_timestamp,
+        uuid: user.uuid,
+    }))
+}
@@ -0,0 +1,121 @@
+use std::net::IpAddr;
+
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::password::*;
+use misato_utils::username::UsernamePolicy;
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::fairings::client_addr::ClientAddr;
+use crate::models::credentials_model;
+use crate::routes::root::account::{check_lock, login_keys, register_failure};
+
+/// Same lockout as the login, a stolen session must not help guessing the password.
+pub async fn check_password(
+    db: &Database,
+    policy: &PasswordPolicy,
+    user: &user_model::User,
+    ip: Option<IpAddr>,
+    password: &str,
+) -> Result<(), api_errors::Error> {
+    let keys = login_keys(&user.username, ip);
+    check_lock(db, &keys).await?;
+    let valid = user
+        .password
+        .as_ref()
+        .map(|hash| !policy.is_too_long(password) && hash.is_correct_password(password.as_bytes()));
+    match valid {
+        Some(true) => Ok(()),
+        Some(false) => {
+            register_failure(db, &keys).await?;
+            Err(api_errors::Error::unauthorized("Invalid password."))
+        }
+        None => Err(api_errors::Error::bad_request(format!(
+            "[{}]: Account has disabled login.",
+            user.uuid
+        ))),
+    }
+}
+
+#[post("/user/change-password", data = "<input>")]
+pub async fn change_password(
+    user: UserToken,
+    db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    params: &State<PasswordParams>,
+    client: ClientAddr,
+    input: Json<credentials_model::ChangePassword>,
+) -> Result<Json<String>, api_errors::Error> {
+    check_password(db, policy, &user.user, client.0, &input.current_password).await?;
+
+    let rules = policy.check(&user.user.username, &input.new_password);
+    if !rules.is_empty() {
+        return Err(api_errors::Error::from_password_rules(&rules));
+    }
+    let password = Password::hash_password_with(input.new_password.as_bytes(), params);
+    match db
+        .usermanager
+        .change_password(&user.user.uuid, &password, &user.token)
+        .await
+    {
+        Ok(_) => Ok(Json(format!(
+            "[{}]: Password changed, other sessions revoked.",
+            user.user.uuid
+        ))),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[post("/user/change-username", data = "<input>")]
+pub async fn change_username(
+    user: UserToken,
+    db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    usernames: &State<UsernamePolicy>,
+    client: ClientAddr,
+    input: Json<credentials_model::ChangeUsername>,
+) -> Result<Json<String>, api_errors::Error> {
+    let user = user.user;
+    check_password(db, policy, &user, client.0, &input.password).await?;
+
+    let username = input.username.trim();
+    if username.is_empty() || username == user.username {
+        return Err(api_errors::Error::invalid_field(
+            "username",
+            format!("[{}]: Invalid username.", input.username),
+        ));
+    }
+    let rules = usernames.check(username);
+    if !rules.is_empty() {
+        return Err(api_errors::Error::from_username_rules(&rules));
+    }
+    // Former usernames are reserved, except to the user who had them.
+    if !user.had_username(username) {
+        match db.usermanager.username_exists(username).await {
+            Ok(false) => {}
+            Ok(true) => return Err(api_errors::Error::username_taken(username)),
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    match db
+        .usermanager
+        .change_username(&user.uuid, &user.username, username)
+        .await
+    {
+        Ok(result) if result.modified_count == 1 => {
+            Ok(Json(format!("[{}]: Username changed.", username)))
+        }
+        Ok(_) => Err(api_errors::Error::conflict(format!(
+            "[{}]: Account changed meanwhile, retry.",
+            user.uuid
+        ))),
+        Err(misato_database::Error::DuplicateKey(_)) => {
+            Err(api_errors::Error::username_taken(username))
+        }
+        Err(error) => Err(error.into()),
+    }
+}
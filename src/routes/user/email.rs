@@ -0,0 +1,56 @@
+use rocket::serde::json::Json;
+use rocket::*;
+use rocket_dyn_templates::tera::Context;
+
+use misato_database::{database::*, models::user_model};
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::mailer::MailService;
+use crate::models::recovery_model;
+
+const VERIFICATION_TOKEN_DURATION: u64 = 24 * 60 * 60;
+
+#[post("/user/email", data = "<input>")]
+pub async fn set_email(
+    user: UserToken,
+    db: &State<Database>,
+    mailer: &State<MailService>,
+    input: Json<recovery_model::EmailAddress>,
+) -> Result<Json<String>, api_errors::Error> {
+    let user = user.user;
+    let email = input.email.trim().to_lowercase();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(api_errors::Error::invalid_field(
+            "email",
+            format!("[{}]: Invalid email address.", input.email),
+        ));
+    }
+
+    let (token, secret) =
+        user_model::UserSecretToken::create(VERIFICATION_TOKEN_DURATION, Some(email.clone()));
+    if let Err(error) = db.usermanager.set_email(&user.uuid, &email, &secret).await {
+        return Err(error.into());
+    }
+
+    let mut context = Context::new();
+    context.insert("username", &user.username);
+    context.insert("email", &email);
+    context.insert("token", &token);
+    context.insert("minutes", &(VERIFICATION_TOKEN_DURATION / 60));
+    match mailer
+        .send_template(
+            &email,
+            "Verify your Misato email address",
+            "email_verification.txt.tera",
+            &context,
+        )
+        .await
+    {
+        Ok(_) => Ok(Json(format!("[{}]: Verification email sent.", email))),
+        Err(error) => {
+            tracing::error!(%error, "Cannot send the verification email");
+            Err(api_errors::Error::internal("Could not send the email."))
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::{self, fs};
+use rocket::*;
+use tracing::Instrument;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use misato_database::{
+    database::*,
+    models::{export_model, user_model},
+    repository::{ApiUserRepository, ExportRepository},
+};
+use misato_security::sign;
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::models::export_model::ExportInfos;
+
+/// The archive is kept a day, the link to it only a few minutes.
+const EXPORT_DURATION: u64 = 24 * 60 * 60;
+const LINK_DURATION: u64 = 15 * 60;
+/// An export pending longer was interrupted, by a restart for instance, and is replaced.
+const PREPARATION_TIMEOUT: u64 = 15 * 60;
+
+/// Message signed in download links.
+pub fn link_message(id: &str, expires: u64) -> String {
+    format!("export:{}:{}", id, expires)
+}
+
+fn export_infos(settings: &Settings, export: export_model::Export) -> ExportInfos {
+    let (url, url_expiration_timestamp) = if export.status == export_model::ExportStatus::Ready {
+        let expires =
+            (get_current_timestamp() + LINK_DURATION * 1000).min(export.expiration_timestamp);
+        let signature = sign(&settings.export_secret, &link_message(&export.id, expires));
+        (
+            Some(format!(
+                "{}/export/{}?expires={}&signature={}",
+                settings.public_url, export.id, expires, signature
+            )),
+            Some(expires),
+        )
+    } else {
+        (None, None)
+    };
+    ExportInfos {
+        id: export.id,
+        status: export.status,
+        timestamp: export.timestamp,
+        expiration_timestamp: export.expiration_timestamp,
+        size: export.size,
+        url,
+        url_expiration_timestamp,
+    }
+}
+
+/// One JSON file per kind of data, secrets such as tokens and hashes are left out.
+/// Revisions and comments have no store yet, their files are empty so the layout stays stable.
+async fn collect_files(
+    apiusers: &dyn ApiUserRepository,
+    settings: &Settings,
+    user: &user_model::User,
+) -> Result<Vec<(&'static str, Value)>, misato_database::Error> {
+    let sessions: Vec<Value> = user
+        .tokens
+        .iter()
+        .flatten()
+        .map(|token| {
+            json!({
+                "timestamp": token.timestamp,
+                "expiration_timestamp": token.expiration_timestamp,
+            })
+        })
+        .collect();
+
+    let mut uploads = Vec::new();
+    if let Some(avatar) = &user.profile.avatar {
+        let size = fs::metadata(Path::new(&settings.avatar_directory).join(avatar))
+            .await
+            .map(|metadata| metadata.len())
+            .ok();
+        uploads.push(json!({"kind": "avatar", "name": avatar, "size": size}));
+    }
+
+    let api_account = apiusers
+        .get_apiuser(None, Some(&user.uuid))
+        .await?
+        .map(|apiuser| {
+            json!({
+                "uuid": apiuser.uuid,
+                "timestamp": apiuser.timestamp,
+                "access": apiuser.access,
+                "token": apiuser.token.map(|token| json!({
+                    "timestamp": token.timestamp,
+                    "expiration_timestamp": token.expiration_timestamp,
+                })),
+            })
+        });
+
+    Ok(vec![
+        (
+            "profile.json",
+            json!({
+                "uuid": user.uuid,
+                "username": user.username,
+                "previous_usernames": user.previous_usernames,
+                "email": user.email,
+                "email_verified": user.email_verified,
+                "profile": user.profile,
+                "access": user.access,
+                "status": user.status,
+                "two_factor_enabled": user.has_totp(),
+                "identities": user.identities,
+                "timestamp": user.timestamp,
+                "last_login": user.last_login,
+            }),
+        ),
+        ("sessions.json", Value::Array(sessions)),
+        (
+            "login_logs.json",
+            json!(user.logs.clone().unwrap_or_default()),
+        ),
+        ("uploads.json", Value::Array(uploads)),
+        ("api_account.json", json!(api_account)),
+        ("revisions.json", json!([])),
+        ("comments.json", json!([])),
+    ])
+}
+
+fn write_archive(files: &[(&'static str, Value)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, content) in files {
+        zip.start_file(*name, options)?;
+        zip.write_all(&serde_json::to_vec_pretty(content).unwrap())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Collects the data of the user and writes the archive, returns its size.
+async fn prepare_export(
+    exports: &dyn ExportRepository,
+    apiusers: &dyn ApiUserRepository,
+    settings: &Settings,
+    user: &user_model::User,
+    export: &export_model::Export,
+) -> Result<u64, String> {
+    let files = collect_files(apiusers, settings, user)
+        .await
+        .map_err(|error| error.to_string())?;
+    let directory = Path::new(&settings.export_directory);
+    let bytes = write_archive(&files).map_err(|error| format!("{:?}", error))?;
+    fs::create_dir_all(directory)
+        .await
+        .map_err(|error| format!("{:?}", error))?;
+    fs::write(directory.join(export.file_name()), &bytes)
+        .await
+        .map_err(|error| format!("{:?}", error))?;
+    let size = bytes.len() as u64;
+    exports
+        .set_status(&export.id, &export_model::ExportStatus::Ready, Some(size))
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok(size)
+}
+
+#[post("/user/export")]
+pub async fn request_export(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+) -> Result<Json<ExportInfos>, api_errors::Error> {
+    let user = user.user;
+    let stale = get_current_timestamp().saturating_sub(PREPARATION_TIMEOUT * 1000);
+    if let Err(error) = db.exportmanager.fail_stale_export(&user.uuid, stale).await {
+        return Err(error.into());
+    }
+    let export = export_model::Export::create(&user.uuid, EXPORT_DURATION);
+    // Refused by the store while another export of the user is pending.
+    match db.exportmanager.create_export(&export).await {
+        Ok(()) => {}
+        Err(misato_database::Error::DuplicateKey(_)) => {
+            return Err(api_errors::Error::conflict(format!(
+                "[{}]: An export is already being prepared.",
+                user.uuid
+            )))
+        }
+        Err(error) => return Err(error.into()),
+    }
+
+    let infos = export_infos(settings, export.clone());
+    let (exports, apiusers) = (db.exportmanager.clone(), db.apiusermanager.clone());
+    let settings = settings.inner().clone();
+    tokio::spawn(
+        async move {
+            let prepared = prepare_export(&*exports, &*apiusers, &settings, &user, &export).await;
+            if let Err(error) = prepared {
+                tracing::error!(export = %export.id, %error, "Error whilst preparing export");
+                let failed = exports
+                    .set_status(&export.id, &export_model::ExportStatus::Failed, None)
+                    .await;
+                if let Err(error) = failed {
+                    tracing::error!(export = %export.id, %error, "Cannot update the export status");
+                }
+            }
+        }
+        .in_current_span(),
+    );
+    Ok(Json(infos))
+}
+
+#[get("/user/export")]
+pub async fn get_export(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+) -> Result<Json<ExportInfos>, api_errors::Error> {
+    match db.exportmanager.get_latest_export(&user.user.uuid).await {
+        Ok(Some(export)) => Ok(Json(export_infos(settings, export))),
+        Ok(None) => Err(api_errors::Error::not_found(format!(
+            "[{}]: No export requested.",
+            user.user.uuid
+        ))),
+        Err(error) => Err(error.into()),
+    }
+}
@@ -0,0 +1,78 @@
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{database::*, models::user_model};
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::models::oidc_model;
+use crate::oidc::{OidcIntent, OidcService};
+
+pub fn identities(user: &user_model::User) -> Vec<oidc_model::Identity> {
+    user.identities
+        .iter()
+        .map(|identity| oidc_model::Identity {
+            provider: identity.provider.clone(),
+            subject: identity.subject.clone(),
+            email: identity.email.clone(),
+            timestamp: identity.timestamp,
+        })
+        .collect()
+}
+
+#[get("/user/identities")]
+pub async fn list(user: UserToken) -> Json<Vec<oidc_model::Identity>> {
+    Json(identities(&user.user))
+}
+
+/// Starts an authorization whose callback links the identity to this account.
+#[post("/user/identities/<provider>/link")]
+pub async fn link(
+    user: UserToken,
+    oidc: &State<OidcService>,
+    cookies: &CookieJar<'_>,
+    provider: &str,
+) -> Result<Json<oidc_model::OidcAuthorization>, api_errors::Error> {
+    let binding = oidc.client_binding(cookies);
+    match oidc
+        .authorization_url(provider, OidcIntent::Link(user.user.uuid), &binding)
+        .await
+    {
+        Ok(url) => Ok(Json(oidc_model::OidcAuthorization { url })),
+        Err(error) => Err(api_errors::Error::bad_request(error.message)),
+    }
+}
+
+#[post("/user/identities/unlink", data = "<input>")]
+pub async fn unlink(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<oidc_model::UnlinkIdentity>,
+) -> Result<Json<Vec<oidc_model::Identity>>, api_errors::Error> {
+    let mut user = user.user;
+    let linked = |identity: &user_model::UserIdentity| {
+        identity.provider == input.provider && identity.subject == input.subject
+    };
+    if !user.identities.iter().any(linked) {
+        return Err(api_errors::Error::not_found(format!(
+            "[{}]: Identity not linked.",
+            input.subject
+        )));
+    }
+    // Keep a way to log in.
+    if user.password.is_none() && user.identities.len() == 1 {
+        return Err(api_errors::Error::bad_request(
+            "Set a password with a reset before unlinking the last identity.",
+        ));
+    }
+    if let Err(error) = db
+        .usermanager
+        .remove_identity(&user.uuid, &input.provider, &input.subject)
+        .await
+    {
+        return Err(error.into());
+    }
+    user.identities.retain(|identity| !linked(identity));
+    Ok(Json(identities(&user)))
+}
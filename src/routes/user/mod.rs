@@ -1 +1,8 @@
 pub mod account;
+pub mod credentials;
+pub mod email;
+pub mod export;
+pub mod identities;
+pub mod oauth;
+pub mod profile;
+pub mod totp;
@@ -0,0 +1,309 @@
+use rocket::serde::json::Json;
+use rocket::*;
+use url::Url;
+
+use misato_database::{
+    database::*,
+    models::oauth_model::{OAuthClient, OAuthCode, OAUTH_SCOPES},
+};
+
+use crate::errors::{api_errors, oauth_errors};
+use crate::fairings::authentication::UserToken;
+use crate::models::oauth_model;
+
+const CODE_DURATION: u64 = 5 * 60;
+const CLIENT_NAME_MAX_LENGTH: usize = 64;
+const MAX_REDIRECT_URIS: usize = 10;
+
+fn invalid_client(client_id: &str) -> api_errors::Error {
+    api_errors::Error::not_found(format!("[{}]: Unknown client.", client_id))
+}
+
+/// HTTPS only, except for the loopback redirects of native apps (RFC 8252).
+fn is_valid_redirect_uri(uri: &str) -> bool {
+    match Url::parse(uri) {
+        Ok(url) => {
+            url.fragment().is_none()
+                && match url.scheme() {
+                    "https" => url.host().is_some(),
+                    "http" => matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+                    _ => false,
+                }
+        }
+        Err(_) => false,
+    }
+}
+
+/// Base64url of a SHA-256 digest.
+fn is_code_challenge(challenge: &str) -> bool {
+    challenge.len() == 43
+        && challenge
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn client_infos(secret: Option<String>, client: OAuthClient) -> oauth_model::ClientInfos {
+    oauth_model::ClientInfos {
+        client_id: client.client_id,
+        client_secret: secret,
+        name: client.name,
+        redirect_uris: client.redirect_uris,
+        scopes: client.scopes,
+        timestamp: client.timestamp,
+    }
+}
+
+/// Errors before the redirect URI is trusted are never sent back to it.
+async fn check_authorization(
+    db: &Database,
+    request: &oauth_model::AuthorizationRequest,
+) -> Result<(OAuthClient, Vec<String>), oauth_errors::Error> {
+    let client = match db.oauthmanager.get_client(&request.client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return Err(oauth_errors::Error::invalid_client()),
+        Err(error) => return Err(error.into()),
+    };
+    if !client.redirect_uris.contains(&request.redirect_uri) {
+        return Err(oauth_errors::Error::invalid_request(
+            "Unregistered redirect_uri.",
+        ));
+    }
+    if request.response_type != "code" {
+        return Err(oauth_errors::Error::unsupported_response_type());
+    }
+    if request.code_challenge_method != "S256" || !is_code_challenge(&request.code_challenge) {
+        return Err(oauth_errors::Error::invalid_request(
+            "PKCE with the S256 method is required.",
+        ));
+    }
+    let mut scopes: Vec<String> = match &request.scope {
+        Some(scope) => scope.split_whitespace().map(str::to_string).collect(),
+        None => client.scopes.clone(),
+    };
+    scopes.sort();
+    scopes.dedup();
+    if scopes.is_empty() || scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Err(oauth_errors::Error::invalid_scope());
+    }
+    Ok((client, scopes))
+}
+
+fn redirect_to(
+    request: &oauth_model::AuthorizationRequest,
+    params: &[(&str, &str)],
+) -> Result<String, oauth_errors::Error> {
+    let mut url = Url::parse(&request.redirect_uri)
+        .map_err(|_| oauth_errors::Error::invalid_request("Invalid redirect_uri."))?;
+    {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in params {
+            query.append_pair(key, value);
+        }
+        if let Some(state) = &request.state {
+            query.append_pair("state", state);
+        }
+    }
+    Ok(url.to_string())
+}
+
+async fn issue_code(
+    db: &Database,
+    uuid: &str,
+    request: &oauth_model::AuthorizationRequest,
+    scopes: Vec<String>,
+) -> Result<String, oauth_errors::Error> {
+    let (code, record) = OAuthCode::create(
+        &request.client_id,
+        uuid,
+        &request.redirect_uri,
+        scopes,
+        &request.code_challenge,
+        CODE_DURATION,
+    );
+    db.oauthmanager.save_code(&record).await?;
+    redirect_to(request, &[("code", &code)])
+}
+
+#[post("/user/oauth/clients", data = "<input>")]
+pub async fn register_client(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<oauth_model::RegisterClient>,
+) -> Result<Json<oauth_model::ClientInfos>, api_errors::Error> {
+    let input = input.into_inner();
+    let name = input.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > CLIENT_NAME_MAX_LENGTH {
+        return Err(api_errors::Error::invalid_field(
+            "name",
+            "Invalid client name.",
+        ));
+    }
+    if input.redirect_uris.is_empty() || input.redirect_uris.len() > MAX_REDIRECT_URIS {
+        return Err(api_errors::Error::invalid_field(
+            "redirect_uris",
+            format!(
+                "Between 1 and {} redirect URIs are required.",
+                MAX_REDIRECT_URIS
+            ),
+        ));
+    }
+    if let Some(uri) = input
+        .redirect_uris
+        .iter()
+        .find(|uri| !is_valid_redirect_uri(uri))
+    {
+        return Err(api_errors::Error::invalid_field(
+            "redirect_uris",
+            format!("[{}]: Invalid redirect URI.", uri),
+        ));
+    }
+    if let Some(scope) = input
+        .scopes
+        .iter()
+        .find(|scope| !OAUTH_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(api_errors::Error::invalid_field(
+            "scopes",
+            format!("[{}]: Unknown scope.", scope),
+        ));
+    }
+    let mut scopes = input.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    let (secret, client) = OAuthClient::create(
+        &user.user.uuid,
+        name,
+        input.redirect_uris,
+        scopes,
+        input.confidential,
+    );
+    db.oauthmanager.create_client(&client).await?;
+    Ok(Json(client_infos(secret, client)))
+}
+
+#[get("/user/oauth/clients")]
+pub async fn list_clients(
+    user: UserToken,
+    db: &State<Database>,
+) -> Result<Json<Vec<oauth_model::ClientInfos>>, api_errors::Error> {
+    match db.oauthmanager.get_clients(&user.user.uuid).await {
+        Ok(clients) => Ok(Json(
+            clients
+                .into_iter()
+                .map(|client| client_infos(None, client))
+                .collect(),
+        )),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[post("/user/oauth/clients/delete", data = "<input>")]
+pub async fn delete_client(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<oauth_model::ClientId>,
+) -> Result<Json<String>, api_errors::Error> {
+    match db
+        .oauthmanager
+        .delete_client(&user.user.uuid, &input.client_id)
+        .await
+    {
+        Ok(result) if result.deleted_count == 1 => Ok(Json(format!(
+            "[{}]: Client deleted, its tokens revoked.",
+            input.client_id
+        ))),
+        Ok(_) => Err(invalid_client(&input.client_id)),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Checks the request for the consent screen, answers right away with the
+/// redirect when the user already granted these scopes.
+#[get("/oauth/authorize?<request..>")]
+pub async fn authorize(
+    user: UserToken,
+    db: &State<Database>,
+    request: oauth_model::AuthorizationRequest,
+) -> Result<Json<oauth_model::AuthorizationPrompt>, oauth_errors::Error> {
+    let (client, scopes) = check_authorization(db, &request).await?;
+    let consent = db
+        .oauthmanager
+        .get_consent(&user.user.uuid, &client.client_id)
+        .await?;
+    let redirect = match consent {
+        Some(consent) if consent.covers(&scopes) => {
+            Some(issue_code(db, &user.user.uuid, &request, scopes.clone()).await?)
+        }
+        _ => None,
+    };
+    Ok(Json(oauth_model::AuthorizationPrompt {
+        client_id: client.client_id,
+        client_name: client.name,
+        scopes,
+        redirect,
+    }))
+}
+
+#[post("/oauth/authorize", data = "<input>")]
+pub async fn decide(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<oauth_model::AuthorizationDecision>,
+) -> Result<Json<oauth_model::AuthorizationRedirect>, oauth_errors::Error> {
+    let input = input.into_inner();
+    let request = input.request;
+    let (client, scopes) = check_authorization(db, &request).await?;
+    if !input.approve {
+        return Ok(Json(oauth_model::AuthorizationRedirect {
+            redirect: redirect_to(&request, &[("error", "access_denied")])?,
+        }));
+    }
+    db.oauthmanager
+        .add_consent(&user.user.uuid, &client.client_id, &scopes)
+        .await?;
+    Ok(Json(oauth_model::AuthorizationRedirect {
+        redirect: issue_code(db, &user.user.uuid, &request, scopes).await?,
+    }))
+}
+
+#[get("/user/oauth/consents")]
+pub async fn list_consents(
+    user: UserToken,
+    db: &State<Database>,
+) -> Result<Json<Vec<oauth_model::Consent>>, api_errors::Error> {
+    let consents = db.oauthmanager.get_consents(&user.user.uuid).await?;
+    let mut result = Vec::new();
+    for consent in consents {
+        let client = db.oauthmanager.get_client(&consent.client_id).await?;
+        if let Some(client) = client {
+            result.push(oauth_model::Consent {
+                client_id: consent.client_id,
+                client_name: client.name,
+                scopes: consent.scopes,
+                timestamp: consent.timestamp,
+            });
+        }
+    }
+    Ok(Json(result))
+}
+
+#[post("/user/oauth/consents/revoke", data = "<input>")]
+pub async fn revoke_consent(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<oauth_model::ClientId>,
+) -> Result<Json<String>, api_errors::Error> {
+    match db
+        .oauthmanager
+        .revoke_consent(&user.user.uuid, &input.client_id)
+        .await
+    {
+        Ok(result) if result.deleted_count == 1 => Ok(Json(format!(
+            "[{}]: Consent withdrawn, tokens revoked.",
+            input.client_id
+        ))),
+        Ok(_) => Err(invalid_client(&input.client_id)),
+        Err(error) => Err(error.into()),
+    }
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket::tokio::fs;
+use rocket::*;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::generate_token;
+use misato_utils::settings::Settings;
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::models::profile_model;
+use crate::routes::root::profile::{avatar_url, is_avatar_name};
+
+const DISPLAY_NAME_MAX_LENGTH: usize = 64;
+const BIO_MAX_LENGTH: usize = 500;
+
+fn invalid_field(field: &str) -> api_errors::Error {
+    api_errors::Error::invalid_field(field, format!("Invalid {}.", field))
+}
+
+/// Trims the field, an empty one is cleared.
+fn clean_field(
+    value: &Option<String>,
+    field: &str,
+    valid: impl Fn(&str) -> bool,
+) -> Result<Option<String>, api_errors::Error> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) if valid(value) => Ok(Some(value.to_string())),
+        Some(_) => Err(invalid_field(field)),
+    }
+}
+
+pub fn check_profile(
+    input: &profile_model::UpdateProfile,
+) -> Result<user_model::UserProfile, api_errors::Error> {
+    Ok(user_model::UserProfile {
+        display_name: clean_field(&input.display_name, "display name", |value| {
+            value.chars().count() <= DISPLAY_NAME_MAX_LENGTH && !value.chars().any(char::is_control)
+        })?,
+        bio: clean_field(&input.bio, "bio", |value| {
+            value.chars().count() <= BIO_MAX_LENGTH
+        })?,
+        // BCP 47 tag such as "fr" or "pt-BR".
+        locale: clean_field(&input.locale, "locale", |value| {
+            value.len() <= 35
+                && value
+                    .split('-')
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+        })?,
+        // IANA name such as "Europe/Paris" or "Etc/GMT+2".
+        timezone: clean_field(&input.timezone, "timezone", |value| {
+            value.len() <= 64
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c))
+        })?,
+        avatar: None,
+    })
+}
+
+/// Extension of the image, only when its content matches the announced type.
+fn image_extension(content_type: &ContentType, bytes: &[u8]) -> Option<&'static str> {
+    if *content_type == ContentType::PNG && bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if *content_type == ContentType::JPEG && bytes.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if *content_type == ContentType::GIF && bytes.starts_with(b"GIF8") {
+        Some("gif")
+    } else if *content_type == ContentType::WEBP
+        && bytes.starts_with(b"RIFF")
+        && bytes.get(8..12) == Some(b"WEBP")
+    {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+async fn remove_avatar_file(settings: &Settings, avatar: &Option<String>) {
+    if let Some(avatar) = avatar {
+        if is_avatar_name(avatar) {
+            let _ = fs::remove_file(Path::new(&settings.avatar_directory).join(avatar)).await;
+        }
+    }
+}
+
+fn profile(settings: &Settings, user: user_model::User) -> profile_model::Profile {
+    profile_model::Profile {
+        avatar: avatar_url(settings, &user.profile.avatar),
+        uuid: user.uuid,
+        username: user.username,
+        display_name: user.profile.display_name,
+        bio: user.profile.bio,
+        locale: user.profile.locale,
+        timezone: user.profile.timezone,
+        email: user.email,
+        email_verified: user.email_verified,
+        timestamp: user.timestamp,
+    }
+}
+
+#[get("/user/profile")]
+pub async fn get_profile(
+    user: UserToken,
+    settings: &State<Settings>,
+) -> Json<profile_model::Profile> {
+    Json(profile(settings, user.user))
+}
+
+#[post("/user/profile", data = "<input>")]
+pub async fn update_profile(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+    input: Json<profile_model::UpdateProfile>,
+) -> Result<Json<profile_model::Profile>, api_errors::Error> {
+    let mut user = user.user;
+    let updated = user_model::UserProfile {
+        avatar: user.profile.avatar.clone(),
+        ..check_profile(&input)?
+    };
+    db.usermanager.set_profile(&user.uuid, &updated).await?;
+    user.profile = updated;
+    Ok(Json(profile(settings, user)))
+}
+
+#[post("/user/avatar", data = "<data>")]
+pub async fn upload_avatar(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+    content_type: &ContentType,
+    data: Data<'_>,
+) -> Result<Json<profile_model::Profile>, api_errors::Error> {
+    let mut user = user.user;
+    let bytes = match data
+        .open(settings.avatar_max_size.bytes())
+        .into_bytes()
+        .await
+    {
+        Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
+        Ok(_) => {
+            return Err(api_errors::Error::new(
+                api_errors::ErrorCode::PayloadTooLarge,
+                format!("Avatar larger than {} bytes.", settings.avatar_max_size),
+            ))
+        }
+        Err(_) => return Err(invalid_field("avatar")),
+    };
+    let extension = match image_extension(content_type, &bytes) {
+        Some(extension) => extension,
+        None => {
+            return Err(api_errors::Error::new(
+                api_errors::ErrorCode::UnsupportedMediaType,
+                "Avatar must be a PNG, JPEG, GIF or WebP image.",
+            ))
+        }
+    };
+
+    let avatar = format!("{}.{}", generate_token(32), extension);
+    let directory = Path::new(&settings.avatar_directory);
+    if let Err(error) = fs::create_dir_all(directory).await {
+        tracing::error!(%error, "Cannot store the avatar");
+        return Err(api_errors::Error::internal("Could not store the avatar."));
+    }
+    if let Err(error) = fs::write(directory.join(&avatar), &bytes).await {
+        tracing::error!(%error, "Cannot store the avatar");
+        return Err(api_errors::Error::internal("Could not store the avatar."));
+    }
+    db.usermanager.set_avatar(&user.uuid, Some(&avatar)).await?;
+    remove_avatar_file(settings, &user.profile.avatar).await;
+    user.profile.avatar = Some(avatar);
+    Ok(Json(profile(settings, user)))
+}
+
+#[post("/user/avatar/delete")]
+pub async fn delete_avatar(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+) -> Result<Json<profile_model::Profile>, api_errors::Error> {
+    let mut user = user.user;
+    db.usermanager.set_avatar(&user.uuid, None).await?;
+    remove_avatar_file(settings, &user.profile.avatar).await;
+    user.profile.avatar = None;
+    Ok(Json(profile(settings, user)))
+}
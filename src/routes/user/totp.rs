@@ -0,0 +1,146 @@
+use rocket::serde::json::Json;
+use rocket::*;
+
+use misato_database::{database::*, models::user_model};
+use misato_security::{hash_token, password::*, totp::*};
+use misato_utils::{get_current_timestamp, settings::Settings};
+
+use crate::errors::api_errors;
+use crate::fairings::authentication::UserToken;
+use crate::fairings::client_addr::ClientAddr;
+use crate::models::totp_model;
+use crate::routes::root::account::{check_lock, login_keys, register_failure};
+use crate::routes::user::credentials::check_password;
+
+const RECOVERY_CODES: usize = 10;
+/// Steps accepted before and after the current one.
+const TOTP_WINDOW: u64 = 1;
+
+/// Accepts a code from the authenticator or a recovery code, both only once.
+pub async fn check_second_factor(
+    db: &Database,
+    user: &user_model::User,
+    code: &str,
+) -> Result<bool, api_errors::Error> {
+    let totp = match &user.totp {
+        Some(totp) if totp.enabled => totp,
+        _ => return Ok(false),
+    };
+    let timestamp = get_current_timestamp() / 1000;
+    if let Some(step) = verify_code(&totp.secret, code, timestamp, TOTP_WINDOW) {
+        return match db.usermanager.use_totp_step(&user.uuid, step).await {
+            Ok(result) => Ok(result.modified_count == 1),
+            Err(error) => Err(error.into()),
+        };
+    }
+    // Recovery codes are random, a single hash lookup finds them.
+    let hash = hash_token(&code.trim().to_ascii_lowercase());
+    match db.usermanager.remove_recovery_code(&user.uuid, &hash).await {
+        Ok(result) => Ok(result.modified_count == 1),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[post("/user/totp/enroll")]
+pub async fn enroll(
+    user: UserToken,
+    db: &State<Database>,
+    settings: &State<Settings>,
+) -> Result<Json<totp_model::TotpEnrollment>, api_errors::Error> {
+    let user = user.user;
+    if user.has_totp() {
+        return Err(api_errors::Error::conflict(format!(
+            "[{}]: Two-factor authentication already enabled.",
+            user.uuid
+        )));
+    }
+    let totp = user_model::UserTotp {
+        secret: generate_secret(),
+        ..Default::default()
+    };
+    match db.usermanager.set_totp(&user.uuid, &totp).await {
+        Ok(_) => Ok(Json(totp_model::TotpEnrollment {
+            uri: otpauth_uri(&settings.totp_issuer, &user.username, &totp.secret),
+            secret: totp.secret,
+        })),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[post("/user/totp/activate", data = "<input>")]
+pub async fn activate(
+    user: UserToken,
+    db: &State<Database>,
+    input: Json<totp_model::TotpCode>,
+) -> Result<Json<totp_model::TotpRecoveryCodes>, api_errors::Error> {
+    let user = user.user;
+    let totp = match user.totp {
+        Some(totp) if !totp.enabled => totp,
+        _ => {
+            return Err(api_errors::Error::bad_request(format!(
+                "[{}]: No pending two-factor enrollment.",
+                user.uuid
+            )))
+        }
+    };
+    let step = match verify_code(
+        &totp.secret,
+        &input.code,
+        get_current_timestamp() / 1000,
+        TOTP_WINDOW,
+    ) {
+        Some(step) => step,
+        None => return Err(api_errors::Error::unauthorized("Invalid two-factor code.")),
+    };
+
+    let recovery_codes = generate_recovery_codes(RECOVERY_CODES);
+    let totp = user_model::UserTotp {
+        enabled: true,
+        last_step: step,
+        recovery_codes: recovery_codes.iter().map(|code| hash_token(code)).collect(),
+        ..totp
+    };
+    match db.usermanager.set_totp(&user.uuid, &totp).await {
+        Ok(_) => Ok(Json(totp_model::TotpRecoveryCodes { recovery_codes })),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Asks for the password or a second factor, with the lockout of the login.
+#[post("/user/totp/disable", data = "<input>")]
+pub async fn disable(
+    user: UserToken,
+    db: &State<Database>,
+    policy: &State<PasswordPolicy>,
+    client: ClientAddr,
+    input: Json<totp_model::TotpDisable>,
+) -> Result<Json<String>, api_errors::Error> {
+    let user = user.user;
+    if !user.has_totp() {
+        return Err(api_errors::Error::bad_request(format!(
+            "[{}]: Two-factor authentication is not enabled.",
+            user.uuid
+        )));
+    }
+    match (&input.password, &input.code) {
+        (Some(password), _) => check_password(db, policy, &user, client.0, password).await?,
+        (None, Some(code)) => {
+            let keys = login_keys(&user.username, client.0);
+            check_lock(db, &keys).await?;
+            if !check_second_factor(db, &user, code).await? {
+                register_failure(db, &keys).await?;
+                return Err(api_errors::Error::unauthorized("Invalid two-factor code."));
+            }
+        }
+        (None, None) => {
+            return Err(api_errors::Error::bad_request(format!(
+                "[{}]: The password or a two-factor code is required.",
+                user.uuid
+            )))
+        }
+    }
+    match db.usermanager.clear_totp(&user.uuid).await {
+        Ok(_) => Ok(Json("Two-factor authentication disabled.".to_string())),
+        Err(error) => Err(error.into()),
+    }
+}
@@ -0,0 +1,47 @@
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+
+use misato_utils::settings::Settings;
+
+/// Nothing listens on port 1, every connection attempt fails right away.
+async fn client() -> Client {
+    let settings = Settings {
+        database_backend: "mongodb".to_string(),
+        mongodb_uri: "mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100".to_string(),
+        mongodb_name: "misato".to_string(),
+        admin_token: "admin-token".to_string(),
+        database_retry_delay: 60 * 1000,
+        mailer: "stdout".to_string(),
+        ..Settings::init()
+    };
+    Client::tracked(misato_api::rocket(settings))
+        .await
+        .expect("valid rocket instance")
+}
+
+#[rocket::async_test]
+async fn starts_degraded_without_database() {
+    let client = client().await;
+    let response = client.get("/health/live").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/health/ready").dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let ready: Value = response.into_json().await.unwrap();
+    assert_eq!(ready["status"], "unavailable");
+    assert_eq!(ready["database"]["connected"], false);
+    assert_eq!(ready["database"]["attempts"], 1);
+    assert!(ready["database"]["error"].is_string());
+    assert_eq!(ready["pending_migrations"], Value::Null);
+
+    let response = client
+        .post("/login")
+        .header(ContentType::JSON)
+        .body(json!({"username": "asuka", "password": "correct horse battery"}).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let error: Value = response.into_json().await.unwrap();
+    assert_eq!(error["error"], "unavailable");
+}
@@ -0,0 +1,484 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+
+use misato_utils::settings::Settings;
+
+fn settings() -> Settings {
+    Settings {
+        database_backend: "memory".to_string(),
+        admin_token: "admin-token".to_string(),
+        registration: "open".to_string(),
+        signup_pow_difficulty: 0,
+        mailer: "stdout".to_string(),
+        metrics_token: "metrics-token".to_string(),
+        ..Settings::init()
+    }
+}
+
+async fn client_with(settings: Settings) -> Client {
+    Client::tracked(misato_api::rocket(settings))
+        .await
+        .expect("valid rocket instance")
+}
+
+async fn client() -> Client {
+    client_with(settings()).await
+}
+
+async fn post(client: &Client, uri: &str, body: Value) -> (Status, Value) {
+    let response = client
+        .post(uri.to_string())
+        .header(ContentType::JSON)
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap_or(Value::Null))
+}
+
+async fn signup(client: &Client, username: &str, password: &str) -> (Status, Value) {
+    post(
+        client,
+        "/signup",
+        json!({"username": username, "password": password}),
+    )
+    .await
+}
+
+#[rocket::async_test]
+async fn signup_opens_a_session() {
+    let client = client().await;
+    let (status, infos) = signup(&client, "asuka", "correct horse battery").await;
+    assert_eq!(status, Status::Ok);
+    let token = infos["token"].as_str().unwrap().to_string();
+
+    let response = client
+        .get("/user/profile")
+        .header(Header::new("X-Misato-User-Token", token))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.headers().get_one("X-Request-Id").is_some());
+    let profile: Value = response.into_json().await.unwrap();
+    assert_eq!(profile["username"], "asuka");
+    assert_eq!(profile["uuid"], infos["uuid"]);
+}
+
+#[rocket::async_test]
+async fn usernames_are_unique_ignoring_case() {
+    let client = client().await;
+    let (status, _) = signup(&client, "shinji", "correct horse battery").await;
+    assert_eq!(status, Status::Ok);
+    let (status, error) = signup(&client, "Shinji", "correct horse battery").await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["error"], "conflict");
+
+    let (status, result) = post(
+        &client,
+        "/login",
+        json!({"username": "SHINJI", "password": "correct horse battery"}),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert!(result["token"].is_string());
+    let response = client.get("/profile/Shinji").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn login_checks_the_password() {
+    let client = client().await;
+    signup(&client, "rei", "correct horse battery").await;
+
+    let (status, error) = post(
+        &client,
+        "/login",
+        json!({"username": "rei", "password": "wrong password"}),
+    )
+    .await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(error["error"], "unauthorized");
+
+    let (status, result) = post(
+        &client,
+        "/login",
+        json!({"username": "rei", "password": "correct horse battery"}),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert!(result["token"].is_string());
+}
+
+#[rocket::async_test]
+async fn overlong_passwords_are_refused_before_hashing() {
+    let client = client().await;
+    signup(&client, "rei", "correct horse battery").await;
+
+    let password = format!("correct horse battery{}", "a".repeat(100_000));
+    let (status, error) = post(
+        &client,
+        "/login",
+        json!({"username": "rei", "password": password}),
+    )
+    .await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(error["error"], "unauthorized");
+}
+
+#[rocket::async_test]
+async fn a_login_keeps_the_failures_of_its_address() {
+    let client = client_with(Settings {
+        login_max_attempts: 3,
+        ..settings()
+    })
+    .await;
+    signup(&client, "rei", "correct horse battery").await;
+    let login = |username: &'static str, password: &'static str| {
+        client
+            .post("/login")
+            .remote("198.51.100.4:4000".parse().unwrap())
+            .header(ContentType::JSON)
+            .body(json!({"username": username, "password": password}).to_string())
+            .dispatch()
+    };
+
+    for _ in 0..2 {
+        let response = login("nobody", "wrong password").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+    let response = login("rei", "correct horse battery").await;
+    assert_eq!(response.status(), Status::Ok);
+    // Still counted, the third failure of the address locks it.
+    let response = login("somebody", "wrong password").await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = login("rei", "correct horse battery").await;
+    assert_eq!(response.status(), Status::TooManyRequests);
+}
+
+#[rocket::async_test]
+async fn profile_changes_are_public() {
+    let client = client().await;
+    let (_, infos) = signup(&client, "kaji", "correct horse battery").await;
+    let token = infos["token"].as_str().unwrap().to_string();
+
+    let response = client
+        .post("/user/profile")
+        .header(Header::new("X-Misato-User-Token", token))
+        .header(ContentType::JSON)
+        .body(json!({"display_name": "Ryoji Kaji", "bio": "Watermelons."}).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.get("/profile/kaji").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let profile: Value = response.into_json().await.unwrap();
+    assert_eq!(profile["display_name"], "Ryoji Kaji");
+    assert_eq!(profile["bio"], "Watermelons.");
+
+    let response = client.get("/profile/nobody").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Hidden as soon as the account is deleted, before the end of its grace period.
+    let response = client
+        .post("/admin/delete")
+        .header(Header::new("X-Misato-API-Token", "admin-token"))
+        .header(ContentType::JSON)
+        .body(json!({"uuid": infos["uuid"]}).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/profile/kaji").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn api_tokens_stop_with_their_user() {
+    let client = client().await;
+    let (_, infos) = signup(&client, "penpen", "correct horse battery").await;
+    let response = client
+        .post("/api/signup")
+        .header(Header::new(
+            "X-Misato-User-Token",
+            infos["token"].as_str().unwrap().to_string(),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let api: Value = response.into_json().await.unwrap();
+    let check_token = || {
+        client
+            .post("/api/check-token")
+            .header(Header::new(
+                "X-Misato-API-Token",
+                api["token"].as_str().unwrap().to_string(),
+            ))
+            .dispatch()
+    };
+    assert_eq!(check_token().await.status(), Status::Ok);
+
+    let response = client
+        .post("/admin/delete")
+        .header(Header::new("X-Misato-API-Token", "admin-token"))
+        .header(ContentType::JSON)
+        .body(json!({"uuid": infos["uuid"]}).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(check_token().await.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn exports_are_downloaded_once() {
+    let directory = std::env::temp_dir().join(format!("misato-exports-{}", std::process::id()));
+    let client = client_with(Settings {
+        export_directory: directory.to_string_lossy().to_string(),
+        ..settings()
+    })
+    .await;
+    let (_, infos) = signup(&client, "mari", "correct horse battery").await;
+    let token = infos["token"].as_str().unwrap().to_string();
+    let response = client
+        .post("/user/export")
+        .header(Header::new("X-Misato-User-Token", token.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Prepared in the background.
+    let mut url = None;
+    for _ in 0..100 {
+        let response = client
+            .get("/user/export")
+            .header(Header::new("X-Misato-User-Token", token.clone()))
+            .dispatch()
+            .await;
+        let export: Value = response.into_json().await.unwrap();
+        if let Some(found) = export["url"].as_str() {
+            url = Some(found[found.find("/export/").unwrap()..].to_string());
+            break;
+        }
+        rocket::tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let url = url.unwrap();
+
+    let response = client.get(url.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.into_bytes().await.unwrap().starts_with(b"PK"));
+    let response = client.get(url).dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+#[rocket::async_test]
+async fn weak_passwords_are_detailed() {
+    let client = client().await;
+    let (status, error) = signup(&client, "toji", "short").await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["error"], "validation_failed");
+    assert_eq!(error["details"][0]["field"], "password");
+}
+
+#[rocket::async_test]
+async fn guard_failures_are_json() {
+    let client = client().await;
+    let response = client
+        .get("/user/profile")
+        .header(Header::new("X-Request-Id", "trace-42"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(response.headers().get_one("X-Request-Id"), Some("trace-42"));
+    let error: Value = response.into_json().await.unwrap();
+    assert_eq!(error["code"], 401);
+    assert_eq!(error["error"], "unauthorized");
+    assert_eq!(error["request_id"], "trace-42");
+
+    let response = client
+        .get("/user/profile")
+        .header(Header::new("X-Misato-User-Token", "nope"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(response.headers().get_one("X-Request-Id").is_some());
+
+    let response = client.get("/nowhere").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    let error: Value = response.into_json().await.unwrap();
+    assert_eq!(error["error"], "not_found");
+}
+
+#[rocket::async_test]
+async fn bearer_failures_carry_a_challenge() {
+    let client = client().await;
+    let response = client.get("/oauth/userinfo").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(
+        response.headers().get_one("WWW-Authenticate"),
+        Some("Bearer")
+    );
+    let error: Value = response.into_json().await.unwrap();
+    assert_eq!(error["error"], "unauthorized");
+
+    let response = client
+        .get("/oauth/userinfo")
+        .header(Header::new("Authorization", "Bearer forged"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(
+        response.headers().get_one("WWW-Authenticate"),
+        Some("Bearer error=\"invalid_token\"")
+    );
+    let error: Value = response.into_json().await.unwrap();
+    assert_eq!(error["message"], "Invalid or expired token.");
+}
+
+#[rocket::async_test]
+async fn metrics_are_restricted() {
+    let client = client().await;
+    signup(&client, "maya", "correct horse battery").await;
+    client.get("/user/profile").dispatch().await;
+
+    let response = client.get("/metrics").dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    // Only the proxies of MISATO_TRUSTED_PROXIES are believed.
+    let response = client
+        .get("/metrics")
+        .remote("203.0.113.7:4000".parse().unwrap())
+        .header(Header::new("X-Real-IP", "127.0.0.1"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = client
+        .get("/metrics")
+        .header(Header::new("Authorization", "Bearer wrong"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client
+        .get("/metrics")
+        .remote("127.0.0.1:9090".parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = client
+        .get("/metrics")
+        .header(Header::new("Authorization", "Bearer metrics-token"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let metrics = response.into_string().await.unwrap();
+    assert!(metrics.contains(
+        "misato_http_requests_total{method=\"POST\",route=\"/signup\",status=\"200\"} 1"
+    ));
+    assert!(metrics.contains("misato_auth_failures_total{guard=\"user\",reason=\"Missing\"} 1"));
+    assert!(metrics.contains(
+        "misato_database_operation_duration_seconds_count{manager=\"users\",method=\"create_user\"} 1"
+    ));
+    assert!(metrics.contains("misato_active_sessions 1"));
+
+    let behind_proxy = client_with(Settings {
+        trusted_proxies: "203.0.113.0/24".to_string(),
+        ..settings()
+    })
+    .await;
+    let response = behind_proxy
+        .get("/metrics")
+        .remote("203.0.113.7:4000".parse().unwrap())
+        .header(Header::new("X-Real-IP", "127.0.0.1"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn health_probes_report_the_database() {
+    let client = client().await;
+    let response = client.get("/health/live").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let live: Value = response.into_json().await.unwrap();
+    assert_eq!(live["version"], env!("CARGO_PKG_VERSION"));
+
+    let response = client.get("/health/ready").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let ready: Value = response.into_json().await.unwrap();
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["database"]["connected"], true);
+    assert_eq!(ready["database"]["error"], Value::Null);
+    assert_eq!(ready["pending_migrations"], json!([]));
+}
+
+#[rocket::async_test]
+async fn rate_limits_ignore_unverified_tokens_and_addresses() {
+    let client = client_with(Settings {
+        ratelimit_login: "2/60".parse().unwrap(),
+        ..settings()
+    })
+    .await;
+    let mut statuses = Vec::new();
+    for attempt in 0..3 {
+        let response = client
+            .post("/login/totp")
+            .remote("203.0.113.7:4000".parse().unwrap())
+            .header(ContentType::JSON)
+            .header(Header::new(
+                "X-Misato-User-Token",
+                format!("forged-{}", attempt),
+            ))
+            .header(Header::new("X-Real-IP", format!("198.51.100.{}", attempt)))
+            .body(json!({"challenge": "unknown", "code": "000000"}).to_string())
+            .dispatch()
+            .await;
+        statuses.push(response.status());
+        if response.status() == Status::TooManyRequests {
+            assert!(response.headers().get_one("Retry-After").is_some());
+            let error: Value = response.into_json().await.unwrap();
+            assert_eq!(error["error"], "too_many_requests");
+        }
+    }
+    assert_eq!(
+        statuses,
+        [
+            Status::Unauthorized,
+            Status::Unauthorized,
+            Status::TooManyRequests
+        ]
+    );
+
+    let metrics = client
+        .get("/metrics")
+        .header(Header::new("Authorization", "Bearer metrics-token"))
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(metrics.contains(
+        "misato_http_requests_total{method=\"POST\",route=\"/login/totp\",status=\"429\"} 1"
+    ));
+}
+
+#[rocket::async_test]
+async fn probes_are_not_rate_limited() {
+    let client = client_with(Settings {
+        ratelimit_read: "2/60".parse().unwrap(),
+        ..settings()
+    })
+    .await;
+    for _ in 0..4 {
+        for uri in ["/health/live", "/health/ready"] {
+            let response = client.get(uri).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.headers().get_one("X-RateLimit-Limit").is_none());
+        }
+        let response = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer metrics-token"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
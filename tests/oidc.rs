@@ -0,0 +1,305 @@
+//! Logins through a mock provider served in-process: discovery document, keys and token
+//! endpoint. The ID token the token endpoint answers with is set by each test.
+
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rocket::http::{ContentType, Cookie, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+use serde_json::{json, Value};
+use url::Url;
+
+use misato_api::oidc::BINDING_COOKIE;
+use misato_utils::get_current_timestamp;
+use misato_utils::settings::{OidcProviderSettings, Settings};
+
+/// Key id, private key and modulus of the keys the provider rotates through.
+const KEYS: [(&str, &str, &str); 2] = [
+    (
+        "key-1",
+        include_str!("fixtures/oidc_key_1.pem"),
+        "tdzOFPSocpmJpXI1vWsk2D1DxJhl9FNpZeyQiby8tJo8tzWtqVQlp2CwY-qQue2W4HogcrVHah6Hh3hU6CpYXK1K\
+         i-VsJhHFVZ2DpsIPeYzZsyd2vy5MBY1vYZL56tLPW4Kae0ulTBTgws3tqQYrYx0NT9rVSSdAzNfKnYcymNgwt-9y\
+         yuroPquLisla7cMeuJfHBRCYo6lMid0Ale23ri2pv2wN_WObij-kuGGhnav6fmJEAujtYKSngZbVXSkAqpfD6Pt6\
+         630X1P2t1kJT1r7UXSai3fQAXWm-gzPJ_4bdBGFufikVa0WbqiQIOBW8gjbisD_6iF35r5oKodoPxw",
+    ),
+    (
+        "key-2",
+        include_str!("fixtures/oidc_key_2.pem"),
+        "oHl__i1EMVsUy2KM3BBT-ts2z0io_WV0uwTJsGqUYhs_WAgaceEeCPtFVK8Agb5t7D6O91kIR2UIDfgkfzU2xsHd\
+         3uNDUaRbEeZouyMmKe0_CfDsvRmdjczGMN8pIkTDg8ZuFxF8pN1yN14RN2USuMjGHFGDZQIJdPC9-f53g0Vjt5JW\
+         G2MAjD6sQjBLrbMntzztf0sRAdRRpWiTBgPCcr4LUSvpfbFnXRdggkOSf9qewo5B_TAm0k3idNfavcMpgvjTwFMO\
+         qxagBESLYxe5RGHcFVP5tpQmXRboJuoCrXgF3epJKkoYPdX6qW6xpEL-e2fBfpWupsZ0hhBUANXIYQ",
+    ),
+];
+
+struct Provider {
+    issuer: String,
+    /// Index in `KEYS` of the published key.
+    key: Mutex<usize>,
+    id_token: Mutex<String>,
+}
+
+#[get("/.well-known/openid-configuration")]
+fn discovery(provider: &State<Arc<Provider>>) -> Json<Value> {
+    Json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+    }))
+}
+
+#[get("/jwks")]
+fn jwks(provider: &State<Arc<Provider>>) -> Json<Value> {
+    let (kid, _, n) = KEYS[*provider.key.lock().unwrap()];
+    Json(json!({"keys": [{"kty": "RSA", "kid": kid, "n": n, "e": "AQAB"}]}))
+}
+
+#[post("/token")]
+fn token(provider: &State<Arc<Provider>>) -> Json<Value> {
+    let id_token = provider.id_token.lock().unwrap().clone();
+    Json(json!({"id_token": id_token, "token_type": "Bearer"}))
+}
+
+async fn start_provider() -> Arc<Provider> {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let provider = Arc::new(Provider {
+        issuer: format!("http://127.0.0.1:{}", port),
+        key: Mutex::new(0),
+        id_token: Mutex::new(String::new()),
+    });
+    let figment = rocket::Config::figment()
+        .merge(("address", "127.0.0.1"))
+        .merge(("port", port))
+        .merge(("log_level", "off"));
+    let server = rocket::custom(figment)
+        .manage(provider.clone())
+        .mount("/", routes![discovery, jwks, token]);
+    rocket::tokio::spawn(server.launch());
+    for _ in 0..100 {
+        if rocket::tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_ok()
+        {
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    provider
+}
+
+async fn client(provider: &Provider) -> Client {
+    let settings = Settings {
+        database_backend: "memory".to_string(),
+        admin_token: "admin-token".to_string(),
+        registration: "open".to_string(),
+        mailer: "stdout".to_string(),
+        oidc_providers: vec![OidcProviderSettings {
+            name: "mock".to_string(),
+            issuer: provider.issuer.clone(),
+            client_id: "misato".to_string(),
+            client_secret: String::new(),
+            scopes: "openid email profile".to_string(),
+        }],
+        ..Settings::init()
+    };
+    Client::untracked(misato_api::rocket(settings))
+        .await
+        .expect("valid rocket instance")
+}
+
+/// Starts a login, returns the state and nonce sent to the provider and the binding cookie.
+async fn start(client: &Client) -> (String, String, Cookie<'static>) {
+    let response = client.get("/oidc/mock/login").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let cookie = response.cookies().get(BINDING_COOKIE).unwrap().clone();
+    // Also sent to `/user/identities`, which starts authorizations too.
+    assert_eq!(cookie.path(), Some("/"));
+    let body: Value = response.into_json().await.unwrap();
+    let url = Url::parse(body["url"].as_str().unwrap()).unwrap();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    };
+    (param("state"), param("nonce"), cookie)
+}
+
+fn claims(provider: &Provider, nonce: &str) -> Value {
+    let now = get_current_timestamp() / 1000;
+    json!({
+        "iss": provider.issuer,
+        "aud": "misato",
+        "sub": "subject-1",
+        "nonce": nonce,
+        "iat": now,
+        "exp": now + 300,
+        "email": "rei@example.com",
+        "email_verified": true,
+        "preferred_username": "rei",
+    })
+}
+
+/// Makes the token endpoint answer with these claims signed by the key at `key`.
+fn issue(provider: &Provider, key: usize, claims: &Value) {
+    let (kid, pem, _) = KEYS[key];
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(kid.to_string());
+    let key = EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap();
+    *provider.id_token.lock().unwrap() = encode(&header, claims, &key).unwrap();
+}
+
+/// Code the callback sends the browser to the frontend with.
+async fn callback_code(client: &Client, state: &str, cookie: Option<Cookie<'static>>) -> String {
+    let mut request = client.get(format!("/oidc/mock/callback?code=code&state={}", state));
+    if let Some(cookie) = cookie {
+        request = request.cookie(cookie);
+    }
+    let response = request.dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+    assert_eq!(location.path(), "/oidc/callback");
+    location
+        .query_pairs()
+        .find(|(key, _)| key == "code")
+        .map(|(_, value)| value.to_string())
+        .unwrap()
+}
+
+async fn exchange(client: &Client, code: &str) -> (Status, Value) {
+    let response = client
+        .post("/oidc/exchange")
+        .header(ContentType::JSON)
+        .body(json!({ "code": code }).to_string())
+        .dispatch()
+        .await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap_or(Value::Null))
+}
+
+/// Goes through the callback and the exchange, as the frontend does.
+async fn callback(
+    client: &Client,
+    state: &str,
+    cookie: Option<Cookie<'static>>,
+) -> (Status, Value) {
+    let code = callback_code(client, state, cookie).await;
+    exchange(client, &code).await
+}
+
+#[rocket::async_test]
+async fn logs_in_once_per_state() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, cookie) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, &nonce));
+
+    let (status, session) = callback(&client, &state, Some(cookie.clone())).await;
+    assert_eq!(status, Status::Ok);
+    assert!(session["token"].is_string());
+
+    let (status, _) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn refuses_a_callback_from_another_client() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, _) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, &nonce));
+
+    let (status, _) = callback(&client, &state, None).await;
+    assert_eq!(status, Status::BadRequest);
+    let (state, _, _) = start(&client).await;
+    let forged = Cookie::new(BINDING_COOKIE, "forged");
+    let (status, _) = callback(&client, &state, Some(forged)).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn refuses_another_nonce() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, _, cookie) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, "replayed-nonce"));
+
+    let (status, error) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(error["message"].as_str().unwrap().contains("nonce"));
+}
+
+#[rocket::async_test]
+async fn refuses_another_audience() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, cookie) = start(&client).await;
+    let mut claims = claims(&provider, &nonce);
+    claims["aud"] = json!("another-client");
+    issue(&provider, 0, &claims);
+
+    let (status, _) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn fetches_the_keys_again_after_a_rotation() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, cookie) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, &nonce));
+    let (status, _) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::Ok);
+
+    // The cached keys only hold the first one.
+    *provider.key.lock().unwrap() = 1;
+    let (state, nonce, cookie) = start(&client).await;
+    issue(&provider, 1, &claims(&provider, &nonce));
+    let (status, session) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::Ok);
+    assert!(session["token"].is_string());
+}
+
+#[rocket::async_test]
+async fn exchanges_the_code_once() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, cookie) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, &nonce));
+
+    let code = callback_code(&client, &state, Some(cookie)).await;
+    let (status, session) = exchange(&client, &code).await;
+    assert_eq!(status, Status::Ok);
+    assert!(session["token"].is_string());
+    let (status, _) = exchange(&client, &code).await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn refuses_an_address_verified_by_another_account() {
+    let provider = start_provider().await;
+    let client = client(&provider).await;
+    let (state, nonce, cookie) = start(&client).await;
+    issue(&provider, 0, &claims(&provider, &nonce));
+    let (status, _) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::Ok);
+
+    // Another identity with the same verified address.
+    let (state, nonce, cookie) = start(&client).await;
+    let mut claims = claims(&provider, &nonce);
+    claims["sub"] = json!("subject-2");
+    issue(&provider, 0, &claims);
+    let (status, error) = callback(&client, &state, Some(cookie)).await;
+    assert_eq!(status, Status::Conflict);
+    assert!(error["message"].as_str().unwrap().contains("Email address"));
+}